@@ -0,0 +1,105 @@
+use actix_web::{
+    http::header,
+    web::{self, Data},
+    HttpRequest, HttpResponse, Responder,
+};
+
+use crate::{
+    structs::{
+        config::Config,
+        requests::{Cache, InFlightRenders},
+    },
+    utils::disk_cache::DiskCache,
+};
+
+///Checks the bearer token, returns the response to send if the request isn't allowed
+fn check_token(req: &HttpRequest, config: &Config) -> Option<HttpResponse> {
+    let Some(token) = &config.admin_token else {
+        return Some(HttpResponse::Forbidden().body("Admin endpoints are disabled"));
+    };
+    let provided = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "));
+    if provided == Some(token.as_str()) {
+        None
+    } else {
+        Some(HttpResponse::Unauthorized().body("Invalid admin token"))
+    }
+}
+
+///Returns the render cache statistics as json
+#[actix_web::get("/admin/cache")]
+async fn cache_stats(
+    req: HttpRequest,
+    config: Data<Config>,
+    cache: web::Data<Cache>,
+) -> impl Responder {
+    if let Some(response) = check_token(&req, &config) {
+        return response;
+    }
+    let stats = cache.lock().unwrap().stats();
+    HttpResponse::Ok().json(stats)
+}
+
+///Removes everything from the render cache
+#[actix_web::delete("/admin/cache")]
+async fn clear_cache(
+    req: HttpRequest,
+    config: Data<Config>,
+    cache: web::Data<Cache>,
+) -> impl Responder {
+    if let Some(response) = check_token(&req, &config) {
+        return response;
+    }
+    cache.lock().unwrap().clear();
+    HttpResponse::NoContent().finish()
+}
+
+///Returns how many renders were saved by waiting for identical requests, as json
+#[actix_web::get("/admin/coalescing")]
+async fn coalescing_stats(
+    req: HttpRequest,
+    config: Data<Config>,
+    in_flight: Data<InFlightRenders>,
+) -> impl Responder {
+    if let Some(response) = check_token(&req, &config) {
+        return response;
+    }
+    HttpResponse::Ok().json(in_flight.stats())
+}
+
+///Returns the disk cache statistics as json
+#[actix_web::get("/admin/disk_cache")]
+async fn disk_cache_stats(
+    req: HttpRequest,
+    config: Data<Config>,
+    disk_cache: Option<Data<DiskCache>>,
+) -> impl Responder {
+    if let Some(response) = check_token(&req, &config) {
+        return response;
+    }
+    let Some(disk_cache) = disk_cache else {
+        return HttpResponse::NotFound().body("Disk cache is disabled");
+    };
+    let stats = disk_cache.stats();
+    HttpResponse::Ok().json(stats)
+}
+
+///Removes everything from the disk cache
+#[actix_web::delete("/admin/disk_cache")]
+async fn clear_disk_cache(
+    req: HttpRequest,
+    config: Data<Config>,
+    disk_cache: Option<Data<DiskCache>>,
+) -> impl Responder {
+    if let Some(response) = check_token(&req, &config) {
+        return response;
+    }
+    let Some(disk_cache) = disk_cache else {
+        return HttpResponse::NotFound().body("Disk cache is disabled");
+    };
+    disk_cache.clear().await;
+    HttpResponse::NoContent().finish()
+}
@@ -0,0 +1,135 @@
+use std::sync::Arc;
+
+use actix_web::{
+    web::{self, Data},
+    HttpResponse, Responder, ResponseError,
+};
+
+use crate::{
+    grimoire,
+    structs::{
+        errors::ApiError,
+        rendering::{Backend, RenderParameters, RenderProgress},
+        requests::{AnimationBody, AnimationMode, RequestBody, SimplifiedFractals},
+    },
+    utils::{
+        animation::FrameEncoder,
+        cpu,
+        export::async_iter,
+        graphics::{render_request, render_with_progress},
+    },
+    PipelineStore,
+};
+
+///Renders a zoom from one position to another or cycles the colors, every frame uses the same
+///parameters as `/fractals/{fractal}` apart from the position, zoom and color offset
+#[actix_web::get("/animations/{fractal}")]
+async fn render_animation(
+    backend: Data<Backend>,
+    pipelines: Data<PipelineStore>,
+    fractal: web::Path<SimplifiedFractals>,
+    query: web::Query<RequestBody>,
+    animation: web::Query<AnimationBody>,
+) -> impl Responder {
+    let frames = match animation.to_frames(&query, fractal.into_inner()) {
+        Ok(frames) => frames,
+        Err(e) => return e.error_response(),
+    };
+
+    let progress = Arc::default();
+    match animation_data(&backend, &pipelines, &frames, &animation, &progress).await {
+        Ok(data) => HttpResponse::Ok()
+            .content_type(animation.output.unwrap_or_default().mime())
+            .streaming(async_iter(data)),
+        Err(e) => e.error_response(),
+    }
+}
+
+///Pixels that [`animation_data`] goes through, for its progress
+pub(super) fn animation_pixels(frames: &[RenderParameters], animation: &AnimationBody) -> u64 {
+    let frame = u64::from(frames[0].width) * u64::from(frames[0].height);
+    let renders = if animation.mode == Some(AnimationMode::Cycle) {
+        //The raw data, then every frame is recolored
+        frames.len() + 1
+    } else {
+        frames.len()
+    };
+    frame * renders as u64
+}
+
+///Renders and encodes every frame, advancing `progress` as the frames are rendered
+pub(super) async fn animation_data(
+    backend: &Backend,
+    pipelines: &PipelineStore,
+    frames: &[RenderParameters],
+    animation: &AnimationBody,
+    progress: &Arc<RenderProgress>,
+) -> Result<Vec<u8>, ApiError> {
+    let output = animation.output.unwrap_or_default();
+    let (width, height) = (frames[0].width, frames[0].height);
+
+    let mut encoder =
+        FrameEncoder::new(output, width, height, frames.len() as u32, animation.fps())
+            .map_err(bad_request)?;
+    //Only the colors change, so the escape time data can be reused for every frame
+    let field = if animation.mode == Some(AnimationMode::Cycle) {
+        Some(Arc::new(
+            render_frame(backend, pipelines, &frames[0].to_raw_field(), progress).await?,
+        ))
+    } else {
+        None
+    };
+    for parameters in frames {
+        let img = match &field {
+            Some(field) => {
+                let request = render_request(parameters).await.map_err(bad_request)?;
+                let field = field.clone();
+                let img = web::block(move || cpu::recolor(&request, &field))
+                    .await
+                    .map_err(|e| export_error(&e.to_string()))?;
+                progress.advance(u64::from(width) * u64::from(height));
+                img
+            }
+            None => render_frame(backend, pipelines, parameters, progress).await?,
+        };
+        //Encoding a frame takes about as long as rendering it, so it shouldn't block the worker
+        encoder = web::block(move || encoder.add_frame(img).map(|()| encoder))
+            .await
+            .map_err(|e| e.to_string())
+            .and_then(|result| result)
+            .map_err(|e| export_error(&e))?;
+    }
+
+    web::block(move || encoder.finish())
+        .await
+        .map_err(|e| e.to_string())
+        .and_then(|result| result)
+        .map_err(|e| export_error(&e))
+}
+
+async fn render_frame(
+    backend: &Backend,
+    pipelines: &PipelineStore,
+    parameters: &RenderParameters,
+    progress: &Arc<RenderProgress>,
+) -> Result<Vec<u8>, ApiError> {
+    let request = render_request(parameters).await.map_err(bad_request)?;
+    render_with_progress(backend, pipelines, &request, progress)
+        .await
+        .map_err(|e| {
+            log::error!(target: grimoire::LOGGING_TARGET, "{e}");
+            ApiError::Internal(String::new())
+        })
+}
+
+const fn bad_request(message: String) -> ApiError {
+    ApiError::BadRequest(message)
+}
+
+fn export_error(e: &str) -> ApiError {
+    log::error!(
+        target: grimoire::LOGGING_TARGET,
+        "Could not export animation {e}"
+    );
+    ApiError::Internal("Unable to export animation".to_string())
+}
@@ -0,0 +1,199 @@
+use std::{
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use actix_web::{
+    http::StatusCode,
+    web::{self, Data},
+    HttpResponse, Responder, ResponseError,
+};
+use serde_json::json;
+
+use super::rendering::cached_data;
+use crate::{
+    grimoire,
+    structs::{
+        errors::ApiError,
+        rendering::{Backend, RenderParameters},
+        requests::{
+            ArchiveFormat, BatchBody, BatchFormat, BatchItem, Cache, InFlightRenders, OutputFormat,
+        },
+    },
+    utils::{archive::Archive, disk_cache::DiskCache, export::async_iter},
+    PipelineStore,
+};
+
+///Renders an array of images with the fields of `POST /fractals/{fractal}` and the fractal, one
+///after the other so they share pipelines. Returns a zip (or `output=tar`) with `manifest.json`
+///and the images, or `output=multipart` with a part per image. Images that fail don't fail the
+///rest, their status and error are in the manifest or in their part
+#[allow(clippy::too_many_arguments)]
+#[actix_web::post("/batch")]
+async fn render_batch(
+    backend: Data<Backend>,
+    pipelines: Data<PipelineStore>,
+    items: web::Json<Vec<BatchItem>>,
+    query: web::Query<BatchBody>,
+    cache: Data<Cache>,
+    disk_cache: Option<Data<DiskCache>>,
+    in_flight: Data<InFlightRenders>,
+) -> impl Responder {
+    let items = items.into_inner();
+    if items.len() > grimoire::MAX_BATCH_SIZE {
+        return ApiError::CountOutOfRange {
+            field: "batch",
+            min: 0,
+            max: grimoire::MAX_BATCH_SIZE as u64,
+        }
+        .error_response();
+    }
+    let parameters = items.into_iter().map(item_parameters).collect::<Vec<_>>();
+    let pixels = parameters
+        .iter()
+        .flatten()
+        .map(|parameters| u64::from(parameters.width) * u64::from(parameters.height))
+        .sum::<u64>();
+    if pixels > grimoire::MAX_BATCH_PIXELS {
+        return ApiError::BadRequest(format!(
+            "A batch can have at most {} pixels",
+            grimoire::MAX_BATCH_PIXELS
+        ))
+        .error_response();
+    }
+
+    let mut results = Vec::with_capacity(parameters.len());
+    for parameters in parameters {
+        let result = match parameters {
+            Ok(parameters) => cached_data(
+                &backend,
+                &pipelines,
+                &cache,
+                disk_cache.as_ref().map(|d| d.get_ref()),
+                &in_flight,
+                &parameters,
+                &Arc::default(),
+            )
+            .await
+            .map(|data| (parameters.format, data)),
+            Err(e) => Err(e),
+        };
+        results.push(result);
+    }
+
+    match query.output.unwrap_or_default() {
+        BatchFormat::Zip => archive_response(ArchiveFormat::Zip, results).await,
+        BatchFormat::Tar => archive_response(ArchiveFormat::Tar, results).await,
+        BatchFormat::Multipart => multipart_response(results),
+    }
+}
+
+///The format defaults to png, since the accept header is about the whole batch
+fn item_parameters(item: BatchItem) -> Result<RenderParameters, ApiError> {
+    let mut query = item.spec.into_request_body()?;
+    query.format.get_or_insert_with(OutputFormat::default);
+    query.to_parameters(item.fractal)
+}
+
+type BatchResult = Result<(OutputFormat, Vec<u8>), ApiError>;
+
+fn file_name(index: usize, format: OutputFormat) -> String {
+    format!("{index}.{}", format.extension())
+}
+
+async fn archive_response(format: ArchiveFormat, results: Vec<BatchResult>) -> HttpResponse {
+    let manifest = results
+        .iter()
+        .enumerate()
+        .map(|(index, result)| match result {
+            Ok((format, _)) => json!({
+                "index": index,
+                "status": StatusCode::OK.as_u16(),
+                "file": file_name(index, *format),
+            }),
+            Err(e) => json!({
+                "index": index,
+                "status": e.status().as_u16(),
+                "error": e.body(),
+            }),
+        })
+        .collect::<Vec<_>>();
+    let files = results
+        .into_iter()
+        .enumerate()
+        .filter_map(|(index, result)| {
+            let (format, data) = result.ok()?;
+            Some((file_name(index, format), data))
+        })
+        .collect::<Vec<_>>();
+
+    let archive = web::block(move || {
+        let mut archive = Archive::new(format);
+        let manifest = serde_json::to_vec_pretty(&manifest).map_err(|e| e.to_string())?;
+        archive.add("manifest.json", &manifest)?;
+        for (name, data) in files {
+            archive.add(&name, &data)?;
+        }
+        archive.finish()
+    })
+    .await
+    .map_err(|e| e.to_string())
+    .and_then(|result| result);
+    match archive {
+        Ok(data) => HttpResponse::Ok()
+            .content_type(format.mime())
+            .streaming(async_iter(data)),
+        Err(e) => {
+            log::error!(
+                target: grimoire::LOGGING_TARGET,
+                "Could not export batch {e}"
+            );
+            ApiError::Internal("Unable to export batch".to_string()).error_response()
+        }
+    }
+}
+
+///Every part has the status of its image in `X-Status`, failed images are their json error
+fn multipart_response(results: Vec<BatchResult>) -> HttpResponse {
+    //Images are binary, so the boundary is made unlikely to show up in them by chance
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    let boundary = format!("fractals-batch-{nanos:032x}");
+
+    let mut body = Vec::new();
+    for (index, result) in results.into_iter().enumerate() {
+        let (status, content_type, name, data) = match result {
+            Ok((format, data)) => (
+                StatusCode::OK,
+                format.mime(),
+                file_name(index, format),
+                data,
+            ),
+            //The same body as the error would have on its own
+            Err(e) => (
+                e.status(),
+                "application/json",
+                format!("{index}.json"),
+                e.body().to_string().into_bytes(),
+            ),
+        };
+        body.extend_from_slice(
+            format!(
+                "--{boundary}\r\nContent-Type: {content_type}\r\n\
+                 Content-Disposition: attachment; filename=\"{name}\"\r\n\
+                 X-Status: {}\r\n\r\n",
+                status.as_u16()
+            )
+            .as_bytes(),
+        );
+        body.extend_from_slice(&data);
+        body.extend_from_slice(b"\r\n");
+    }
+    body.extend_from_slice(format!("--{boundary}--\r\n").as_bytes());
+
+    HttpResponse::Ok()
+        .content_type(format!("multipart/mixed; boundary={boundary}"))
+        .streaming(async_iter(body))
+}
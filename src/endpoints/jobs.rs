@@ -0,0 +1,174 @@
+use actix_web::{
+    http::header,
+    rt,
+    web::{self, Data},
+    HttpResponse, Responder, ResponseError,
+};
+
+use super::{
+    animation::{animation_data, animation_pixels},
+    pyramid::{check_pyramid_size, pyramid_data, pyramid_pixels},
+    rendering::cached_data,
+};
+use crate::{
+    structs::{
+        errors::ApiError,
+        rendering::{Backend, RenderParameters},
+        requests::{
+            AnimationBody, ArchiveFormat, Cache, InFlightRenders, JobBody, JobOutput, Jobs,
+        },
+    },
+    utils::{disk_cache::DiskCache, export::async_iter, jobs::JobStatus},
+    PipelineStore,
+};
+
+///What a job renders, validated before it's queued
+enum JobKind {
+    Image(RenderParameters),
+    Animation(Vec<RenderParameters>, AnimationBody),
+    Pyramid(RenderParameters, ArchiveFormat),
+}
+
+///Queues a render that takes longer than a request should, the body is json with the same fields
+///as `/fractals/{fractal}`, the fractal, and an optional `animation` with the fields of
+///`/animations/{fractal}` or `pyramid` with the fields of `/pyramids/{fractal}`
+#[allow(clippy::too_many_arguments)]
+#[actix_web::post("/jobs")]
+async fn submit_job(
+    backend: Data<Backend>,
+    pipelines: Data<PipelineStore>,
+    body: web::Json<JobBody>,
+    jobs: Data<Jobs>,
+    cache: Data<Cache>,
+    disk_cache: Option<Data<DiskCache>>,
+    in_flight: Data<InFlightRenders>,
+) -> impl Responder {
+    let JobBody {
+        fractal,
+        animation,
+        pyramid,
+        render,
+    } = body.into_inner();
+    let kind = match (animation, pyramid) {
+        (Some(_), Some(_)) => Err(ApiError::invalid(
+            "pyramid",
+            "A job can't be both an animation and a pyramid",
+        )),
+        (Some(animation), None) => animation
+            .to_frames(&render, fractal)
+            .map(|frames| JobKind::Animation(frames, animation)),
+        (None, Some(pyramid)) => render
+            .to_pyramid_parameters(fractal)
+            .and_then(|parameters| {
+                check_pyramid_size(&parameters)?;
+                Ok(JobKind::Pyramid(
+                    parameters,
+                    pyramid.output.unwrap_or_default(),
+                ))
+            }),
+        (None, None) => render.to_poster_parameters(fractal).map(JobKind::Image),
+    };
+    let kind = match kind {
+        Ok(kind) => kind,
+        Err(e) => return e.error_response(),
+    };
+    let pixels = match &kind {
+        JobKind::Image(parameters) => u64::from(parameters.width) * u64::from(parameters.height),
+        JobKind::Animation(frames, animation) => animation_pixels(frames, animation),
+        JobKind::Pyramid(parameters, _) => pyramid_pixels(parameters),
+    };
+    let (id, progress) = match jobs.submit(pixels) {
+        Ok(job) => job,
+        Err(e) => return ApiError::Unavailable(e).error_response(),
+    };
+
+    //Runs on this worker, since the backend and pipelines belong to it
+    let queue = jobs.clone();
+    let task = rt::spawn(async move {
+        let _permit = queue.start(id).await;
+        let result = match kind {
+            JobKind::Image(parameters) => cached_data(
+                &backend,
+                &pipelines,
+                &cache,
+                disk_cache.as_ref().map(|d| d.get_ref()),
+                &in_flight,
+                &parameters,
+                &progress,
+            )
+            .await
+            .map(|data| JobOutput {
+                data,
+                content_type: parameters.format.mime(),
+            }),
+            JobKind::Animation(frames, animation) => {
+                animation_data(&backend, &pipelines, &frames, &animation, &progress)
+                    .await
+                    .map(|data| JobOutput {
+                        data,
+                        content_type: animation.output.unwrap_or_default().mime(),
+                    })
+            }
+            JobKind::Pyramid(parameters, output) => {
+                pyramid_data(&backend, &pipelines, &parameters, output, &progress)
+                    .await
+                    .map(|data| JobOutput {
+                        data,
+                        content_type: output.mime(),
+                    })
+            }
+        };
+        let size = result.as_ref().map_or(0, |output| output.data.len() as u64);
+        queue.finish(id, result, size);
+    });
+    jobs.set_task(id, task.abort_handle());
+
+    HttpResponse::Accepted()
+        .insert_header((header::LOCATION, format!("/jobs/{id}")))
+        .json(jobs.info(id))
+}
+
+///Status and progress of a job
+#[actix_web::get("/jobs/{id}")]
+async fn job_status(id: web::Path<u64>, jobs: Data<Jobs>) -> impl Responder {
+    match jobs.info(*id) {
+        Some(info) => HttpResponse::Ok().json(info),
+        None => not_found(*id),
+    }
+}
+
+///The output of a finished job, or its status if it isn't done yet
+#[actix_web::get("/jobs/{id}/result")]
+async fn job_result(id: web::Path<u64>, jobs: Data<Jobs>) -> impl Responder {
+    let Some(info) = jobs.info(*id) else {
+        return not_found(*id);
+    };
+    match info.status {
+        JobStatus::Queued | JobStatus::Running => HttpResponse::Accepted().json(info),
+        JobStatus::Canceled => {
+            ApiError::Gone(format!("Job {} was canceled", info.id)).error_response()
+        }
+        JobStatus::Done | JobStatus::Failed => match jobs.result(info.id) {
+            Some(Ok(output)) => HttpResponse::Ok()
+                .content_type(output.content_type)
+                .streaming(async_iter(output.data)),
+            Some(Err(e)) => e.error_response(),
+            //Dropped by the retention in the meantime
+            None => not_found(info.id),
+        },
+    }
+}
+
+///Cancels a job that's queued or running, or deletes the result of a finished one
+#[actix_web::delete("/jobs/{id}")]
+async fn cancel_job(id: web::Path<u64>, jobs: Data<Jobs>) -> impl Responder {
+    if jobs.cancel(*id) {
+        HttpResponse::NoContent().finish()
+    } else {
+        not_found(*id)
+    }
+}
+
+fn not_found(id: u64) -> HttpResponse {
+    ApiError::NotFound(format!("No job with id {id}")).error_response()
+}
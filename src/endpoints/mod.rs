@@ -1,4 +1,20 @@
+mod admin;
+mod animation;
+mod batch;
+mod jobs;
+mod preview;
+mod progress;
+mod pyramid;
 mod rendering;
 mod r#static;
+mod tiles;
+pub use admin::*;
+pub use animation::*;
+pub use batch::*;
+pub use jobs::*;
+pub use preview::*;
+pub use progress::*;
+pub use pyramid::*;
 pub use r#static::*;
 pub use rendering::*;
+pub use tiles::*;
@@ -0,0 +1,229 @@
+use std::sync::Arc;
+
+use actix_http::ws::{CloseCode, Frame, Message};
+use actix_web::{
+    rt,
+    web::{self, Data},
+    HttpRequest, Responder,
+};
+use futures::{channel::mpsc, SinkExt, StreamExt};
+use serde_json::json;
+use tokio::sync::watch;
+
+use crate::{
+    grimoire,
+    structs::{
+        errors::ApiError,
+        rendering::{Backend, RenderParameters, RenderProgress, RenderRequest},
+        requests::{OutputFormat, RequestBody, SimplifiedFractals},
+    },
+    utils::{
+        export,
+        graphics::{deep_zoom, render_with_progress},
+        websocket,
+    },
+    PipelineStore,
+};
+
+///Interactive previews over a websocket. Every text message is a view as json with the fields of
+///`/fractals/{fractal}`, which is sent back at increasing sizes, each as a json text message that
+///describes the frame followed by the image. A new view cancels the frames of the previous one,
+///and views that come in faster than they're rendered are skipped
+#[actix_web::get("/previews/{fractal}")]
+async fn preview(
+    req: HttpRequest,
+    payload: web::Payload,
+    backend: Data<Backend>,
+    pipelines: Data<PipelineStore>,
+    fractal: web::Path<SimplifiedFractals>,
+) -> impl Responder {
+    let fractal = fractal.into_inner();
+    let (mut sender, receiver) = mpsc::channel(grimoire::PREVIEW_BUFFER);
+    let response = match websocket::upgrade(&req, receiver) {
+        Ok(response) => response,
+        Err(response) => return response,
+    };
+
+    //Views that arrive while one is rendering replace each other, only the latest one is rendered
+    let (views, latest) = watch::channel(None);
+    rt::spawn(refine_latest(backend, pipelines, sender.clone(), latest));
+    rt::spawn(async move {
+        let mut frames = Box::pin(websocket::frames(payload));
+        //Canceled once the view is replaced, so that its render stops right away
+        let mut current = Arc::<RenderProgress>::default();
+        let mut view = 0;
+        while let Some(frame) = frames.next().await {
+            let reply = match frame {
+                Ok(Frame::Text(text)) => {
+                    view += 1;
+                    match view_parameters(&text, fractal) {
+                        Ok(parameters) => {
+                            //An invalid view leaves the previous one going
+                            current.cancel();
+                            current = Arc::default();
+                            views.send_replace(Some(View {
+                                number: view,
+                                parameters,
+                                progress: current.clone(),
+                            }));
+                            continue;
+                        }
+                        Err(e) => error_message(view, &e),
+                    }
+                }
+                Ok(Frame::Ping(data)) => Message::Pong(data),
+                Ok(Frame::Pong(_)) => continue,
+                Ok(Frame::Binary(_) | Frame::Continuation(_)) => {
+                    let error = ApiError::BadRequest(
+                        "Views should be sent as json text messages".to_string(),
+                    );
+                    error_message(view, &error)
+                }
+                Ok(Frame::Close(reason)) => {
+                    current.cancel();
+                    let _ = sender.send(Message::Close(reason)).await;
+                    break;
+                }
+                Err(e) => {
+                    log::debug!(target: grimoire::LOGGING_TARGET, "Invalid websocket frame {e}");
+                    current.cancel();
+                    let _ = sender
+                        .send(Message::Close(Some(CloseCode::Protocol.into())))
+                        .await;
+                    break;
+                }
+            };
+            if sender.send(reply).await.is_err() {
+                break;
+            }
+        }
+        //If the client only stopped sending, the frames of the last view are still sent
+    });
+    response
+}
+
+///A view that the client asked for
+#[derive(Clone)]
+struct View {
+    number: u64,
+    parameters: RenderParameters,
+    progress: Arc<RenderProgress>,
+}
+
+fn view_parameters(text: &[u8], fractal: SimplifiedFractals) -> Result<RenderParameters, ApiError> {
+    let mut query = serde_json::from_slice::<RequestBody>(text)
+        .map_err(|e| ApiError::BadRequest(format!("Invalid view: {e}")))?;
+    query.format.get_or_insert_with(OutputFormat::default);
+    query.to_parameters(fractal)
+}
+
+fn error_message(view: u64, error: &ApiError) -> Message {
+    Message::Text(
+        json!({ "view": view, "error": error.body() })
+            .to_string()
+            .into(),
+    )
+}
+
+///Refines the latest view, until the client stops sending them
+async fn refine_latest(
+    backend: Data<Backend>,
+    pipelines: Data<PipelineStore>,
+    mut sender: mpsc::Sender<Message>,
+    mut views: watch::Receiver<Option<View>>,
+) {
+    while views.changed().await.is_ok() {
+        let Some(view) = views.borrow_and_update().clone() else {
+            continue;
+        };
+        if refine(&backend, &pipelines, &mut sender, view)
+            .await
+            .is_err()
+        {
+            return;
+        }
+    }
+}
+
+///Sends the view at every preview scale, from the roughest to the full size, stopping once it's
+///replaced. Errors once the client is gone
+async fn refine(
+    backend: &Backend,
+    pipelines: &PipelineStore,
+    sender: &mut mpsc::Sender<Message>,
+    view: View,
+) -> Result<(), mpsc::SendError> {
+    let stages = grimoire::PREVIEW_SCALES.len();
+    //The reference orbit doesn't depend on the size, so every stage shares it
+    let deep_zoom = deep_zoom(&view.parameters).await;
+    if view.progress.is_canceled() {
+        return Ok(());
+    }
+    let deep_zoom = match deep_zoom {
+        Ok(deep_zoom) => deep_zoom,
+        Err(e) => {
+            return sender
+                .send(error_message(view.number, &ApiError::BadRequest(e)))
+                .await
+        }
+    };
+    for (stage, scale) in grimoire::PREVIEW_SCALES.into_iter().enumerate() {
+        //The zoom is relative to the height, so the view stays the same
+        let parameters = RenderParameters {
+            width: (view.parameters.width / scale).max(1),
+            height: (view.parameters.height / scale).max(1),
+            ..view.parameters.clone()
+        };
+        let (width, height) = (parameters.width, parameters.height);
+        let request = parameters.to_render_request_with(deep_zoom.clone());
+        let result = preview_image(backend, pipelines, &parameters, &request, &view.progress).await;
+        if view.progress.is_canceled() {
+            return Ok(());
+        }
+        let image = match result {
+            Ok(image) => image,
+            Err(e) => return sender.send(error_message(view.number, &e)).await,
+        };
+        let info = json!({
+            "view": view.number,
+            "stage": stage,
+            "stages": stages,
+            "width": width,
+            "height": height,
+        });
+        sender.send(Message::Text(info.to_string().into())).await?;
+        sender.send(Message::Binary(image.into())).await?;
+    }
+    Ok(())
+}
+
+async fn preview_image(
+    backend: &Backend,
+    pipelines: &PipelineStore,
+    parameters: &RenderParameters,
+    request: &RenderRequest,
+    progress: &Arc<RenderProgress>,
+) -> Result<Vec<u8>, ApiError> {
+    let img = render_with_progress(backend, pipelines, request, progress)
+        .await
+        .map_err(|e| {
+            if progress.is_canceled() {
+                return ApiError::Gone(e);
+            }
+            log::error!(target: grimoire::LOGGING_TARGET, "{e}");
+            ApiError::Internal("Unable to render the preview".to_string())
+        })?;
+    let (width, height) = (request.width, request.height);
+    let (format, quality) = (parameters.format, parameters.quality);
+    web::block(move || export::encode(img, width, height, format, quality))
+        .await
+        .map_err(|e| e.to_string())
+        .and_then(|result| result)
+        .map_err(|e| {
+            log::error!(
+                target: grimoire::LOGGING_TARGET,
+                "Could not export preview {e}"
+            );
+            ApiError::Internal("Unable to export the preview".to_string())
+        })
+}
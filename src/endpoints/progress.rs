@@ -0,0 +1,170 @@
+use std::{sync::Arc, time::Instant};
+
+use actix_web::{
+    http::header,
+    rt,
+    web::{self, Bytes, Data},
+    HttpRequest, HttpResponse, Responder, ResponseError,
+};
+use futures::{channel::oneshot, stream};
+use serde_json::json;
+
+use super::rendering::cached_data;
+use crate::{
+    grimoire,
+    structs::{
+        errors::ApiError,
+        rendering::{Backend, RenderProgress},
+        requests::{Cache, InFlightRenders, Jobs, OutputFormat, RequestBody, SimplifiedFractals},
+    },
+    utils::{disk_cache::DiskCache, jobs::JobStatus},
+    PipelineStore,
+};
+
+///What the event stream sends next
+enum Update {
+    Progress(serde_json::Value),
+    ///Sent last, the stream ends after it
+    Finished(&'static str, serde_json::Value),
+}
+
+///Estimates the time left from how fast the progress moved since it was first seen
+#[derive(Default)]
+struct Eta {
+    first: Option<(Instant, f32)>,
+}
+
+impl Eta {
+    fn seconds(&mut self, progress: f32) -> Option<f32> {
+        let (start, from) = *self.first.get_or_insert((Instant::now(), progress));
+        let rate = (progress - from) / start.elapsed().as_secs_f32();
+        (rate > 0.0).then(|| (1.0 - progress) / rate)
+    }
+}
+
+///Renders like `/fractals/{fractal}` and streams its progress as server-sent events. The last
+///event has the url of the image, which is cached by then
+#[allow(clippy::too_many_arguments)]
+#[actix_web::get("/fractals/{fractal}/progress")]
+async fn render_progress(
+    req: HttpRequest,
+    backend: Data<Backend>,
+    pipelines: Data<PipelineStore>,
+    fractal: web::Path<SimplifiedFractals>,
+    query: web::Query<RequestBody>,
+    cache: Data<Cache>,
+    disk_cache: Option<Data<DiskCache>>,
+    in_flight: Data<InFlightRenders>,
+) -> impl Responder {
+    let mut query = query.into_inner();
+    let mut query_string = req.query_string().to_string();
+    //The accept header is for the events, so the format of the image has to be in its url
+    if query.format.is_none() {
+        let format = OutputFormat::default();
+        query.format = Some(format);
+        if !query_string.is_empty() {
+            query_string.push('&');
+        }
+        query_string.push_str(&format!("format={}", format.extension()));
+    }
+    let parameters = match query.to_parameters(fractal.into_inner()) {
+        Ok(parameters) => parameters,
+        Err(e) => return e.error_response(),
+    };
+    let url = format!(
+        "{}?{query_string}",
+        req.path().trim_end_matches("/progress")
+    );
+    let rows = parameters.height;
+    let progress = Arc::new(RenderProgress::new(
+        u64::from(parameters.width) * u64::from(rows),
+    ));
+
+    //Keeps going if the client disconnects, the image still ends up in the cache
+    let (sender, mut receiver) = oneshot::channel();
+    let render_progress = progress.clone();
+    rt::spawn(async move {
+        let result = cached_data(
+            &backend,
+            &pipelines,
+            &cache,
+            disk_cache.as_ref().map(|d| d.get_ref()),
+            &in_flight,
+            &parameters,
+            &render_progress,
+        )
+        .await;
+        let _ = sender.send(result.map(|_| ()));
+    });
+
+    let mut eta = Eta::default();
+    event_stream(move || match receiver.try_recv() {
+        Ok(None) => {
+            let fraction = progress.fraction();
+            Update::Progress(json!({
+                "progress": fraction,
+                "rows_done": (fraction * rows as f32) as u32,
+                "rows_total": rows,
+                "eta_seconds": eta.seconds(fraction),
+            }))
+        }
+        Ok(Some(Ok(()))) => Update::Finished("done", json!({ "url": url })),
+        Ok(Some(Err(e))) => {
+            let mut error = e.body();
+            error["status"] = json!(e.status().as_u16());
+            Update::Finished("failed", error)
+        }
+        Err(_) => Update::Finished(
+            "failed",
+            json!({ "status": 500, "message": "The render stopped unexpectedly" }),
+        ),
+    })
+}
+
+///Streams the progress of a job as server-sent events, the last event has the url of the result
+#[actix_web::get("/jobs/{id}/progress")]
+async fn job_progress(id: web::Path<u64>, jobs: Data<Jobs>) -> impl Responder {
+    let id = id.into_inner();
+    if jobs.info(id).is_none() {
+        return ApiError::NotFound(format!("No job with id {id}")).error_response();
+    }
+
+    let mut eta = Eta::default();
+    event_stream(move || {
+        let Some(info) = jobs.info(id) else {
+            return Update::Finished("failed", json!({ "message": "The job was deleted" }));
+        };
+        match info.status {
+            JobStatus::Queued | JobStatus::Running => Update::Progress(json!({
+                "status": info.status,
+                "progress": info.progress,
+                "eta_seconds": eta.seconds(info.progress),
+            })),
+            JobStatus::Done => {
+                Update::Finished("done", json!({ "url": format!("/jobs/{id}/result") }))
+            }
+            JobStatus::Failed => Update::Finished("failed", json!({ "message": info.error })),
+            JobStatus::Canceled => Update::Finished("canceled", json!({})),
+        }
+    })
+}
+
+///Calls `poll` every [`grimoire::PROGRESS_INTERVAL`] and sends what it returns as server-sent
+///events, until it's finished
+fn event_stream(poll: impl FnMut() -> Update + 'static) -> HttpResponse {
+    let interval = rt::time::interval(grimoire::PROGRESS_INTERVAL);
+    let events = stream::unfold(Some((interval, poll)), |state| async move {
+        let (mut interval, mut poll) = state?;
+        interval.tick().await;
+        let (name, data, next) = match poll() {
+            Update::Progress(data) => ("progress", data, Some((interval, poll))),
+            Update::Finished(name, data) => (name, data, None),
+        };
+        let event = format!("event: {name}\ndata: {data}\n\n");
+        Some((Ok::<_, actix_web::Error>(Bytes::from(event)), next))
+    });
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header((header::CACHE_CONTROL, "no-cache"))
+        .streaming(events)
+}
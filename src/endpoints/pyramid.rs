@@ -0,0 +1,242 @@
+use std::sync::Arc;
+
+use actix_web::{
+    http::header::{self, HeaderValue},
+    web::{self, Data},
+    HttpRequest, HttpResponse, Responder, ResponseError,
+};
+
+use super::rendering::render_cached;
+use crate::{
+    grimoire,
+    structs::{
+        errors::ApiError,
+        rendering::{Backend, RenderParameters, RenderProgress},
+        requests::{
+            ArchiveFormat, Cache, InFlightRenders, OutputFormat, PyramidBody, RequestBody,
+            SimplifiedFractals,
+        },
+    },
+    utils::{
+        archive::Archive,
+        disk_cache::DiskCache,
+        export::{self, async_iter},
+        graphics::{render_request, render_with_progress},
+        pyramid,
+    },
+    PipelineStore,
+};
+
+///Renders a whole deep zoom pyramid tile by tile, and returns the `.dzi` descriptor and the tile
+///folders in an archive. Big pyramids have to be rendered as a job
+#[actix_web::get("/pyramids/{fractal}")]
+async fn render_pyramid(
+    backend: Data<Backend>,
+    pipelines: Data<PipelineStore>,
+    fractal: web::Path<SimplifiedFractals>,
+    query: web::Query<RequestBody>,
+    pyramid: web::Query<PyramidBody>,
+) -> impl Responder {
+    let parameters = match query.to_pyramid_parameters(fractal.into_inner()) {
+        Ok(parameters) => parameters,
+        Err(e) => return e.error_response(),
+    };
+    if u64::from(parameters.width) * u64::from(parameters.height)
+        > grimoire::MAX_DIRECT_PYRAMID_PIXELS
+    {
+        return ApiError::BadRequest(format!(
+            "Deep zoom pyramids over {} pixels have to be rendered as a job, on /jobs",
+            grimoire::MAX_DIRECT_PYRAMID_PIXELS
+        ))
+        .error_response();
+    }
+    let output = pyramid.output.unwrap_or_default();
+    match pyramid_data(&backend, &pipelines, &parameters, output, &Arc::default()).await {
+        Ok(data) => HttpResponse::Ok()
+            .content_type(output.mime())
+            .streaming(async_iter(data)),
+        Err(e) => e.error_response(),
+    }
+}
+
+///Errors if the pyramid is too big to be rendered at all
+pub(super) fn check_pyramid_size(parameters: &RenderParameters) -> Result<(), ApiError> {
+    if u64::from(parameters.width) * u64::from(parameters.height) > grimoire::MAX_PYRAMID_PIXELS {
+        return Err(ApiError::BadRequest(format!(
+            "Deep zoom pyramids can have at most {} pixels",
+            grimoire::MAX_PYRAMID_PIXELS
+        )));
+    }
+    Ok(())
+}
+
+///Pixels that [`pyramid_data`] goes through, for its progress
+pub(super) fn pyramid_pixels(parameters: &RenderParameters) -> u64 {
+    pyramid::dzi_tiles(parameters.width, parameters.height)
+        .iter()
+        .map(|tile| u64::from(tile.size[0]) * u64::from(tile.size[1]))
+        .sum()
+}
+
+///Renders every tile and puts them in an archive with the descriptor, advancing `progress` as the
+///tiles are rendered
+pub(super) async fn pyramid_data(
+    backend: &Backend,
+    pipelines: &PipelineStore,
+    parameters: &RenderParameters,
+    output: ArchiveFormat,
+    progress: &Arc<RenderProgress>,
+) -> Result<Vec<u8>, ApiError> {
+    let (width, height) = (parameters.width, parameters.height);
+    let (format, quality) = (parameters.format, parameters.quality);
+
+    let mut archive = Archive::new(output);
+    let descriptor = pyramid::dzi_descriptor(width, height, format);
+    archive
+        .add("pyramid.dzi", descriptor.as_bytes())
+        .map_err(|e| export_error(&e))?;
+    for tile in pyramid::dzi_tiles(width, height) {
+        let request = render_request(&parameters.region(tile.region, tile.size))
+            .await
+            .map_err(ApiError::BadRequest)?;
+        let img = render_with_progress(backend, pipelines, &request, progress)
+            .await
+            .map_err(|e| {
+                if progress.is_canceled() {
+                    return ApiError::Gone(e);
+                }
+                log::error!(target: grimoire::LOGGING_TARGET, "{e}");
+                ApiError::Internal(String::new())
+            })?;
+        let name = format!(
+            "pyramid_files/{}/{}_{}.{}",
+            tile.level,
+            tile.column,
+            tile.row,
+            format.extension()
+        );
+        let [tile_width, tile_height] = tile.size;
+        archive = web::block(move || {
+            let data = export::encode(img, tile_width, tile_height, format, quality)?;
+            archive.add(&name, &data).map(|()| archive)
+        })
+        .await
+        .map_err(|e| e.to_string())
+        .and_then(|result| result)
+        .map_err(|e| export_error(&e))?;
+    }
+
+    web::block(move || archive.finish())
+        .await
+        .map_err(|e| e.to_string())
+        .and_then(|result| result)
+        .map_err(|e| export_error(&e))
+}
+
+///Iiif image api description of an image, the identifier is the query string of the image, like
+///`width=65536&height=65536&smooth=true`
+#[actix_web::get("/iiif/{fractal}/{identifier}/info.json")]
+async fn iiif_info(
+    req: HttpRequest,
+    path: web::Path<(SimplifiedFractals, String)>,
+) -> impl Responder {
+    let (fractal, identifier) = path.into_inner();
+    let parameters = match iiif_parameters(fractal, &identifier, None) {
+        Ok(parameters) => parameters,
+        Err(e) => return e.error_response(),
+    };
+    let connection = req.connection_info();
+    let path = req.path().trim_end_matches("/info.json");
+    let id = format!("{}://{}{path}", connection.scheme(), connection.host());
+    HttpResponse::Ok()
+        //Viewers are usually on a different origin
+        .insert_header((header::ACCESS_CONTROL_ALLOW_ORIGIN, "*"))
+        .json(pyramid::iiif_info(&id, parameters.width, parameters.height))
+}
+
+///Iiif image api requests for a part of an image, rendered and cached like any other image
+#[allow(clippy::too_many_arguments)]
+#[actix_web::get("/iiif/{fractal}/{identifier}/{region}/{size}/{rotation}/{quality}.{format}")]
+async fn iiif_image(
+    backend: Data<Backend>,
+    pipelines: Data<PipelineStore>,
+    path: web::Path<(
+        SimplifiedFractals,
+        String,
+        String,
+        String,
+        String,
+        String,
+        String,
+    )>,
+    cache: Data<Cache>,
+    disk_cache: Option<Data<DiskCache>>,
+    in_flight: Data<InFlightRenders>,
+) -> impl Responder {
+    let (fractal, identifier, region, size, rotation, quality, extension) = path.into_inner();
+    let Some(format) = OutputFormat::ALL
+        .into_iter()
+        .find(|format| !format.is_raw() && format.extension() == extension)
+    else {
+        return ApiError::invalid("format", format!("Unsupported format {extension}"))
+            .error_response();
+    };
+    if rotation != "0" {
+        return ApiError::invalid("rotation", "Only a rotation of 0 is supported").error_response();
+    }
+    if quality != "default" && quality != "color" {
+        return ApiError::invalid(
+            "quality",
+            "Only the default and color qualities are supported",
+        )
+        .error_response();
+    }
+    let parameters = match iiif_parameters(fractal, &identifier, Some(format)).and_then(|full| {
+        let region = pyramid::parse_region(&region, [full.width, full.height])
+            .map_err(|e| ApiError::invalid("region", e))?;
+        let size = pyramid::parse_size(&size, [region[2], region[3]])
+            .map_err(|e| ApiError::invalid("size", e))?;
+        Ok(full.region(region.map(f64::from), size))
+    }) {
+        Ok(parameters) => parameters,
+        Err(e) => return e.error_response(),
+    };
+
+    let mut response = render_cached(
+        &backend,
+        &pipelines,
+        &cache,
+        disk_cache.as_ref().map(|d| d.get_ref()),
+        &in_flight,
+        &parameters,
+    )
+    .await;
+    response.headers_mut().insert(
+        header::ACCESS_CONTROL_ALLOW_ORIGIN,
+        HeaderValue::from_static("*"),
+    );
+    response
+}
+
+///Parameters of the full image that an iiif identifier describes
+fn iiif_parameters(
+    fractal: SimplifiedFractals,
+    identifier: &str,
+    format: Option<OutputFormat>,
+) -> Result<RenderParameters, ApiError> {
+    let query = web::Query::<RequestBody>::from_query(identifier)
+        .map_err(|e| ApiError::invalid("identifier", format!("Invalid identifier: {e}")))?;
+    RequestBody {
+        format,
+        ..query.into_inner()
+    }
+    .to_pyramid_parameters(fractal)
+}
+
+fn export_error(e: &str) -> ApiError {
+    log::error!(
+        target: grimoire::LOGGING_TARGET,
+        "Could not export image pyramid {e}"
+    );
+    ApiError::Internal("Unable to export image pyramid".to_string())
+}
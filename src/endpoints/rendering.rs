@@ -1,268 +1,273 @@
-#![allow(
-    clippy::cast_possible_truncation,
-    clippy::cast_precision_loss,
-    clippy::too_many_lines
-)]
+use std::{sync::Arc, time::Instant};
+
 use actix_web::{
+    http::header::{self, Accept},
     web::{self, Data},
-    HttpResponse, Responder,
+    HttpMessage, HttpRequest, HttpResponse, Responder, ResponseError,
 };
-use wgpu::CommandBuffer;
 
 use crate::{
     grimoire,
     structs::{
-        rendering::{GpuStructs, PipelineBufers, ShaderDataUniforms},
-        requests::{Cache, RequestBody, RequestIdentifier, SimplifiedFractals},
+        errors::ApiError,
+        rendering::{Backend, RenderParameters, RenderProgress},
+        requests::{
+            Cache, InFlightRenders, OutputFormat, RenderSpec, RequestBody, SimplifiedFractals,
+        },
     },
     utils::{
+        coalesce::Joined,
+        disk_cache::DiskCache,
         export::{self, async_iter},
-        graphics::{generate_pipeline, to_raw_colors, vec_from_hex},
-        vec::{contains_key, get},
+        graphics::{render_request, render_with_progress},
     },
     PipelineStore,
 };
 
-fn generate_command_buffer(
-    encoder: wgpu::CommandEncoder,
-    texture: &wgpu::Texture,
-    buffer: &wgpu::Buffer,
-    pipeline: &PipelineBufers,
-    bytes_per_row: u32,
-) -> CommandBuffer {
-    let mut encoder = encoder;
-    let texture_view = texture.create_view(&wgpu::TextureViewDescriptor::default());
-    {
-        //Clear
-        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-            label: Some("Render image pass"),
-            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                view: &texture_view,
-                resolve_target: None,
-                ops: wgpu::Operations {
-                    load: wgpu::LoadOp::Clear(grimoire::CLEAR_COLOR),
-                    store: true,
-                },
-            })],
-            depth_stencil_attachment: None,
-        });
-
-        render_pass.set_bind_group(0, &pipeline.bind_group, &[]);
-        render_pass.set_pipeline(&pipeline.pipeline);
-        render_pass.draw(0..6, 0..1);
-    }
-    {
-        //Copy contents of render texture to the buffer
-        encoder.copy_texture_to_buffer(
-            texture.as_image_copy(),
-            wgpu::ImageCopyBuffer {
-                buffer,
-                layout: wgpu::ImageDataLayout {
-                    offset: 0,
-                    bytes_per_row: Some(bytes_per_row),
-                    rows_per_image: None,
-                },
-            },
-            texture.size(),
-        );
-    }
-    encoder.finish()
-}
-
-//Post so that there's a body
 ///The main endpoint for rendering fractals
+#[allow(clippy::too_many_arguments)]
 #[actix_web::get("/fractals/{fractal}")]
 async fn render_fractal(
-    gpu: Data<GpuStructs>,
+    req: HttpRequest,
+    backend: Data<Backend>,
     pipelines: Data<PipelineStore>,
     fractal: web::Path<SimplifiedFractals>,
     query: web::Query<RequestBody>,
     cache: web::Data<Cache>,
+    disk_cache: Option<web::Data<DiskCache>>,
+    in_flight: web::Data<InFlightRenders>,
 ) -> impl Responder {
-    let query = query.into_inner();
-    let fractal = fractal.into_inner();
-    let identifier = RequestIdentifier::new(fractal, &query);
+    let parameters = match request_parameters(&req, query.into_inner(), fractal.into_inner()) {
+        Ok(parameters) => parameters,
+        Err(response) => return response,
+    };
 
-    //Putting it in a separate block so that cache is unlocked after the check
-    {
-        let cache = cache.lock().unwrap();
-        if let Some(data) = get(&cache, &identifier) {
-            let stream = async_iter(data.clone());
-            log::debug!(target: grimoire::LOGGING_TARGET, "Returning cached data");
-            return HttpResponse::Ok().streaming(stream);
-        }
-    }
-    //A temporary check  while it is not implemented
-    if fractal == SimplifiedFractals::Custom {
-        return HttpResponse::NotImplemented().into();
-    }
+    render_cached(
+        &backend,
+        &pipelines,
+        &cache,
+        disk_cache.as_ref().map(|d| d.get_ref()),
+        &in_flight,
+        &parameters,
+    )
+    .await
+}
 
-    if fractal == SimplifiedFractals::Custom && query.formula.is_none() {
-        return HttpResponse::BadRequest()
-            .body("Formula should be specified to use custom fractal");
-    }
+///Same as `/fractals/{fractal}` with the parameters in a json body, for palettes and formulas
+///that don't fit in a url. See [`RenderSpec`] for what else it accepts
+#[allow(clippy::too_many_arguments)]
+#[actix_web::post("/fractals/{fractal}")]
+async fn render_fractal_spec(
+    req: HttpRequest,
+    backend: Data<Backend>,
+    pipelines: Data<PipelineStore>,
+    fractal: web::Path<SimplifiedFractals>,
+    spec: web::Json<RenderSpec>,
+    cache: web::Data<Cache>,
+    disk_cache: Option<web::Data<DiskCache>>,
+    in_flight: web::Data<InFlightRenders>,
+) -> impl Responder {
+    let query = match spec.into_inner().into_request_body() {
+        Ok(query) => query,
+        Err(e) => return e.error_response(),
+    };
+    let parameters = match request_parameters(&req, query, fractal.into_inner()) {
+        Ok(parameters) => parameters,
+        Err(response) => return response,
+    };
 
-    //Get all request data
-    let fractal = fractal.into_fractals(None);
-    let width = query.width.unwrap_or(grimoire::DEFAULT_WIDTH);
-    let height = query.height.unwrap_or(grimoire::DEFAULT_HEIGHT);
+    render_cached(
+        &backend,
+        &pipelines,
+        &cache,
+        disk_cache.as_ref().map(|d| d.get_ref()),
+        &in_flight,
+        &parameters,
+    )
+    .await
+}
 
-    let colors = query.colors.clone().map_or_else(
-        || Ok(grimoire::DEFAULT_COLORS.into()),
-        |v| {
-            let v = v.split(',').collect::<Vec<&str>>();
-            vec_from_hex(&v)
-        },
-    );
-    if colors.is_err() {
-        return HttpResponse::BadRequest().body("Invalid color format");
+///Picks the format from the accept header if it isn't set and validates the request, errors with
+///the response to send back
+fn request_parameters(
+    req: &HttpRequest,
+    mut query: RequestBody,
+    fractal: SimplifiedFractals,
+) -> Result<RenderParameters, HttpResponse> {
+    if query.format.is_none() {
+        let format = req
+            .get_header::<Accept>()
+            .map_or(Some(OutputFormat::default()), |accept| {
+                OutputFormat::negotiate(&accept)
+            });
+        let Some(format) = format else {
+            let supported = OutputFormat::ALL.map(OutputFormat::mime).join(", ");
+            return Err(
+                ApiError::NotAcceptable(format!("Supported image types are {supported}"))
+                    .error_response(),
+            );
+        };
+        query.format = Some(format);
     }
+    //Normalized first, so that requests that only differ in defaults share cache entries
+    query.to_parameters(fractal).map_err(|e| e.error_response())
+}
 
-    let colors = colors.unwrap();
-
-    let data = ShaderDataUniforms {
-        aspect: height as f32 / width as f32,
-        arr_len: colors.len() as u32,
-        max_iter: query.max_iterations.unwrap_or(grimoire::DEFUALT_MAX_ITER),
-        num_colors: query.num_colors.unwrap_or(grimoire::DEFAULT_NUM_COLORS),
-        zoom: query.zoom.unwrap_or(grimoire::DEFAULT_ZOOM),
-        position: [
-            query.position_x.unwrap_or(grimoire::DEFAULT_POSITION[0]),
-            -query.position_y.unwrap_or(grimoire::DEFAULT_POSITION[1]),
-        ],
-        flags: {
-            let mut result = u32::from(query.msaa.unwrap_or(1));
-            if query.smooth.unwrap_or_default() {
-                result |= grimoire::rendering_flags::SMOOTH;
-            }
-            if query.debug.unwrap_or_default() {
-                result |= grimoire::rendering_flags::DEBUG;
-            }
-            result
-        },
+///Returns the image from one of the caches, or renders it if no identical render is already
+///running
+pub(super) async fn render_cached(
+    backend: &Backend,
+    pipelines: &PipelineStore,
+    cache: &Cache,
+    disk_cache: Option<&DiskCache>,
+    in_flight: &InFlightRenders,
+    parameters: &RenderParameters,
+) -> HttpResponse {
+    let progress = Arc::default();
+    match cached_data(
+        backend, pipelines, cache, disk_cache, in_flight, parameters, &progress,
+    )
+    .await
+    {
+        Ok(data) => image_response(parameters, data),
+        Err(e) => e.error_response(),
     }
-    .raw();
-    let colors = to_raw_colors(&colors);
-
-    //I'm not checking these bc if they were poisoned, it's basically fucked
-    //According to chat GPT you can't salvage a poisoned mutex
-    let mut pipelines = pipelines.lock().unwrap();
+}
 
-    if !contains_key(&pipelines, &fractal) {
-        pipelines.push((fractal.clone(), generate_pipeline(&fractal, &gpu.device)));
+///Encoded image for [`render_cached`], also used by renders that don't answer right away.
+///`progress` only moves if this request is the one that renders the image
+pub(super) async fn cached_data(
+    backend: &Backend,
+    pipelines: &PipelineStore,
+    cache: &Cache,
+    disk_cache: Option<&DiskCache>,
+    in_flight: &InFlightRenders,
+    parameters: &RenderParameters,
+    progress: &Arc<RenderProgress>,
+) -> Result<Vec<u8>, ApiError> {
+    //Putting it in a separate block so that cache is unlocked after the check
+    {
+        let mut cache = cache.lock().unwrap();
+        if let Some(data) = cache.get(parameters) {
+            log::debug!(target: grimoire::LOGGING_TARGET, "Returning cached data");
+            return Ok(data);
+        }
     }
-
-    let pipeline = get(&pipelines, &fractal);
-    if pipeline.is_none() {
-        log::error!(target: grimoire::LOGGING_TARGET, "Could not get pipeline");
-        return HttpResponse::InternalServerError().into();
+    if let Some(disk_cache) = disk_cache {
+        if let Some(data) = disk_cache.get(parameters).await {
+            log::debug!(target: grimoire::LOGGING_TARGET, "Returning data cached on disk");
+            let size = data.len() as u64;
+            cache
+                .lock()
+                .unwrap()
+                .insert(parameters.clone(), data.clone(), size);
+            return Ok(data);
+        }
     }
-    let pipeline = pipeline.unwrap();
-
-    //Create texture
-    let texture = gpu.device.create_texture(&wgpu::TextureDescriptor {
-        label: None,
-        size: wgpu::Extent3d {
-            width,
-            height,
-            depth_or_array_layers: 1,
-        },
-        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
-        format: wgpu::TextureFormat::Rgba8Unorm,
-        view_formats: &[wgpu::TextureFormat::Rgba8Unorm],
-        dimension: wgpu::TextureDimension::D2,
-        sample_count: 1,
-        mip_level_count: 1,
-    });
-    let size = texture.size();
-    let format_block_size = texture.format().block_size(None).unwrap();
-    let mut bytes_per_row = size.width * format_block_size;
-    if bytes_per_row % 256 != 0 {
-        bytes_per_row = bytes_per_row + (256 - (bytes_per_row % 256));
+    loop {
+        match in_flight.join(parameters) {
+            Joined::Follower(receiver) => {
+                //Canceled if the leader went away before finishing, then it's rendered again
+                if let Ok(result) = receiver.await {
+                    log::debug!(
+                        target: grimoire::LOGGING_TARGET,
+                        "Returning the result of an identical request"
+                    );
+                    return result;
+                }
+            }
+            Joined::Leader(leader) => {
+                let result =
+                    render_image(backend, pipelines, parameters, disk_cache, progress).await;
+                //Dropping the leader makes anyone waiting render it themselves, instead of
+                //getting an error for something they didn't cancel
+                if progress.is_canceled() {
+                    return result;
+                }
+                //Cached before finishing, so that there's no gap where the image can't be found
+                if let Ok(data) = &result {
+                    let size = data.len() as u64;
+                    cache
+                        .lock()
+                        .unwrap()
+                        .insert(parameters.clone(), data.clone(), size);
+                }
+                leader.finish(&result);
+                return result;
+            }
+        }
     }
-    let buffer = gpu.device.create_buffer(&wgpu::BufferDescriptor {
-        label: None,
-        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
-        size: { u64::from(bytes_per_row * size.height) },
-        mapped_at_creation: false,
-    });
+}
 
-    //This one is constant, and I can test it my self, so no need to check
-    let buffer_size = wgpu::BufferSize::new((data.len() * 4) as wgpu::BufferAddress).unwrap();
-    //This one isn't constant so may fail, idk, better safe than sorry
-    let color_buffer_size = wgpu::BufferSize::new((colors.len() * 4) as wgpu::BufferAddress);
-    if color_buffer_size.is_none() {
-        log::error!(
-            target: grimoire::LOGGING_TARGET,
-            "Could not get color buffer size"
-        );
-        return HttpResponse::InternalServerError().into();
+fn image_response(parameters: &RenderParameters, data: Vec<u8>) -> HttpResponse {
+    let mut response = HttpResponse::Ok();
+    response
+        .content_type(parameters.format.mime())
+        //The format can depend on the accept header
+        .insert_header((header::VARY, "Accept"));
+    //The exact view, since the bounds that were asked for can be letterboxed
+    if let Some(bounds) = parameters.bounds() {
+        let names = [
+            "X-Bounds-X-Min",
+            "X-Bounds-X-Max",
+            "X-Bounds-Y-Min",
+            "X-Bounds-Y-Max",
+        ];
+        for (name, value) in names.into_iter().zip(bounds) {
+            response.insert_header((name, value.to_string()));
+        }
     }
+    response.streaming(async_iter(data))
+}
 
-    let mut encoder = gpu
-        .device
-        .create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
-    let mut staging_belt = gpu.staging_belt.lock().unwrap();
-
-    //Write data
-    staging_belt
-        .write_buffer(
-            &mut encoder,
-            &pipeline.info_buffer,
-            0,
-            buffer_size,
-            &gpu.device,
-        )
-        .copy_from_slice(bytemuck::cast_slice(&data));
-    staging_belt
-        .write_buffer(
-            &mut encoder,
-            &pipeline.storage_buffer,
-            0,
-            color_buffer_size.unwrap(),
-            &gpu.device,
-        )
-        .copy_from_slice(bytemuck::cast_slice(&colors));
-    let command_buffer =
-        generate_command_buffer(encoder, &texture, &buffer, pipeline, bytes_per_row);
-    staging_belt.finish();
-    gpu.queue.submit(Some(command_buffer));
-    staging_belt.recall();
-    drop(pipelines);
-    texture.destroy();
+///Renders and encodes the image, storing it in the disk cache if it's enabled
+async fn render_image(
+    backend: &Backend,
+    pipelines: &PipelineStore,
+    parameters: &RenderParameters,
+    disk_cache: Option<&DiskCache>,
+    progress: &Arc<RenderProgress>,
+) -> Result<Vec<u8>, ApiError> {
+    let start = Instant::now();
 
-    //Get the data from the gpu
-    let slice = buffer.slice(..);
-    slice.map_async(wgpu::MapMode::Read, |_| {});
-    gpu.device.poll(wgpu::Maintain::Wait);
+    let request = render_request(parameters)
+        .await
+        .map_err(ApiError::BadRequest)?;
 
-    let img = slice
-        .get_mapped_range()
-        .iter()
-        .copied()
-        .collect::<Vec<u8>>();
-    let byte_stream = export::arr_to_image(
-        &img,
-        bytes_per_row / 4,
-        width,
-        height,
-        image::ImageOutputFormat::Png,
-    );
+    let img = render_with_progress(backend, pipelines, &request, progress)
+        .await
+        .map_err(|e| {
+            if progress.is_canceled() {
+                return ApiError::Gone(e);
+            }
+            log::error!(target: grimoire::LOGGING_TARGET, "{e}");
+            ApiError::Internal(String::new())
+        })?;
+    //Encoding big images takes a while, so it shouldn't block the worker
+    let (width, height) = (request.width, request.height);
+    let (format, quality) = (parameters.format, parameters.quality);
+    let byte_stream = web::block(move || export::encode(img, width, height, format, quality))
+        .await
+        .map_err(|e| e.to_string())
+        .and_then(|result| result)
+        .map_err(|e| {
+            log::error!(
+                target: grimoire::LOGGING_TARGET,
+                "Could not export image {e}"
+            );
+            ApiError::Internal("Unable to export image".to_string())
+        })?;
 
-    if byte_stream.is_err() {
-        log::error!(
-            target: grimoire::LOGGING_TARGET,
-            "Could not export image {}",
-            byte_stream.err().unwrap()
-        );
-        return HttpResponse::InternalServerError().body("Unable to export image");
+    if let Some(disk_cache) = disk_cache {
+        let result = disk_cache
+            .insert(parameters, byte_stream.clone(), start.elapsed())
+            .await;
+        if let Err(e) = result {
+            log::warn!(
+                target: grimoire::LOGGING_TARGET,
+                "Could not write to the disk cache {e}"
+            );
+        }
     }
-    let mut cache = cache.lock().unwrap();
-
-    let byte_stream = byte_stream.unwrap();
-    cache.push((identifier, byte_stream.clone()));
-
-    let stream = async_iter(byte_stream);
-
-    HttpResponse::Ok().streaming(stream)
+    Ok(byte_stream)
 }
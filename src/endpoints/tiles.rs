@@ -0,0 +1,45 @@
+use actix_web::{
+    web::{self, Data},
+    Responder, ResponseError,
+};
+
+use super::rendering::render_cached;
+use crate::{
+    structs::{
+        rendering::Backend,
+        requests::{Cache, InFlightRenders, RequestBody, SimplifiedFractals, TileBody},
+    },
+    utils::disk_cache::DiskCache,
+    PipelineStore,
+};
+
+///Map tiles in the usual z/x/y layout, for viewers like Leaflet or OpenLayers. Tiles are cached
+///like any other render
+#[allow(clippy::too_many_arguments)]
+#[actix_web::get("/tiles/{fractal}/{z}/{x}/{y}.png")]
+async fn render_tile(
+    backend: Data<Backend>,
+    pipelines: Data<PipelineStore>,
+    path: web::Path<(SimplifiedFractals, u32, u32, u32)>,
+    query: web::Query<RequestBody>,
+    tile: web::Query<TileBody>,
+    cache: Data<Cache>,
+    disk_cache: Option<Data<DiskCache>>,
+    in_flight: Data<InFlightRenders>,
+) -> impl Responder {
+    let (fractal, level, x, y) = path.into_inner();
+    let parameters = match tile.to_parameters(&query, fractal, [level, x, y]) {
+        Ok(parameters) => parameters,
+        Err(e) => return e.error_response(),
+    };
+
+    render_cached(
+        &backend,
+        &pipelines,
+        &cache,
+        disk_cache.as_ref().map(|d| d.get_ref()),
+        &in_flight,
+        &parameters,
+    )
+    .await
+}
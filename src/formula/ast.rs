@@ -0,0 +1,166 @@
+///A range of bytes in the source formula, used for error reporting
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub const fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+
+    ///Creates a span covering both `self` and `other`
+    pub const fn to(self, other: Self) -> Self {
+        Self {
+            start: self.start,
+            end: other.end,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Pow,
+}
+
+///Untyped expression, as it comes out of the parser
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExprKind {
+    Number(f64),
+    Identifier(String),
+    Negate(Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    Call(String, Vec<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
+///Types of values the formula language operates on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Type {
+    ///`f32` in the shader
+    Real,
+    ///`vec2<f32>` in the shader
+    Complex,
+}
+
+///Built in values that can be referenced in a formula
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Variable {
+    ///The current value of the iteration
+    Z,
+    ///The coordinate of the pixel
+    C,
+    ///The imaginary unit
+    I,
+    Pi,
+    E,
+}
+
+impl Variable {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "z" => Some(Self::Z),
+            "c" => Some(Self::C),
+            "i" => Some(Self::I),
+            "pi" => Some(Self::Pi),
+            "e" => Some(Self::E),
+            _ => None,
+        }
+    }
+
+    pub const fn ty(self) -> Type {
+        match self {
+            Self::Z | Self::C | Self::I => Type::Complex,
+            Self::Pi | Self::E => Type::Real,
+        }
+    }
+}
+
+///Functions available in the formula language, all of them take a single argument
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Function {
+    Sin,
+    Cos,
+    Tan,
+    Sinh,
+    Cosh,
+    Tanh,
+    Exp,
+    Log,
+    Sqrt,
+    ///Complex conjugate
+    Conj,
+    ///Absolute value, component wise for complex numbers (like in the burning ship)
+    Abs,
+    ///Real part
+    Re,
+    ///Imaginary part
+    Im,
+    ///Argument (angle) of a complex number
+    Arg,
+    ///Modulus of a complex number, can also be written as |z|
+    Mod,
+    ///Squared modulus of a complex number
+    Norm,
+}
+
+impl Function {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "sin" => Some(Self::Sin),
+            "cos" => Some(Self::Cos),
+            "tan" => Some(Self::Tan),
+            "sinh" => Some(Self::Sinh),
+            "cosh" => Some(Self::Cosh),
+            "tanh" => Some(Self::Tanh),
+            "exp" => Some(Self::Exp),
+            "log" | "ln" => Some(Self::Log),
+            "sqrt" => Some(Self::Sqrt),
+            "conj" => Some(Self::Conj),
+            "abs" => Some(Self::Abs),
+            "re" => Some(Self::Re),
+            "im" => Some(Self::Im),
+            "arg" => Some(Self::Arg),
+            "mod" => Some(Self::Mod),
+            "norm" => Some(Self::Norm),
+            _ => None,
+        }
+    }
+
+    ///Returns the type of the result of the function given the type of its argument
+    pub const fn result_type(self, argument: Type) -> Type {
+        match self {
+            Self::Abs => argument,
+            Self::Re | Self::Im | Self::Arg | Self::Mod | Self::Norm => Type::Real,
+            _ => Type::Complex,
+        }
+    }
+}
+
+///Type checked expression, ready for code generation
+#[derive(Debug, Clone, PartialEq)]
+pub enum TypedKind {
+    Number(f32),
+    Variable(Variable),
+    Negate(Box<TypedExpr>),
+    Binary(BinaryOp, Box<TypedExpr>, Box<TypedExpr>),
+    ///Raising to a constant integer power, which is a lot cheaper than the general case
+    PowInt(Box<TypedExpr>, i32),
+    Call(Function, Box<TypedExpr>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypedExpr {
+    pub kind: TypedKind,
+    pub ty: Type,
+}
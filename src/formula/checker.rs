@@ -0,0 +1,142 @@
+#![allow(clippy::cast_possible_truncation)]
+use super::{
+    ast::{BinaryOp, Expr, ExprKind, Function, Type, TypedExpr, TypedKind, Variable},
+    FormulaError,
+};
+use crate::grimoire;
+
+///Resolves names, checks function arity and infers the type of every node
+pub fn check(expr: &Expr) -> Result<TypedExpr, FormulaError> {
+    match &expr.kind {
+        ExprKind::Number(value) => {
+            let value = *value as f32;
+            if !value.is_finite() {
+                return Err(FormulaError::new(
+                    expr.span,
+                    "number is too large".to_string(),
+                ));
+            }
+            Ok(TypedExpr {
+                kind: TypedKind::Number(value),
+                ty: Type::Real,
+            })
+        }
+        ExprKind::Identifier(name) => {
+            let variable = Variable::from_name(name).ok_or_else(|| {
+                let message = if Function::from_name(name).is_some() {
+                    format!("'{name}' is a function and must be called with an argument")
+                } else {
+                    format!("unknown variable '{name}', expected one of z, c, i, pi, e")
+                };
+                FormulaError::new(expr.span, message)
+            })?;
+            Ok(TypedExpr {
+                kind: TypedKind::Variable(variable),
+                ty: variable.ty(),
+            })
+        }
+        ExprKind::Negate(operand) => {
+            let operand = check(operand)?;
+            Ok(TypedExpr {
+                ty: operand.ty,
+                kind: TypedKind::Negate(Box::new(operand)),
+            })
+        }
+        ExprKind::Binary(BinaryOp::Pow, base, exponent) => {
+            let typed_base = check(base)?;
+            if let Some(power) = integer_exponent(exponent) {
+                return Ok(TypedExpr {
+                    ty: typed_base.ty,
+                    kind: TypedKind::PowInt(Box::new(typed_base), power),
+                });
+            }
+            let typed_exponent = check(exponent)?;
+            //A real number to a real power can still be complex, so the general case is always
+            //complex
+            Ok(TypedExpr {
+                kind: TypedKind::Binary(
+                    BinaryOp::Pow,
+                    Box::new(typed_base),
+                    Box::new(typed_exponent),
+                ),
+                ty: Type::Complex,
+            })
+        }
+        ExprKind::Binary(op, lhs, rhs) => {
+            let lhs = check(lhs)?;
+            let rhs = check(rhs)?;
+            let ty = if lhs.ty == Type::Real && rhs.ty == Type::Real {
+                Type::Real
+            } else {
+                Type::Complex
+            };
+            Ok(TypedExpr {
+                kind: TypedKind::Binary(*op, Box::new(lhs), Box::new(rhs)),
+                ty,
+            })
+        }
+        ExprKind::Call(name, arguments) => {
+            let function = Function::from_name(name).ok_or_else(|| {
+                FormulaError::new(expr.span, format!("unknown function '{name}'"))
+            })?;
+            if arguments.len() != 1 {
+                return Err(FormulaError::new(
+                    expr.span,
+                    format!(
+                        "function '{name}' takes 1 argument, but {} were given",
+                        arguments.len()
+                    ),
+                ));
+            }
+            let argument = check(&arguments[0])?;
+            Ok(TypedExpr {
+                ty: function.result_type(argument.ty),
+                kind: TypedKind::Call(function, Box::new(argument)),
+            })
+        }
+    }
+}
+
+///Returns the exponent if it's a small integer constant, like in z^2 or z^-1
+fn integer_exponent(exponent: &Expr) -> Option<i32> {
+    let (value, sign) = match &exponent.kind {
+        ExprKind::Number(value) => (*value, 1.0),
+        ExprKind::Negate(inner) => match inner.kind {
+            ExprKind::Number(value) => (value, -1.0),
+            _ => return None,
+        },
+        _ => return None,
+    };
+    let value = value * sign;
+    if value.fract() == 0.0 && value.abs() <= f64::from(grimoire::MAX_INTEGER_POWER) {
+        Some(value as i32)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+fn check_source(source: &str) -> Result<TypedExpr, FormulaError> {
+    let tokens = super::lexer::tokenize(source)?;
+    check(&super::parser::Parser::new(tokens).parse()?)
+}
+
+#[test]
+fn test_check_types() {
+    assert_eq!(check_source("z^3 + c*sin(z)").unwrap().ty, Type::Complex);
+    assert_eq!(check_source("re(z)^2 - pi").unwrap().ty, Type::Real);
+    assert_eq!(check_source("abs(z)").unwrap().ty, Type::Complex);
+    assert_eq!(check_source("|z|").unwrap().ty, Type::Real);
+}
+
+#[test]
+fn test_check_errors() {
+    let error = check_source("z^2 + w").unwrap_err();
+    assert_eq!(error.span.start, 6);
+
+    let error = check_source("z + sin(z, c)").unwrap_err();
+    assert_eq!(error.span.start, 4);
+
+    assert!(check_source("foo(z) + c").is_err());
+    assert!(check_source("sin + c").is_err());
+}
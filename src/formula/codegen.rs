@@ -0,0 +1,110 @@
+use super::ast::{BinaryOp, Function, Type, TypedExpr, TypedKind, Variable};
+use crate::grimoire;
+
+///Generates the source of the `fractal_func` shader for a type checked formula,
+///meant to be appended after `base_fragment.wgsl`
+pub fn generate_shader(expr: &TypedExpr) -> String {
+    let mut source = format!("const max_dot = {:?};\n\n", grimoire::CUSTOM_MAX_DOT);
+    source.push_str(include_str!("../shaders/complex.wgsl"));
+    source.push_str(&format!(
+        "\nfn fractal_func(z: vec2<f32>, c: vec2<f32>) -> vec2<f32> {{\n    return {};\n}}\n",
+        as_complex(expr)
+    ));
+    source
+}
+
+///Generates the expression, converting it into a complex number if necessary
+fn as_complex(expr: &TypedExpr) -> String {
+    promote(&generate(expr), expr.ty)
+}
+
+fn promote(code: &str, ty: Type) -> String {
+    match ty {
+        Type::Real => format!("vec2<f32>({code}, 0.0)"),
+        Type::Complex => code.to_owned(),
+    }
+}
+
+fn generate(expr: &TypedExpr) -> String {
+    match &expr.kind {
+        //Debug formatting always includes either a decimal point or an exponent,
+        //so it's a valid float literal
+        TypedKind::Number(value) => format!("{value:?}"),
+        TypedKind::Variable(variable) => match variable {
+            Variable::Z => "z".to_string(),
+            Variable::C => "c".to_string(),
+            Variable::I => "vec2<f32>(0.0, 1.0)".to_string(),
+            Variable::Pi => format!("{:?}", std::f32::consts::PI),
+            Variable::E => format!("{:?}", std::f32::consts::E),
+        },
+        TypedKind::Negate(operand) => format!("(-{})", generate(operand)),
+        TypedKind::PowInt(base, power) => match (base.ty, power) {
+            (_, 1) => generate(base),
+            (Type::Complex, 2) => format!("complex_square({})", generate(base)),
+            (Type::Complex, _) => format!("complex_powi({}, {power})", generate(base)),
+            (Type::Real, _) => format!("real_powi({}, {power})", generate(base)),
+        },
+        TypedKind::Binary(op, lhs, rhs) => binary(*op, lhs, rhs),
+        TypedKind::Call(function, argument) => call(*function, argument),
+    }
+}
+
+fn binary(op: BinaryOp, lhs: &TypedExpr, rhs: &TypedExpr) -> String {
+    let types = (lhs.ty, rhs.ty);
+    match op {
+        BinaryOp::Add | BinaryOp::Sub => {
+            let symbol = if op == BinaryOp::Add { '+' } else { '-' };
+            if types == (Type::Real, Type::Real) {
+                format!("({} {symbol} {})", generate(lhs), generate(rhs))
+            } else {
+                format!("({} {symbol} {})", as_complex(lhs), as_complex(rhs))
+            }
+        }
+        //Scaling a vector by a scalar does the right thing for complex numbers
+        BinaryOp::Mul => match types {
+            (Type::Complex, Type::Complex) => {
+                format!("complex_mul({}, {})", generate(lhs), generate(rhs))
+            }
+            _ => format!("({} * {})", generate(lhs), generate(rhs)),
+        },
+        BinaryOp::Div => match types {
+            (_, Type::Complex) => {
+                format!("complex_div({}, {})", as_complex(lhs), generate(rhs))
+            }
+            _ => format!("({} / {})", generate(lhs), generate(rhs)),
+        },
+        BinaryOp::Pow => format!("complex_pow({}, {})", as_complex(lhs), as_complex(rhs)),
+    }
+}
+
+fn call(function: Function, argument: &TypedExpr) -> String {
+    let complex = as_complex(argument);
+    match function {
+        Function::Sin => format!("complex_sin({complex})"),
+        Function::Cos => format!("complex_cos({complex})"),
+        Function::Tan => format!("complex_tan({complex})"),
+        Function::Sinh => format!("complex_sinh({complex})"),
+        Function::Cosh => format!("complex_cosh({complex})"),
+        Function::Tanh => format!("complex_tanh({complex})"),
+        Function::Exp => format!("complex_exp({complex})"),
+        Function::Log => format!("complex_log({complex})"),
+        Function::Sqrt => format!("complex_sqrt({complex})"),
+        Function::Conj => format!("({complex} * vec2<f32>(1.0, -1.0))"),
+        Function::Abs => format!("abs({})", generate(argument)),
+        Function::Re => format!("{complex}.x"),
+        Function::Im => format!("{complex}.y"),
+        Function::Arg => format!("atan2({complex}.y, {complex}.x)"),
+        Function::Mod => format!("length({complex})"),
+        Function::Norm => format!("dot({complex}, {complex})"),
+    }
+}
+
+#[test]
+fn test_generate_shader() {
+    let shader = super::compile("z^3 + c*sin(z)").unwrap();
+    assert!(shader.contains("return (complex_powi(z, 3) + complex_mul(c, complex_sin(z)));"));
+
+    let shader = super::compile("2*z^2 - 1/c").unwrap();
+    assert!(shader
+        .contains("return ((2.0 * complex_square(z)) - complex_div(vec2<f32>(1.0, 0.0), c));"));
+}
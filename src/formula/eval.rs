@@ -0,0 +1,104 @@
+use super::ast::{BinaryOp, Function, TypedExpr, TypedKind, Variable};
+use crate::utils::complex::Complex;
+
+///Result of evaluating an expression, mirrors the types used in the generated shader
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Value {
+    Real(f32),
+    Complex(Complex),
+}
+
+impl Value {
+    const fn complex(self) -> Complex {
+        match self {
+            Self::Real(value) => Complex::new(value, 0.0),
+            Self::Complex(value) => value,
+        }
+    }
+}
+
+///Evaluates a type checked formula on the cpu, the same way the generated shader would
+pub fn evaluate(expr: &TypedExpr, z: Complex, c: Complex) -> Complex {
+    value(expr, z, c).complex()
+}
+
+fn value(expr: &TypedExpr, z: Complex, c: Complex) -> Value {
+    match &expr.kind {
+        TypedKind::Number(number) => Value::Real(*number),
+        TypedKind::Variable(variable) => match variable {
+            Variable::Z => Value::Complex(z),
+            Variable::C => Value::Complex(c),
+            Variable::I => Value::Complex(Complex::new(0.0, 1.0)),
+            Variable::Pi => Value::Real(std::f32::consts::PI),
+            Variable::E => Value::Real(std::f32::consts::E),
+        },
+        TypedKind::Negate(operand) => match value(operand, z, c) {
+            Value::Real(x) => Value::Real(-x),
+            Value::Complex(x) => Value::Complex(-x),
+        },
+        TypedKind::PowInt(base, power) => match value(base, z, c) {
+            Value::Real(x) => Value::Real(x.powi(*power)),
+            Value::Complex(x) => Value::Complex(x.powi(*power)),
+        },
+        TypedKind::Binary(op, lhs, rhs) => binary(*op, value(lhs, z, c), value(rhs, z, c)),
+        TypedKind::Call(function, argument) => call(*function, value(argument, z, c)),
+    }
+}
+
+fn binary(op: BinaryOp, lhs: Value, rhs: Value) -> Value {
+    if let (Value::Real(a), Value::Real(b)) = (lhs, rhs) {
+        match op {
+            BinaryOp::Add => return Value::Real(a + b),
+            BinaryOp::Sub => return Value::Real(a - b),
+            BinaryOp::Mul => return Value::Real(a * b),
+            BinaryOp::Div => return Value::Real(a / b),
+            BinaryOp::Pow => {}
+        }
+    }
+    let result = match (op, lhs, rhs) {
+        (BinaryOp::Mul, Value::Real(a), Value::Complex(b))
+        | (BinaryOp::Mul, Value::Complex(b), Value::Real(a)) => b * a,
+        (BinaryOp::Div, Value::Complex(a), Value::Real(b)) => a / b,
+        (BinaryOp::Add, _, _) => lhs.complex() + rhs.complex(),
+        (BinaryOp::Sub, _, _) => lhs.complex() - rhs.complex(),
+        (BinaryOp::Mul, _, _) => lhs.complex() * rhs.complex(),
+        (BinaryOp::Div, _, _) => lhs.complex() / rhs.complex(),
+        (BinaryOp::Pow, _, _) => lhs.complex().pow(rhs.complex()),
+    };
+    Value::Complex(result)
+}
+
+fn call(function: Function, argument: Value) -> Value {
+    if let (Function::Abs, Value::Real(x)) = (function, argument) {
+        return Value::Real(x.abs());
+    }
+    let z = argument.complex();
+    match function {
+        Function::Sin => Value::Complex(z.sin()),
+        Function::Cos => Value::Complex(z.cos()),
+        Function::Tan => Value::Complex(z.tan()),
+        Function::Sinh => Value::Complex(z.sinh()),
+        Function::Cosh => Value::Complex(z.cosh()),
+        Function::Tanh => Value::Complex(z.tanh()),
+        Function::Exp => Value::Complex(z.exp()),
+        Function::Log => Value::Complex(z.ln()),
+        Function::Sqrt => Value::Complex(z.sqrt()),
+        Function::Conj => Value::Complex(z.conj()),
+        Function::Abs => Value::Complex(z.abs()),
+        Function::Re => Value::Real(z.re),
+        Function::Im => Value::Real(z.im),
+        Function::Arg => Value::Real(z.arg()),
+        Function::Mod => Value::Real(z.length()),
+        Function::Norm => Value::Real(z.norm()),
+    }
+}
+
+#[test]
+fn test_evaluate() {
+    let formula = super::parse("z^2 + c*re(z) - 1/i").unwrap();
+    let z = Complex::new(1.0, 2.0);
+    let c = Complex::new(0.5, 0.0);
+
+    //(1 + 2i)^2 + 0.5 * 1 + i = -3 + 4i + 0.5 + i
+    assert_eq!(evaluate(&formula, z, c), Complex::new(-2.5, 5.0));
+}
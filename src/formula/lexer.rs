@@ -0,0 +1,149 @@
+use super::{ast::Span, FormulaError};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TokenKind {
+    Number(f64),
+    Identifier(String),
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    Caret,
+    LeftParen,
+    RightParen,
+    Comma,
+    Bar,
+    End,
+}
+
+impl std::fmt::Display for TokenKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Number(n) => write!(f, "number {n}"),
+            Self::Identifier(name) => write!(f, "'{name}'"),
+            Self::Plus => write!(f, "'+'"),
+            Self::Minus => write!(f, "'-'"),
+            Self::Star => write!(f, "'*'"),
+            Self::Slash => write!(f, "'/'"),
+            Self::Caret => write!(f, "'^'"),
+            Self::LeftParen => write!(f, "'('"),
+            Self::RightParen => write!(f, "')'"),
+            Self::Comma => write!(f, "','"),
+            Self::Bar => write!(f, "'|'"),
+            Self::End => write!(f, "end of formula"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub span: Span,
+}
+
+///Splits the formula into tokens, the last token is always `TokenKind::End`
+pub fn tokenize(source: &str) -> Result<Vec<Token>, FormulaError> {
+    let bytes = source.as_bytes();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let start = i;
+        let kind = match bytes[i] {
+            b' ' | b'\t' | b'\n' | b'\r' => {
+                i += 1;
+                continue;
+            }
+            b'+' => TokenKind::Plus,
+            b'-' => TokenKind::Minus,
+            b'*' => TokenKind::Star,
+            b'/' => TokenKind::Slash,
+            b'^' => TokenKind::Caret,
+            b'(' => TokenKind::LeftParen,
+            b')' => TokenKind::RightParen,
+            b',' => TokenKind::Comma,
+            b'|' => TokenKind::Bar,
+            b'0'..=b'9' | b'.' => {
+                while i < bytes.len() && (bytes[i].is_ascii_digit() || bytes[i] == b'.') {
+                    i += 1;
+                }
+                //Exponent, only if it's actually followed by digits, so that `2e` is still an error
+                if i < bytes.len() && (bytes[i] == b'e' || bytes[i] == b'E') {
+                    let mut j = i + 1;
+                    if j < bytes.len() && (bytes[j] == b'+' || bytes[j] == b'-') {
+                        j += 1;
+                    }
+                    if j < bytes.len() && bytes[j].is_ascii_digit() {
+                        i = j;
+                        while i < bytes.len() && bytes[i].is_ascii_digit() {
+                            i += 1;
+                        }
+                    }
+                }
+                let text = &source[start..i];
+                let value = text.parse::<f64>().map_err(|_| {
+                    FormulaError::new(Span::new(start, i), format!("invalid number '{text}'"))
+                })?;
+                tokens.push(Token {
+                    kind: TokenKind::Number(value),
+                    span: Span::new(start, i),
+                });
+                continue;
+            }
+            b'a'..=b'z' | b'A'..=b'Z' | b'_' => {
+                while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'_') {
+                    i += 1;
+                }
+                tokens.push(Token {
+                    kind: TokenKind::Identifier(source[start..i].to_ascii_lowercase()),
+                    span: Span::new(start, i),
+                });
+                continue;
+            }
+            _ => {
+                let character = source[start..].chars().next().unwrap_or_default();
+                return Err(FormulaError::new(
+                    Span::new(start, start + character.len_utf8()),
+                    format!("unexpected character '{character}'"),
+                ));
+            }
+        };
+        i += 1;
+        tokens.push(Token {
+            kind,
+            span: Span::new(start, i),
+        });
+    }
+
+    tokens.push(Token {
+        kind: TokenKind::End,
+        span: Span::new(bytes.len(), bytes.len()),
+    });
+    Ok(tokens)
+}
+
+#[test]
+fn test_tokenize() {
+    let tokens = tokenize("z^2 + 1.5e-3*c").unwrap();
+    let kinds = tokens.into_iter().map(|t| t.kind).collect::<Vec<_>>();
+
+    assert_eq!(
+        kinds,
+        vec![
+            TokenKind::Identifier("z".to_string()),
+            TokenKind::Caret,
+            TokenKind::Number(2.0),
+            TokenKind::Plus,
+            TokenKind::Number(1.5e-3),
+            TokenKind::Star,
+            TokenKind::Identifier("c".to_string()),
+            TokenKind::End,
+        ]
+    );
+}
+
+#[test]
+fn test_tokenize_invalid_character() {
+    let error = tokenize("z^2 # c").unwrap_err();
+    assert_eq!(error.span, Span::new(4, 5));
+}
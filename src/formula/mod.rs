@@ -0,0 +1,75 @@
+use ast::Span;
+
+///Syntax tree and type definitions
+pub mod ast;
+///Type checking and name resolution
+mod checker;
+///WGSL generation
+mod codegen;
+///Evaluating formulas on the cpu
+pub mod eval;
+///Splitting the formula into tokens
+mod lexer;
+///Building the syntax tree out of tokens
+mod parser;
+
+///An error in a formula, along with where it happened
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FormulaError {
+    pub span: Span,
+    pub message: String,
+}
+
+impl FormulaError {
+    pub const fn new(span: Span, message: String) -> Self {
+        Self { span, message }
+    }
+
+    ///Formats the error along with the formula and a marker under the offending part
+    pub fn highlight(&self, formula: &str) -> String {
+        let width = (self.span.end - self.span.start).max(1);
+        format!(
+            "{self}\n{formula}\n{}{}",
+            " ".repeat(self.span.start),
+            "^".repeat(width)
+        )
+    }
+}
+
+impl std::fmt::Display for FormulaError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} at column {}", self.message, self.span.start + 1)
+    }
+}
+
+///Parses and type checks a formula
+pub fn parse(formula: &str) -> Result<ast::TypedExpr, FormulaError> {
+    if formula.len() > crate::grimoire::MAX_FORMULA_LENGTH {
+        return Err(FormulaError::new(
+            Span::new(crate::grimoire::MAX_FORMULA_LENGTH, formula.len()),
+            format!(
+                "formula is longer than {} characters",
+                crate::grimoire::MAX_FORMULA_LENGTH
+            ),
+        ));
+    }
+    let tokens = lexer::tokenize(formula)?;
+    let expr = parser::Parser::new(tokens).parse()?;
+    checker::check(&expr)
+}
+
+///Compiles a formula into WGSL source defining `fractal_func` and `max_dot`
+pub fn compile(formula: &str) -> Result<String, FormulaError> {
+    Ok(codegen::generate_shader(&parse(formula)?))
+}
+
+#[test]
+fn test_highlight() {
+    let formula = "z^2 + w";
+    let error = parse(formula).unwrap_err();
+
+    assert_eq!(
+        error.highlight(formula),
+        "unknown variable 'w', expected one of z, c, i, pi, e at column 7\nz^2 + w\n      ^"
+    );
+}
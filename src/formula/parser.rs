@@ -0,0 +1,240 @@
+use super::{
+    ast::{BinaryOp, Expr, ExprKind},
+    lexer::{Token, TokenKind},
+    FormulaError,
+};
+use crate::grimoire;
+
+///A simple recursive descent parser
+///
+///Grammar, from lowest to highest precedence:
+///```text
+///expr    = term (('+' | '-') term)*
+///term    = unary (('*' | '/') unary)*
+///unary   = '-' unary | power
+///power   = primary ('^' unary)?
+///primary = number | identifier | identifier '(' expr (',' expr)* ')' | '(' expr ')' | '|' expr '|'
+///```
+pub struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+    depth: usize,
+}
+
+impl Parser {
+    pub const fn new(tokens: Vec<Token>) -> Self {
+        Self {
+            tokens,
+            position: 0,
+            depth: 0,
+        }
+    }
+
+    ///Parses the whole formula, failing if there's anything left after the expression
+    pub fn parse(mut self) -> Result<Expr, FormulaError> {
+        let expr = self.expr()?;
+        let token = self.peek();
+        if token.kind != TokenKind::End {
+            return Err(FormulaError::new(
+                token.span,
+                format!("unexpected {}, expected an operator", token.kind),
+            ));
+        }
+        Ok(expr)
+    }
+
+    fn peek(&self) -> &Token {
+        &self.tokens[self.position]
+    }
+
+    fn next(&mut self) -> Token {
+        let token = self.tokens[self.position].clone();
+        //Never go past the end token
+        if token.kind != TokenKind::End {
+            self.position += 1;
+        }
+        token
+    }
+
+    fn expect(&mut self, kind: &TokenKind) -> Result<Token, FormulaError> {
+        let token = self.next();
+        if &token.kind == kind {
+            Ok(token)
+        } else {
+            Err(FormulaError::new(
+                token.span,
+                format!("expected {kind}, found {}", token.kind),
+            ))
+        }
+    }
+
+    fn binary(op: BinaryOp, lhs: Expr, rhs: Expr) -> Expr {
+        Expr {
+            span: lhs.span.to(rhs.span),
+            kind: ExprKind::Binary(op, Box::new(lhs), Box::new(rhs)),
+        }
+    }
+
+    fn expr(&mut self) -> Result<Expr, FormulaError> {
+        //Every level of nesting goes through here, so this is enough to keep the stack in check
+        self.depth += 1;
+        if self.depth > grimoire::MAX_FORMULA_DEPTH {
+            return Err(FormulaError::new(
+                self.peek().span,
+                "formula is nested too deeply".to_string(),
+            ));
+        }
+
+        let mut lhs = self.term()?;
+        loop {
+            let op = match self.peek().kind {
+                TokenKind::Plus => BinaryOp::Add,
+                TokenKind::Minus => BinaryOp::Sub,
+                _ => break,
+            };
+            self.next();
+            let rhs = self.term()?;
+            lhs = Self::binary(op, lhs, rhs);
+        }
+
+        self.depth -= 1;
+        Ok(lhs)
+    }
+
+    fn term(&mut self) -> Result<Expr, FormulaError> {
+        let mut lhs = self.unary()?;
+        loop {
+            let op = match self.peek().kind {
+                TokenKind::Star => BinaryOp::Mul,
+                TokenKind::Slash => BinaryOp::Div,
+                _ => break,
+            };
+            self.next();
+            let rhs = self.unary()?;
+            lhs = Self::binary(op, lhs, rhs);
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Expr, FormulaError> {
+        if self.peek().kind == TokenKind::Minus {
+            let minus = self.next();
+            self.depth += 1;
+            if self.depth > grimoire::MAX_FORMULA_DEPTH {
+                return Err(FormulaError::new(
+                    minus.span,
+                    "formula is nested too deeply".to_string(),
+                ));
+            }
+            let operand = self.unary()?;
+            self.depth -= 1;
+            return Ok(Expr {
+                span: minus.span.to(operand.span),
+                kind: ExprKind::Negate(Box::new(operand)),
+            });
+        }
+        self.power()
+    }
+
+    fn power(&mut self) -> Result<Expr, FormulaError> {
+        let base = self.primary()?;
+        if self.peek().kind == TokenKind::Caret {
+            let caret = self.next();
+            self.depth += 1;
+            if self.depth > grimoire::MAX_FORMULA_DEPTH {
+                return Err(FormulaError::new(
+                    caret.span,
+                    "formula is nested too deeply".to_string(),
+                ));
+            }
+            //Right associative, and binds tighter than unary minus on the left, so -z^2 = -(z^2)
+            let exponent = self.unary()?;
+            self.depth -= 1;
+            return Ok(Self::binary(BinaryOp::Pow, base, exponent));
+        }
+        Ok(base)
+    }
+
+    fn primary(&mut self) -> Result<Expr, FormulaError> {
+        let token = self.next();
+        match token.kind {
+            TokenKind::Number(value) => Ok(Expr {
+                kind: ExprKind::Number(value),
+                span: token.span,
+            }),
+            TokenKind::Identifier(name) => {
+                if self.peek().kind != TokenKind::LeftParen {
+                    return Ok(Expr {
+                        kind: ExprKind::Identifier(name),
+                        span: token.span,
+                    });
+                }
+                self.next();
+                let mut arguments = vec![self.expr()?];
+                while self.peek().kind == TokenKind::Comma {
+                    self.next();
+                    arguments.push(self.expr()?);
+                }
+                let close = self.expect(&TokenKind::RightParen)?;
+                Ok(Expr {
+                    kind: ExprKind::Call(name, arguments),
+                    span: token.span.to(close.span),
+                })
+            }
+            TokenKind::LeftParen => {
+                let inner = self.expr()?;
+                let close = self.expect(&TokenKind::RightParen)?;
+                Ok(Expr {
+                    kind: inner.kind,
+                    span: token.span.to(close.span),
+                })
+            }
+            TokenKind::Bar => {
+                let inner = self.expr()?;
+                let close = self.expect(&TokenKind::Bar)?;
+                Ok(Expr {
+                    kind: ExprKind::Call("mod".to_string(), vec![inner]),
+                    span: token.span.to(close.span),
+                })
+            }
+            kind => Err(FormulaError::new(
+                token.span,
+                format!("unexpected {kind}, expected a value"),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+fn parse(source: &str) -> Result<Expr, FormulaError> {
+    Parser::new(super::lexer::tokenize(source)?).parse()
+}
+
+#[test]
+fn test_parse_precedence() {
+    let expr = parse("-z^2 + c*2").unwrap();
+
+    let ExprKind::Binary(BinaryOp::Add, lhs, rhs) = expr.kind else {
+        panic!("Expected an addition, got {expr:?}");
+    };
+    assert!(matches!(lhs.kind, ExprKind::Negate(_)));
+    assert!(matches!(rhs.kind, ExprKind::Binary(BinaryOp::Mul, _, _)));
+}
+
+#[test]
+fn test_parse_error_position() {
+    let error = parse("z^2 + (c").unwrap_err();
+    assert_eq!(error.span, super::ast::Span::new(8, 8));
+
+    let error = parse("z^2 c").unwrap_err();
+    assert_eq!(error.span, super::ast::Span::new(4, 5));
+}
+
+#[test]
+fn test_parse_depth() {
+    let depth = crate::grimoire::MAX_FORMULA_DEPTH;
+    //The whole formula is the first level
+    assert!(parse(&format!("z{}", "^z".repeat(depth - 1))).is_ok());
+    let error = parse(&format!("z{}", "^z".repeat(depth))).unwrap_err();
+    assert_eq!(error.message, "formula is nested too deeply");
+}
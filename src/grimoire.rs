@@ -6,16 +6,121 @@ pub const DEFUALT_MAX_ITER: u32 = 1000;
 pub const DEFAULT_NUM_COLORS: u32 = 200;
 pub const DEFAULT_POSITION: [f32; 2] = [-0.75, 0.0];
 pub const DEFAULT_ZOOM: f32 = 1.0;
+///Jpeg quality, if `quality` isn't set
+pub const DEFAULT_JPEG_QUALITY: u8 = 90;
+///Default constant used for julia sets
+pub const DEFAULT_JULIA: [f32; 2] = [-0.8, 0.156];
+///Julia sets are centered around 0, unlike most of their parent sets
+pub const DEFAULT_JULIA_POSITION: [f32; 2] = [0.0, 0.0];
 
 pub const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
+///Texture format used for the raw escape time data
+pub const RAW_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba32Float;
 pub const CLEAR_COLOR: wgpu::Color = wgpu::Color {
     r: 0.0,
     g: 0.0,
     b: 0.0,
     a: 1.0,
 };
-pub const STAGING_BELT_SIZE: u64 = 2048;
+///Size of the render cache in bytes, if `CACHE_SIZE` isn't set
+pub const DEFAULT_CACHE_SIZE: u64 = 256 * 1024 * 1024;
+///Size of the disk cache in bytes, if `DISK_CACHE_SIZE` isn't set
+pub const DEFAULT_DISK_CACHE_SIZE: u64 = 1024 * 1024 * 1024;
+///Most colors a single request can use
 pub const MAX_COLORS: u64 = 1024;
+///Largest width and height of an image, pyramids go up to [`MAX_PYRAMID_SIDE`] instead
+pub const MAX_IMAGE_SIDE: u32 = 1 << 16;
+///Most pixels in an image that's rendered while the request waits, bigger ones have to be rendered
+///as a job
+pub const MAX_IMAGE_PIXELS: u64 = 1 << 26;
+///Most pixels in an image rendered as a job, big enough for posters since big images are rendered
+///in tiles
+pub const MAX_POSTER_PIXELS: u64 = 1 << 30;
+///Most pixels in raw data, which takes 16 bytes per pixel instead of 4
+pub const MAX_RAW_PIXELS: u64 = 1 << 24;
+pub const MAX_ITERATIONS: u32 = 1 << 20;
+///Most samples per pixel, the shader keeps them in the lowest byte of the flags
+pub const MAX_MSAA: u8 = 16;
+///Most iterations that a single color stretches over
+pub const MAX_NUM_COLORS: u32 = 1 << 20;
+///Largest side of a tile rendered on the gpu at once, bigger images are split into tiles so that
+///the memory used by the gpu stays bounded
+pub const MAX_TILE_SIZE: u32 = 4096;
+
+///Frames in an animation, if `frames` isn't set
+pub const DEFAULT_FRAMES: u32 = 60;
+pub const DEFAULT_FPS: u32 = 30;
+pub const MAX_FRAMES: u32 = 1000;
+///Gif delays are in hundredths of a second, so faster ones wouldn't play correctly
+pub const MAX_FPS: u32 = 50;
+///Most pixels in all the frames of an animation together, so that a single request can't keep
+///the renderer busy for too long
+pub const MAX_ANIMATION_PIXELS: u64 = 1 << 28;
+
+///Width and height of map tiles
+pub const TILE_SIZE: u32 = 256;
+///Square of the complex plane that's covered by the tile at zoom level 0, as center x, center y
+///and side length, if `root_x`, `root_y` and `root_size` aren't set
+pub const DEFAULT_TILE_ROOT: [f32; 3] = [-0.5, 0.0, 4.0];
+///Deepest tile zoom level, past it the pixels get too close together for f32 coordinates
+pub const MAX_TILE_LEVEL: u32 = 12;
+
+///Largest side of an image pyramid, past it the pixels get too close together for f32 coordinates
+pub const MAX_PYRAMID_SIDE: u32 = 1 << 20;
+///Most pixels in a deep zoom pyramid, since all of its tiles go into one archive
+pub const MAX_PYRAMID_PIXELS: u64 = 1 << 30;
+///Most pixels in a deep zoom pyramid that's rendered while the request waits, bigger ones have to
+///go through the job queue
+pub const MAX_DIRECT_PYRAMID_PIXELS: u64 = 1 << 24;
+///Most pixels in a single iiif image request
+pub const MAX_IIIF_AREA: u64 = 4096 * 4096;
+
+///Rows the gpu renders between progress updates, this also keeps single draws short
+pub const PROGRESS_BAND_HEIGHT: u32 = 256;
+///Time between progress events
+pub const PROGRESS_INTERVAL: std::time::Duration = std::time::Duration::from_millis(250);
+
+///Previews are sent at the size of the image divided by each of these, from the roughest to the
+///full size
+pub const PREVIEW_SCALES: [u32; 4] = [8, 4, 2, 1];
+///Messages waiting to be sent on a preview websocket, past it rendering waits for the client
+pub const PREVIEW_BUFFER: usize = 2;
+
+///Jobs that render at the same time, if `JOB_WORKERS` isn't set
+pub const DEFAULT_JOB_WORKERS: usize = 2;
+///Seconds that finished jobs and their results are kept, if `JOB_RETENTION` isn't set
+pub const DEFAULT_JOB_RETENTION: u64 = 60 * 60;
+///Bytes of finished job results that are kept, if `JOB_RESULTS_SIZE` isn't set
+pub const DEFAULT_JOB_RESULTS_SIZE: u64 = 512 * 1024 * 1024;
+///Most jobs that can be queued or running at once
+pub const MAX_PENDING_JOBS: usize = 256;
+
+///Most images in a single batch
+pub const MAX_BATCH_SIZE: usize = 256;
+///Most pixels of all the images of a batch together
+pub const MAX_BATCH_PIXELS: u64 = 1 << 28;
+
+///Maximum length of a custom formula
+pub const MAX_FORMULA_LENGTH: usize = 1024;
+///Maximum nesting depth of a custom formula, to avoid overflowing the stack while parsing
+pub const MAX_FORMULA_DEPTH: usize = 64;
+///Largest integer exponent that gets expanded into multiplications
+pub const MAX_INTEGER_POWER: u32 = 64;
+///Escape radius squared used by custom formulas
+pub const CUSTOM_MAX_DOT: f32 = 256.0;
+///Custom formula pipelines kept around, the least recently used ones get dropped after this
+pub const MAX_CUSTOM_PIPELINES: u64 = 64;
+
+///Maximum length of the reference orbit used for deep zoom, this is also the iteration limit in
+///deep zoom mode
+pub const MAX_ORBIT_LENGTH: u64 = 1 << 18;
+///Bits of precision used for the reference orbit on top of the ones needed for the zoom
+pub const DEEP_ZOOM_EXTRA_PRECISION: u32 = 96;
+///Largest power of 10 accepted in deep zoom coordinates, digits after the point included, keeps
+///the reference orbit reasonably fast
+pub const MAX_DECIMAL_EXPONENT: i64 = 1000;
+///Most significant digits accepted in deep zoom coordinates, more than the deepest zoom can use
+pub const MAX_DECIMAL_DIGITS: usize = 1100;
 
 ///Flags for changing how the fractal is rendered
 pub mod rendering_flags {
@@ -24,6 +129,12 @@ pub mod rendering_flags {
     pub const SMOOTH: u32 = 2 << 30;
     ///Renders a debug grid over the fractal, for easier definition of the position
     pub const DEBUG: u32 = 2 << 29;
+    ///Renders the julia set instead, using the pixel as the starting value and a fixed constant
+    pub const JULIA: u32 = 2 << 28;
+    ///Renders using perturbation theory around a high precision reference orbit
+    pub const DEEP: u32 = 2 << 27;
+    ///Outputs the smooth iteration count, `|z|` and the angle of z instead of a color
+    pub const RAW: u32 = 2 << 26;
 }
 ///Default colors for the fractal, taken from the trans flag 🏳️‍⚧️
 pub const DEFAULT_COLORS: [wgpu::Color; 5] = [
@@ -4,18 +4,24 @@
     clippy::future_not_send
 )]
 use std::env;
-use std::sync::Mutex;
 
 use actix_web::web::Data;
 use actix_web::{middleware, App, HttpServer};
 use dotenvy::dotenv;
-use structs::{rendering::PipelineStore, requests::RequestIdentifier};
-use utils::graphics::generate_backend;
+use structs::{
+    config::Config,
+    errors,
+    rendering::PipelineStore,
+    requests::{Cache, InFlightRenders, Jobs},
+};
+use utils::{cache::LruCache, disk_cache::DiskCache, graphics::generate_backend};
 
 use crate::endpoints::*;
 
 ///Module to contain all the endpoints
 mod endpoints;
+///Parser and compiler for custom fractal formulas
+mod formula;
 ///Module to store all the magic values
 mod grimoire;
 ///Structure/Enum definitions
@@ -49,24 +55,100 @@ async fn main() -> std::io::Result<()> {
         .format_timestamp(None)
         .init();
 
+    let config = Config::from_env();
+    //Shared between the workers, so that they don't each keep their own copy
+    let cache = Data::new(Cache::new(LruCache::new(
+        config.cache_size,
+        config.cache_ttl,
+    )));
+    let disk_cache = config.disk_cache_dir.as_ref().map(|dir| {
+        Data::new(
+            DiskCache::open(dir, config.disk_cache_size).expect("Unable to open the disk cache"),
+        )
+    });
+    //Shared so that the worker limit is for the whole server
+    let jobs = Data::new(Jobs::new(
+        config.job_workers,
+        config.job_retention,
+        config.job_results_size,
+    ));
+    let config = Data::new(config);
+    //Also shared, identical requests can end up on different workers
+    let in_flight = Data::new(InFlightRenders::new());
+
     HttpServer::new(move || {
-        App::new()
+        let app = App::new()
             .service(main_page)
             .service(coloring_page)
             .data_factory(|| async { generate_backend().await })
-            .app_data(Data::new(Mutex::new(
-                Vec::<(RequestIdentifier, Vec<u8>)>::new(),
-            )))
-            .app_data(Data::new(PipelineStore::new(Vec::new())))
+            //Extractor errors are sent back as json like the rest
+            .app_data(errors::path_config())
+            .app_data(errors::query_config())
+            .app_data(errors::json_config())
+            .app_data(cache.clone())
+            .app_data(in_flight.clone())
+            .app_data(config.clone())
+            .app_data(jobs.clone())
+            .app_data(Data::new(PipelineStore::default()))
             .service(render_fractal)
-            .wrap(middleware::Logger::default())
+            .service(render_fractal_spec)
+            .service(render_progress)
+            .service(preview)
+            .service(render_animation)
+            .service(render_batch)
+            .service(render_tile)
+            .service(render_pyramid)
+            .service(iiif_info)
+            .service(iiif_image)
+            .service(submit_job)
+            .service(job_status)
+            .service(job_result)
+            .service(cancel_job)
+            .service(job_progress)
+            .service(cache_stats)
+            .service(clear_cache)
+            .service(coalescing_stats)
+            .service(disk_cache_stats)
+            .service(clear_disk_cache);
+        //Only registered if it's enabled, the endpoints check for it
+        let app = match &disk_cache {
+            Some(disk_cache) => app.app_data(disk_cache.clone()),
+            None => app,
+        };
+        app.wrap(middleware::Logger::default())
     })
     .bind((ip, port))?
     .run()
     .await
 }
 
+///App with the state that the endpoints share, rendering on the cpu so that tests don't need a gpu.
+///Tests only add the services they use
+#[cfg(test)]
+fn cpu_test_app() -> App<
+    impl actix_web::dev::ServiceFactory<
+        actix_web::dev::ServiceRequest,
+        Config = (),
+        Response = actix_web::dev::ServiceResponse,
+        Error = actix_web::Error,
+        InitError = (),
+    >,
+> {
+    App::new()
+        .app_data(errors::path_config())
+        .app_data(errors::query_config())
+        .app_data(errors::json_config())
+        .app_data(Data::new(PipelineStore::default()))
+        .app_data(Data::new(Cache::new(LruCache::new(
+            grimoire::DEFAULT_CACHE_SIZE,
+            None,
+        ))))
+        .app_data(Data::new(InFlightRenders::new()))
+        .app_data(Data::new(structs::rendering::Backend::Cpu))
+}
+
 #[actix_web::test]
+#[allow(clippy::unnecessary_mut_passed)]
 async fn fractals_endpoint_test() {
     env_logger::Builder::new()
         .filter_module(grimoire::LOGGING_TARGET, log::LevelFilter::Debug)
@@ -78,10 +160,12 @@ async fn fractals_endpoint_test() {
 
     let mut app = actix_web::test::init_service(
         App::new()
-            .app_data(Data::new(PipelineStore::new(Vec::new())))
-            .app_data(Data::new(Mutex::new(
-                Vec::<(RequestIdentifier, Vec<u8>)>::new(),
-            )))
+            .app_data(Data::new(PipelineStore::default()))
+            .app_data(Data::new(Cache::new(LruCache::new(
+                grimoire::DEFAULT_CACHE_SIZE,
+                None,
+            ))))
+            .app_data(Data::new(InFlightRenders::new()))
             .data_factory(|| async { generate_backend().await })
             .service(render_fractal)
             .wrap(middleware::Logger::default()),
@@ -139,4 +223,988 @@ async fn fractals_endpoint_test() {
     let resp = actix_web::test::call_service(&mut app, req).await;
     let status = resp.status();
     assert_eq!(status, actix_web::http::StatusCode::OK);
+
+    //Julia sets
+    let req = actix_web::test::TestRequest::with_uri(
+        "/fractals/Mandelbrot?julia=true&julia_x=-0.4&julia_y=0.6&width=256&height=256&smooth=true",
+    )
+    .to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    let status = resp.status();
+    assert_eq!(status, actix_web::http::StatusCode::OK);
+
+    let req =
+        actix_web::test::TestRequest::with_uri("/fractals/Feather?julia=true&width=256&height=256")
+            .to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    let status = resp.status();
+    assert_eq!(status, actix_web::http::StatusCode::OK);
+
+    //Deep zoom
+    let req = actix_web::test::TestRequest::with_uri("/fractals/Mandelbrot?deep=true&deep_x=0&deep_y=1&deep_zoom=1e100&width=256&height=256&max_iterations=2000&smooth=true")
+        .to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    let status = resp.status();
+    assert_eq!(status, actix_web::http::StatusCode::OK);
+
+    let req = actix_web::test::TestRequest::with_uri("/fractals/Tricorn?deep=true&deep_x=-0.1011&deep_y=0.9563&deep_zoom=1e20&width=256&height=256")
+        .to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    let status = resp.status();
+    assert_eq!(status, actix_web::http::StatusCode::OK);
+
+    //Deep zoom, on a fractal without a perturbation formula
+    let req = actix_web::test::TestRequest::with_uri("/fractals/Eye?deep=true&deep_zoom=1e20")
+        .to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    let status = resp.status();
+    assert_eq!(status, actix_web::http::StatusCode::BAD_REQUEST);
+
+    //Deep zoom, with an invalid position
+    let req = actix_web::test::TestRequest::with_uri("/fractals/Mandelbrot?deep=true&deep_x=0.1.2")
+        .to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    let status = resp.status();
+    assert_eq!(status, actix_web::http::StatusCode::BAD_REQUEST);
+
+    //Wider than the largest texture, so it has to be rendered in tiles
+    let req = actix_web::test::TestRequest::with_uri(
+        "/fractals/Mandelbrot?width=10000&height=64&zoom=0.2&max_iterations=100",
+    )
+    .to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    let status = resp.status();
+    assert_eq!(status, actix_web::http::StatusCode::OK);
+
+    //Custom
+    let req = actix_web::test::TestRequest::with_uri(
+        "/fractals/Custom?formula=z%5E3%20%2B%20c*sin(z)&width=256&height=256&smooth=true",
+    )
+    .to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    let status = resp.status();
+    assert_eq!(status, actix_web::http::StatusCode::OK);
+
+    //Custom, with every function to make sure that the generated shader is valid
+    let req = actix_web::test::TestRequest::with_uri("/fractals/Custom?formula=sin(z)%2Bcos(z)%2Btan(z)%2Bsinh(z)%2Bcosh(z)%2Btanh(z)%2Bexp(z)%2Blog(z)%2Bsqrt(z)%2Bconj(z)%2Babs(z)%2Bre(z)%2Bim(z)%2Barg(z)%2B%7Cz%7C%2Bnorm(z)%2Bz%5Ec%2Bz%5E-2%2Bre(z)%5E3%2Bpi*i%2Fe%2Bc&width=64&height=64")
+        .to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    let status = resp.status();
+    assert_eq!(status, actix_web::http::StatusCode::OK);
+
+    //Custom, with an invalid formula
+    let req = actix_web::test::TestRequest::with_uri("/fractals/Custom?formula=z%5E2%20%2B%20(c")
+        .to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    let status = resp.status();
+    assert_eq!(status, actix_web::http::StatusCode::BAD_REQUEST);
+
+    //Custom, with more powers in a row than the formula can be nested
+    let req = actix_web::test::TestRequest::with_uri(&format!(
+        "/fractals/Custom?formula=z{}",
+        "%5Ez".repeat(grimoire::MAX_FORMULA_DEPTH + 1)
+    ))
+    .to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    assert_eq!(resp.status(), actix_web::http::StatusCode::BAD_REQUEST);
+    let body: serde_json::Value = actix_web::test::read_body_json(resp).await;
+    assert!(body["message"]
+        .as_str()
+        .unwrap()
+        .contains("nested too deeply"));
+}
+
+#[actix_web::test]
+async fn cpu_endpoint_test() {
+    let app = actix_web::test::init_service(cpu_test_app().service(render_fractal)).await;
+
+    for fractal in ["Mandelbrot", "BurningShip", "Tricorn", "Feather", "Eye"] {
+        let req = actix_web::test::TestRequest::with_uri(&format!(
+            "/fractals/{fractal}?width=128&height=96&smooth=true&debug=true"
+        ))
+        .to_request();
+        let resp = actix_web::test::call_service(&app, req).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::OK);
+    }
+
+    //Custom
+    let req = actix_web::test::TestRequest::with_uri(
+        "/fractals/Custom?formula=z%5E3%20%2B%20c*sin(z)&width=128&height=96&julia=true",
+    )
+    .to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    assert_eq!(resp.status(), actix_web::http::StatusCode::OK);
+
+    //Deep zoom
+    let req = actix_web::test::TestRequest::with_uri(
+        "/fractals/Mandelbrot?deep=true&deep_x=0&deep_y=1&deep_zoom=1e30&width=128&height=96",
+    )
+    .to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    assert_eq!(resp.status(), actix_web::http::StatusCode::OK);
+
+    //Output formats
+    for (format, mime) in [
+        ("png", "image/png"),
+        ("jpeg&quality=50", "image/jpeg"),
+        ("webp", "image/webp"),
+        ("bmp", "image/bmp"),
+        ("tiff", "image/tiff"),
+        ("qoi", "image/qoi"),
+        ("ppm", "image/x-portable-pixmap"),
+    ] {
+        let req = actix_web::test::TestRequest::with_uri(&format!(
+            "/fractals/Mandelbrot?width=64&height=48&format={format}"
+        ))
+        .to_request();
+        let resp = actix_web::test::call_service(&app, req).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::OK);
+        assert_eq!(resp.headers().get("Content-Type").unwrap(), mime);
+        let body = actix_web::test::read_body(resp).await;
+        let image = image::load_from_memory(&body).unwrap();
+        assert_eq!((image.width(), image.height()), (64, 48));
+    }
+
+    //The query parameter wins over the accept header
+    let req = actix_web::test::TestRequest::with_uri("/fractals/Mandelbrot?width=64&height=48")
+        .insert_header(("Accept", "image/webp,image/*;q=0.8"))
+        .to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    assert_eq!(resp.headers().get("Content-Type").unwrap(), "image/webp");
+    let req = actix_web::test::TestRequest::with_uri(
+        "/fractals/Mandelbrot?width=64&height=48&format=bmp",
+    )
+    .insert_header(("Accept", "image/webp"))
+    .to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    assert_eq!(resp.headers().get("Content-Type").unwrap(), "image/bmp");
+
+    //Raw data
+    let req = actix_web::test::TestRequest::with_uri(
+        "/fractals/Mandelbrot?width=64&height=48&format=json&max_iterations=100",
+    )
+    .to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    assert_eq!(
+        resp.headers().get("Content-Type").unwrap(),
+        "application/json"
+    );
+    let raw: utils::export::RawData = actix_web::test::read_body_json(resp).await;
+    assert_eq!(raw.iterations.len(), 64 * 48);
+    assert_eq!(raw.angle.len(), 64 * 48);
+    //The center of the default view is inside the set
+    assert!((raw.iterations[24 * 64 + 32] - 100.0).abs() < f32::EPSILON);
+    assert!(raw.iterations[0] < 10.0);
+    for (format, mime) in [("npy", "application/x-npy"), ("exr", "image/x-exr")] {
+        let req = actix_web::test::TestRequest::with_uri(&format!(
+            "/fractals/Mandelbrot?width=64&height=48&format={format}"
+        ))
+        .to_request();
+        let resp = actix_web::test::call_service(&app, req).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::OK);
+        assert_eq!(resp.headers().get("Content-Type").unwrap(), mime);
+    }
+
+    //Bounds that are too wide get letterboxed, and the actual ones are reported
+    let req = actix_web::test::TestRequest::with_uri(
+        "/fractals/Mandelbrot?width=64&height=32&x_min=-2&x_max=2&y_min=-0.5&y_max=0.5",
+    )
+    .to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    assert_eq!(resp.status(), actix_web::http::StatusCode::OK);
+    for (name, value) in [
+        ("X-Bounds-X-Min", "-2"),
+        ("X-Bounds-X-Max", "2"),
+        ("X-Bounds-Y-Min", "-1"),
+        ("X-Bounds-Y-Max", "1"),
+    ] {
+        assert_eq!(resp.headers().get(name).unwrap(), value);
+    }
+
+    let req = actix_web::test::TestRequest::with_uri("/fractals/Mandelbrot?width=64&height=48")
+        .insert_header(("Accept", "text/html"))
+        .to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    assert_eq!(resp.status(), actix_web::http::StatusCode::NOT_ACCEPTABLE);
+}
+
+#[actix_web::test]
+async fn animation_endpoint_test() {
+    use image::AnimationDecoder;
+
+    let app = actix_web::test::init_service(cpu_test_app().service(render_animation)).await;
+    let animation = |output: &str| {
+        actix_web::test::TestRequest::with_uri(&format!(
+            "/animations/Mandelbrot?width=32&height=24&frames=4&end_x=-1.5&end_zoom=20&output={output}"
+        ))
+        .to_request()
+    };
+
+    let resp = actix_web::test::call_service(&app, animation("gif")).await;
+    assert_eq!(resp.status(), actix_web::http::StatusCode::OK);
+    assert_eq!(resp.headers().get("Content-Type").unwrap(), "image/gif");
+    let body = actix_web::test::read_body(resp).await;
+    let decoder = image::codecs::gif::GifDecoder::new(body.as_ref()).unwrap();
+    assert_eq!(decoder.into_frames().count(), 4);
+
+    let resp = actix_web::test::call_service(&app, animation("apng")).await;
+    assert_eq!(resp.headers().get("Content-Type").unwrap(), "image/apng");
+    let body = actix_web::test::read_body(resp).await;
+    let decoder = image::codecs::png::PngDecoder::new(body.as_ref()).unwrap();
+    assert!(decoder.is_apng());
+    let frames = decoder.apng().into_frames().collect_frames().unwrap();
+    assert_eq!(frames.len(), 4);
+    //Zooming in changes the image
+    assert_ne!(frames[0].buffer(), frames[3].buffer());
+
+    let resp = actix_web::test::call_service(&app, animation("zip")).await;
+    assert_eq!(
+        resp.headers().get("Content-Type").unwrap(),
+        "application/zip"
+    );
+    let body = actix_web::test::read_body(resp).await;
+    let mut archive = zip::ZipArchive::new(std::io::Cursor::new(body)).unwrap();
+    assert_eq!(archive.len(), 4);
+    let frame = archive.by_index(3).unwrap();
+    assert_eq!(frame.name(), "frame_0003.png");
+
+    let resp = actix_web::test::call_service(&app, animation("tar")).await;
+    assert_eq!(
+        resp.headers().get("Content-Type").unwrap(),
+        "application/x-tar"
+    );
+    let body = actix_web::test::read_body(resp).await;
+    let mut archive = tar::Archive::new(body.as_ref());
+    let names = archive
+        .entries()
+        .unwrap()
+        .map(|entry| entry.unwrap().path().unwrap().display().to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        names,
+        [
+            "frame_0000.png",
+            "frame_0001.png",
+            "frame_0002.png",
+            "frame_0003.png"
+        ]
+    );
+
+    //Palette cycling only changes the colors
+    let req = actix_web::test::TestRequest::with_uri(
+        "/animations/Mandelbrot?width=32&height=24&frames=4&mode=cycle&output=apng",
+    )
+    .to_request();
+    let body = actix_web::test::call_and_read_body(&app, req).await;
+    let frames = image::codecs::png::PngDecoder::new(body.as_ref())
+        .unwrap()
+        .apng()
+        .into_frames()
+        .collect_frames()
+        .unwrap();
+    assert_eq!(frames.len(), 4);
+    assert_ne!(frames[0].buffer(), frames[1].buffer());
+
+    for query in [
+        "frames=0",
+        "deep=true",
+        "format=jpeg",
+        "end_zoom=-1",
+        "fps=1000",
+        "mode=cycle&debug=true",
+        "mode=cycle&msaa=4",
+    ] {
+        let req = actix_web::test::TestRequest::with_uri(&format!(
+            "/animations/Mandelbrot?width=32&height=24&{query}"
+        ))
+        .to_request();
+        let resp = actix_web::test::call_service(&app, req).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::BAD_REQUEST);
+    }
+}
+
+#[actix_web::test]
+async fn tile_endpoint_test() {
+    let app = actix_web::test::init_service(cpu_test_app().service(render_tile)).await;
+
+    let req = actix_web::test::TestRequest::with_uri(
+        "/tiles/Mandelbrot/1/0/0.png?max_iterations=100&smooth=true",
+    )
+    .to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    assert_eq!(resp.status(), actix_web::http::StatusCode::OK);
+    assert_eq!(resp.headers().get("Content-Type").unwrap(), "image/png");
+    assert_eq!(resp.headers().get("X-Bounds-X-Min").unwrap(), "-2.5");
+    assert_eq!(resp.headers().get("X-Bounds-Y-Max").unwrap(), "2");
+    let body = actix_web::test::read_body(resp).await;
+    let image = image::load_from_memory(&body).unwrap();
+    assert_eq!((image.width(), image.height()), (256, 256));
+
+    let req = actix_web::test::TestRequest::with_uri("/tiles/Mandelbrot/1/2/0.png").to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    assert_eq!(resp.status(), actix_web::http::StatusCode::BAD_REQUEST);
+}
+
+#[actix_web::test]
+async fn pyramid_endpoint_test() {
+    let app = actix_web::test::init_service(
+        cpu_test_app()
+            .service(render_fractal)
+            .service(render_pyramid)
+            .service(iiif_info)
+            .service(iiif_image),
+    )
+    .await;
+
+    let req = actix_web::test::TestRequest::with_uri(
+        "/pyramids/Mandelbrot?width=600&height=300&max_iterations=50",
+    )
+    .to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    assert_eq!(resp.status(), actix_web::http::StatusCode::OK);
+    assert_eq!(
+        resp.headers().get("Content-Type").unwrap(),
+        "application/zip"
+    );
+    let body = actix_web::test::read_body(resp).await;
+    let mut archive = zip::ZipArchive::new(std::io::Cursor::new(body)).unwrap();
+    assert_eq!(archive.len(), 1 + 17);
+    assert!(archive.by_name("pyramid.dzi").is_ok());
+    let mut tile = Vec::new();
+    std::io::Read::read_to_end(
+        &mut archive.by_name("pyramid_files/10/2_1.png").unwrap(),
+        &mut tile,
+    )
+    .unwrap();
+    let tile = image::load_from_memory(&tile).unwrap();
+    assert_eq!((tile.width(), tile.height()), (88, 44));
+
+    //Too big to render while the request waits
+    let req = actix_web::test::TestRequest::with_uri("/pyramids/Mandelbrot?width=8192&height=4096")
+        .to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    assert_eq!(resp.status(), actix_web::http::StatusCode::BAD_REQUEST);
+
+    let req =
+        actix_web::test::TestRequest::with_uri("/iiif/Mandelbrot/width=1024&height=512/info.json")
+            .to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    assert_eq!(resp.status(), actix_web::http::StatusCode::OK);
+    assert_eq!(
+        resp.headers().get("Access-Control-Allow-Origin").unwrap(),
+        "*"
+    );
+    let info: serde_json::Value = actix_web::test::read_body_json(resp).await;
+    assert_eq!(info["width"], 1024);
+    assert_eq!(info["height"], 512);
+    assert!(info["id"]
+        .as_str()
+        .unwrap()
+        .ends_with("/iiif/Mandelbrot/width=1024&height=512"));
+
+    //A scaled down full region is the same as rendering the smaller image directly
+    let req = actix_web::test::TestRequest::with_uri(
+        "/iiif/Mandelbrot/width=1024&height=512/full/512,/0/default.png",
+    )
+    .to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    assert_eq!(resp.status(), actix_web::http::StatusCode::OK);
+    let iiif = actix_web::test::read_body(resp).await;
+    let req = actix_web::test::TestRequest::with_uri("/fractals/Mandelbrot?width=512&height=256")
+        .to_request();
+    let direct = actix_web::test::call_and_read_body(&app, req).await;
+    assert_eq!(iiif, direct);
+
+    for uri in [
+        "/iiif/Mandelbrot/width=1024&height=512/full/max/90/default.png",
+        "/iiif/Mandelbrot/width=1024&height=512/full/max/0/gray.png",
+        "/iiif/Mandelbrot/width=1024&height=512/full/max/0/default.npy",
+        "/iiif/Mandelbrot/width=1024&height=512/2000,0,10,10/max/0/default.png",
+        "/iiif/Mandelbrot/deep=true/full/max/0/default.png",
+    ] {
+        let req = actix_web::test::TestRequest::with_uri(uri).to_request();
+        let resp = actix_web::test::call_service(&app, req).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::BAD_REQUEST);
+    }
+}
+
+#[actix_web::test]
+async fn job_endpoint_test() {
+    let app = actix_web::test::init_service(
+        cpu_test_app()
+            .app_data(Data::new(Jobs::new(
+                1,
+                std::time::Duration::from_secs(60),
+                u64::MAX,
+            )))
+            .service(render_fractal)
+            .service(submit_job)
+            .service(job_status)
+            .service(job_result)
+            .service(cancel_job),
+    )
+    .await;
+    let submit = |body: serde_json::Value| {
+        actix_web::test::TestRequest::post()
+            .uri("/jobs")
+            .set_json(body)
+            .to_request()
+    };
+
+    let resp = actix_web::test::call_service(
+        &app,
+        submit(serde_json::json!({"fractal": "Mandelbrot", "width": 32, "height": 24})),
+    )
+    .await;
+    assert_eq!(resp.status(), actix_web::http::StatusCode::ACCEPTED);
+    let info: utils::jobs::JobInfo = actix_web::test::read_body_json(resp).await;
+    assert_eq!(info.status, utils::jobs::JobStatus::Queued);
+    let image = info.id;
+
+    let resp = actix_web::test::call_service(
+        &app,
+        submit(serde_json::json!({
+            "fractal": "Mandelbrot",
+            "width": 32,
+            "height": 24,
+            "animation": {"frames": 3, "end_zoom": 10.0, "output": "zip"}
+        })),
+    )
+    .await;
+    let animation: utils::jobs::JobInfo = actix_web::test::read_body_json(resp).await;
+
+    let resp = actix_web::test::call_service(
+        &app,
+        submit(serde_json::json!({
+            "fractal": "Mandelbrot",
+            "width": 600,
+            "height": 300,
+            "max_iterations": 50,
+            "pyramid": {"output": "tar"}
+        })),
+    )
+    .await;
+    let pyramid: utils::jobs::JobInfo = actix_web::test::read_body_json(resp).await;
+
+    //Canceled while it waits for the single worker
+    let resp = actix_web::test::call_service(
+        &app,
+        submit(serde_json::json!({"fractal": "Tricorn", "width": 32, "height": 24})),
+    )
+    .await;
+    let canceled: utils::jobs::JobInfo = actix_web::test::read_body_json(resp).await;
+    let req = actix_web::test::TestRequest::delete()
+        .uri(&format!("/jobs/{}", canceled.id))
+        .to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    assert_eq!(resp.status(), actix_web::http::StatusCode::NO_CONTENT);
+    let req = actix_web::test::TestRequest::with_uri(&format!("/jobs/{}/result", canceled.id))
+        .to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    assert_eq!(resp.status(), actix_web::http::StatusCode::GONE);
+
+    for id in [image, animation.id, pyramid.id] {
+        loop {
+            let req = actix_web::test::TestRequest::with_uri(&format!("/jobs/{id}")).to_request();
+            let info: utils::jobs::JobInfo =
+                actix_web::test::call_and_read_body_json(&app, req).await;
+            if info.status.is_finished() {
+                assert_eq!(info.status, utils::jobs::JobStatus::Done);
+                assert_eq!(info.progress, 1.0);
+                break;
+            }
+            actix_web::rt::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+    }
+
+    let req = actix_web::test::TestRequest::with_uri(&format!("/jobs/{image}/result")).to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    assert_eq!(resp.status(), actix_web::http::StatusCode::OK);
+    assert_eq!(resp.headers().get("Content-Type").unwrap(), "image/png");
+    let result = actix_web::test::read_body(resp).await;
+    let req = actix_web::test::TestRequest::with_uri("/fractals/Mandelbrot?width=32&height=24")
+        .to_request();
+    let direct = actix_web::test::call_and_read_body(&app, req).await;
+    assert_eq!(result, direct);
+
+    let req = actix_web::test::TestRequest::with_uri(&format!("/jobs/{}/result", animation.id))
+        .to_request();
+    let body = actix_web::test::call_and_read_body(&app, req).await;
+    let archive = zip::ZipArchive::new(std::io::Cursor::new(body)).unwrap();
+    assert_eq!(archive.len(), 3);
+
+    let req = actix_web::test::TestRequest::with_uri(&format!("/jobs/{}/result", pyramid.id))
+        .to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    assert_eq!(
+        resp.headers().get("Content-Type").unwrap(),
+        "application/x-tar"
+    );
+    let body = actix_web::test::read_body(resp).await;
+    let mut archive = tar::Archive::new(body.as_ref());
+    assert_eq!(archive.entries().unwrap().count(), 1 + 17);
+
+    //Deleting a finished job drops it
+    let req = actix_web::test::TestRequest::delete()
+        .uri(&format!("/jobs/{image}"))
+        .to_request();
+    actix_web::test::call_service(&app, req).await;
+    let req = actix_web::test::TestRequest::with_uri(&format!("/jobs/{image}")).to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    assert_eq!(resp.status(), actix_web::http::StatusCode::NOT_FOUND);
+
+    //Invalid jobs are rejected before they're queued
+    let resp = actix_web::test::call_service(
+        &app,
+        submit(serde_json::json!({"fractal": "Custom", "width": 32})),
+    )
+    .await;
+    assert_eq!(resp.status(), actix_web::http::StatusCode::BAD_REQUEST);
+    let resp = actix_web::test::call_service(
+        &app,
+        submit(serde_json::json!({"fractal": "Mandelbrot", "width": 65536, "height": 65536, "pyramid": {}})),
+    )
+    .await;
+    assert_eq!(resp.status(), actix_web::http::StatusCode::BAD_REQUEST);
+}
+
+#[actix_web::test]
+async fn progress_endpoint_test() {
+    let app = actix_web::test::init_service(
+        cpu_test_app()
+            .app_data(Data::new(Jobs::new(
+                1,
+                std::time::Duration::from_secs(60),
+                u64::MAX,
+            )))
+            .service(render_fractal)
+            .service(render_progress)
+            .service(submit_job)
+            .service(job_progress),
+    )
+    .await;
+
+    let req =
+        actix_web::test::TestRequest::with_uri("/fractals/Mandelbrot/progress?width=64&height=48")
+            .to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    assert_eq!(resp.status(), actix_web::http::StatusCode::OK);
+    assert_eq!(
+        resp.headers().get("Content-Type").unwrap(),
+        "text/event-stream"
+    );
+    let body = actix_web::test::read_body(resp).await;
+    let events = std::str::from_utf8(&body).unwrap();
+    assert!(events.starts_with("event: progress\ndata: {"));
+    assert!(events.contains("\"rows_total\":48"));
+    assert!(events.ends_with(
+        "event: done\ndata: {\"url\":\"/fractals/Mandelbrot?width=64&height=48&format=png\"}\n\n"
+    ));
+    //The image is in the cache by then
+    let req = actix_web::test::TestRequest::with_uri(
+        "/fractals/Mandelbrot?width=64&height=48&format=png",
+    )
+    .to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    assert_eq!(resp.status(), actix_web::http::StatusCode::OK);
+
+    let req = actix_web::test::TestRequest::post()
+        .uri("/jobs")
+        .set_json(serde_json::json!({"fractal": "Mandelbrot", "width": 32, "height": 24}))
+        .to_request();
+    let info: utils::jobs::JobInfo = actix_web::test::call_and_read_body_json(&app, req).await;
+    let req =
+        actix_web::test::TestRequest::with_uri(&format!("/jobs/{}/progress", info.id)).to_request();
+    let body = actix_web::test::call_and_read_body(&app, req).await;
+    let events = std::str::from_utf8(&body).unwrap();
+    assert!(events.ends_with(&format!(
+        "event: done\ndata: {{\"url\":\"/jobs/{}/result\"}}\n\n",
+        info.id
+    )));
+
+    for uri in ["/jobs/1000/progress", "/fractals/Custom/progress"] {
+        let req = actix_web::test::TestRequest::with_uri(uri).to_request();
+        let resp = actix_web::test::call_service(&app, req).await;
+        assert!(resp.status().is_client_error());
+    }
+}
+
+#[actix_web::test]
+async fn spec_endpoint_test() {
+    let cache = Data::new(Cache::new(LruCache::new(
+        grimoire::DEFAULT_CACHE_SIZE,
+        None,
+    )));
+    let app = actix_web::test::init_service(
+        cpu_test_app()
+            .app_data(cache.clone())
+            .service(render_fractal)
+            .service(render_fractal_spec),
+    )
+    .await;
+
+    let req = actix_web::test::TestRequest::post()
+        .uri("/fractals/Custom")
+        .set_json(serde_json::json!({
+            "formula": "z^2 + c",
+            "colors": ["#000000", [255, 0, 0], {"r": 255, "g": 255, "b": 255}],
+            "transform": {"x": -0.5, "zoom": 2},
+            "output": {"width": 48, "height": 32, "format": "png"},
+        }))
+        .to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    assert_eq!(resp.status(), actix_web::http::StatusCode::OK);
+    assert_eq!(resp.headers().get("Content-Type").unwrap(), "image/png");
+    let posted = actix_web::test::read_body(resp).await;
+
+    //Same cache entry as the query
+    let req = actix_web::test::TestRequest::with_uri(
+        "/fractals/Custom?formula=z%5E2%20%2B%20c&colors=000000,ff0000,ffffff&position_x=-0.5&zoom=2\
+         &width=48&height=32&format=png",
+    )
+    .to_request();
+    let body = actix_web::test::call_and_read_body(&app, req).await;
+    assert_eq!(body, posted);
+    let stats = cache.lock().unwrap().stats();
+    assert_eq!((stats.entries, stats.hits), (1, 1));
+
+    for body in [
+        serde_json::json!({"width": 48, "output": {"width": 48}}),
+        serde_json::json!({"colors": ["nothex"]}),
+        serde_json::json!({"colors": [[255, 0]]}),
+    ] {
+        let req = actix_web::test::TestRequest::post()
+            .uri("/fractals/Mandelbrot")
+            .set_json(body)
+            .to_request();
+        let resp = actix_web::test::call_service(&app, req).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::BAD_REQUEST);
+    }
+}
+
+#[actix_web::test]
+async fn batch_endpoint_test() {
+    use std::io::Read;
+    let app = actix_web::test::init_service(cpu_test_app().service(render_batch)).await;
+    let items = serde_json::json!([
+        {"fractal": "Mandelbrot", "width": 32, "height": 24},
+        {"fractal": "Custom", "width": 32, "height": 24},
+        {"fractal": "Tricorn", "output": {"width": 16, "height": 16, "format": "jpeg"}},
+    ]);
+
+    let req = actix_web::test::TestRequest::post()
+        .uri("/batch")
+        .set_json(&items)
+        .to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    assert_eq!(resp.status(), actix_web::http::StatusCode::OK);
+    assert_eq!(
+        resp.headers().get("Content-Type").unwrap(),
+        "application/zip"
+    );
+    let body = actix_web::test::read_body(resp).await;
+    let mut archive = zip::ZipArchive::new(std::io::Cursor::new(body)).unwrap();
+    assert_eq!(
+        archive
+            .file_names()
+            .collect::<std::collections::HashSet<_>>(),
+        ["manifest.json", "0.png", "2.jpg"].into()
+    );
+    let mut manifest = String::new();
+    archive
+        .by_name("manifest.json")
+        .unwrap()
+        .read_to_string(&mut manifest)
+        .unwrap();
+    let manifest: serde_json::Value = serde_json::from_str(&manifest).unwrap();
+    let statuses = manifest
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|item| item["status"].as_u64().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(statuses, [200, 400, 200]);
+    assert_eq!(manifest[1]["error"]["code"], "missing_parameter");
+    assert_eq!(manifest[1]["error"]["field"], "formula");
+
+    let req = actix_web::test::TestRequest::post()
+        .uri("/batch?output=multipart")
+        .set_json(&items)
+        .to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    let content_type = resp
+        .headers()
+        .get("Content-Type")
+        .unwrap()
+        .to_str()
+        .unwrap();
+    let boundary = content_type
+        .strip_prefix("multipart/mixed; boundary=")
+        .unwrap()
+        .to_string();
+    let body = actix_web::test::read_body(resp).await;
+    let body = String::from_utf8_lossy(&body);
+    assert_eq!(body.matches(&format!("--{boundary}\r\n")).count(), 3);
+    assert!(body.ends_with(&format!("--{boundary}--\r\n")));
+    assert!(body.contains("filename=\"1.json\"\r\nX-Status: 400"));
+    assert!(body.contains("Content-Type: image/jpeg"));
+
+    let too_many = vec![serde_json::json!({"fractal": "Mandelbrot"}); grimoire::MAX_BATCH_SIZE + 1];
+    let req = actix_web::test::TestRequest::post()
+        .uri("/batch")
+        .set_json(too_many)
+        .to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    assert_eq!(resp.status(), actix_web::http::StatusCode::BAD_REQUEST);
+}
+
+#[actix_web::test]
+async fn error_endpoint_test() {
+    let app = actix_web::test::init_service(
+        cpu_test_app()
+            .service(render_fractal)
+            .service(render_fractal_spec),
+    )
+    .await;
+    let error = |uri: String| {
+        let req = actix_web::test::TestRequest::with_uri(&uri).to_request();
+        actix_web::test::call_service(&app, req)
+    };
+
+    let resp = error("/fractals/Julia".to_string()).await;
+    assert_eq!(resp.status(), actix_web::http::StatusCode::NOT_FOUND);
+    let body: serde_json::Value = actix_web::test::read_body_json(resp).await;
+    assert_eq!(body["code"], "unknown_fractal");
+    assert_eq!(body["field"], "fractal");
+    assert_eq!(body["allowed"].as_array().unwrap().len(), 6);
+
+    let too_many_colors = vec!["000000"; grimoire::MAX_COLORS as usize + 1].join(",");
+    for (query, field, max) in [
+        (
+            "width=0".to_string(),
+            "width",
+            u64::from(grimoire::MAX_IMAGE_SIDE),
+        ),
+        (
+            "height=100000".to_string(),
+            "height",
+            u64::from(grimoire::MAX_IMAGE_SIDE),
+        ),
+        ("msaa=0".to_string(), "msaa", u64::from(grimoire::MAX_MSAA)),
+        (
+            "num_colors=0".to_string(),
+            "num_colors",
+            u64::from(grimoire::MAX_NUM_COLORS),
+        ),
+        (
+            "max_iterations=0".to_string(),
+            "max_iterations",
+            u64::from(grimoire::MAX_ITERATIONS),
+        ),
+        (
+            format!("colors={too_many_colors}"),
+            "colors",
+            grimoire::MAX_COLORS,
+        ),
+    ] {
+        let resp = error(format!("/fractals/Mandelbrot?{query}")).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = actix_web::test::read_body_json(resp).await;
+        assert_eq!(body["code"], "out_of_range", "{query}");
+        assert_eq!(body["field"], field);
+        assert_eq!(body["allowed"]["max"], max);
+    }
+
+    //Posters only go through the job queue
+    let resp = error("/fractals/Mandelbrot?width=30000&height=20000".to_string()).await;
+    assert_eq!(resp.status(), actix_web::http::StatusCode::BAD_REQUEST);
+
+    //Raw data takes 4 times the memory of rgba, so it has a lower limit
+    for format in ["npy", "exr", "json"] {
+        let resp = error(format!(
+            "/fractals/Mandelbrot?format={format}&width=30000&height=20000"
+        ))
+        .await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = actix_web::test::read_body_json(resp).await;
+        assert_eq!(body["code"], "bad_request");
+    }
+
+    let resp = error("/fractals/Mandelbrot?colors=nothex".to_string()).await;
+    let body: serde_json::Value = actix_web::test::read_body_json(resp).await;
+    assert_eq!(body["code"], "invalid_parameter");
+    assert_eq!(body["field"], "colors");
+    assert!(body.get("allowed").is_none());
+
+    //Extractor errors are json too
+    let resp = error("/fractals/Mandelbrot?width=wide".to_string()).await;
+    assert_eq!(resp.status(), actix_web::http::StatusCode::BAD_REQUEST);
+    let body: serde_json::Value = actix_web::test::read_body_json(resp).await;
+    assert_eq!(body["code"], "bad_request");
+    let req = actix_web::test::TestRequest::post()
+        .uri("/fractals/Mandelbrot")
+        .insert_header(("Content-Type", "application/json"))
+        .set_payload("{")
+        .to_request();
+    let body: serde_json::Value = actix_web::test::call_and_read_body_json(&app, req).await;
+    assert_eq!(body["code"], "bad_request");
+}
+
+#[actix_web::test]
+async fn preview_endpoint_test() {
+    use actix_http::ws::{Frame, Message};
+    let app = actix_web::test::init_service(cpu_test_app().service(preview)).await;
+
+    let view = |width: u32| Message::Text(format!(r#"{{"width":{width},"height":48}}"#).into());
+    let req = actix_web::test::TestRequest::with_uri("/previews/Mandelbrot")
+        .insert_header(("Upgrade", "websocket"))
+        .insert_header(("Connection", "upgrade"))
+        .insert_header(("Sec-WebSocket-Version", "13"))
+        .insert_header(("Sec-WebSocket-Key", "dGhlIHNhbXBsZSBub25jZQ=="))
+        .set_payload(utils::websocket::client_frames(vec![
+            view(32),
+            view(64),
+            Message::Text("not a view".into()),
+        ]))
+        .to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    assert_eq!(
+        resp.status(),
+        actix_web::http::StatusCode::SWITCHING_PROTOCOLS
+    );
+    assert_eq!(
+        resp.headers().get("Sec-WebSocket-Accept").unwrap(),
+        "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+    );
+    let body = actix_web::test::read_body(resp).await;
+    let frames = utils::websocket::server_frames(&body);
+
+    let mut stages = Vec::new();
+    let mut error = None;
+    for pair in frames.windows(2) {
+        let Frame::Text(text) = &pair[0] else {
+            continue;
+        };
+        let info: serde_json::Value = serde_json::from_slice(text).unwrap();
+        if info.get("error").is_some() {
+            error = Some(info);
+            continue;
+        }
+        //Only the last valid view is refined all the way
+        if info["view"] != 2 {
+            continue;
+        }
+        let Frame::Binary(image) = &pair[1] else {
+            panic!("Every frame description is followed by the image");
+        };
+        let image = image::load_from_memory(image).unwrap();
+        assert_eq!(
+            (image.width(), image.height()),
+            (
+                info["width"].as_u64().unwrap() as u32,
+                info["height"].as_u64().unwrap() as u32
+            )
+        );
+        stages.push(image.width());
+    }
+    assert_eq!(stages, [8, 16, 32, 64]);
+    assert_eq!(error.unwrap()["view"], 3);
+
+    let req = actix_web::test::TestRequest::with_uri("/previews/Mandelbrot").to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    assert_eq!(resp.status(), actix_web::http::StatusCode::BAD_REQUEST);
+}
+
+#[actix_web::test]
+async fn admin_endpoint_test() {
+    let config = Config {
+        admin_token: Some("secret".to_string()),
+        ..Config::from_env()
+    };
+    let app = actix_web::test::init_service(
+        cpu_test_app()
+            .app_data(Data::new(config))
+            .service(render_fractal)
+            .service(cache_stats)
+            .service(clear_cache)
+            .service(coalescing_stats),
+    )
+    .await;
+
+    //Render the same image twice, so that there's a miss and a hit
+    for _ in 0..2 {
+        let req = actix_web::test::TestRequest::with_uri("/fractals/Mandelbrot?width=32&height=32")
+            .to_request();
+        let resp = actix_web::test::call_service(&app, req).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::OK);
+    }
+
+    let req = actix_web::test::TestRequest::with_uri("/admin/cache").to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    assert_eq!(resp.status(), actix_web::http::StatusCode::UNAUTHORIZED);
+
+    let req = actix_web::test::TestRequest::with_uri("/admin/cache")
+        .insert_header(("Authorization", "Bearer secret"))
+        .to_request();
+    let stats: utils::cache::CacheStats = actix_web::test::call_and_read_body_json(&app, req).await;
+    assert_eq!((stats.hits, stats.misses, stats.entries), (1, 1, 1));
+
+    //The second one was cached, so only one render happened
+    let req = actix_web::test::TestRequest::with_uri("/admin/coalescing")
+        .insert_header(("Authorization", "Bearer secret"))
+        .to_request();
+    let stats: utils::coalesce::CoalesceStats =
+        actix_web::test::call_and_read_body_json(&app, req).await;
+    assert_eq!((stats.renders, stats.coalesced, stats.in_flight), (1, 0, 0));
+
+    let req = actix_web::test::TestRequest::delete()
+        .uri("/admin/cache")
+        .insert_header(("Authorization", "Bearer secret"))
+        .to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    assert_eq!(resp.status(), actix_web::http::StatusCode::NO_CONTENT);
+}
+
+#[actix_web::test]
+async fn disk_cache_endpoint_test() {
+    let dir = env::temp_dir().join(format!("fractals_api_endpoint_{}", std::process::id()));
+    let config = Config {
+        admin_token: Some("secret".to_string()),
+        ..Config::from_env()
+    };
+    let app = actix_web::test::init_service(
+        cpu_test_app()
+            //Nothing fits into the memory cache, so the disk one is always used
+            .app_data(Data::new(Cache::new(LruCache::new(0, None))))
+            .app_data(Data::new(
+                DiskCache::open(&dir, grimoire::DEFAULT_DISK_CACHE_SIZE).unwrap(),
+            ))
+            .app_data(Data::new(config))
+            .service(render_fractal)
+            .service(disk_cache_stats)
+            .service(clear_disk_cache),
+    )
+    .await;
+
+    let mut images = Vec::new();
+    for _ in 0..2 {
+        let req = actix_web::test::TestRequest::with_uri("/fractals/Tricorn?width=32&height=32")
+            .to_request();
+        images.push(actix_web::test::call_and_read_body(&app, req).await);
+    }
+    assert_eq!(images[0], images[1]);
+
+    let req = actix_web::test::TestRequest::with_uri("/admin/disk_cache")
+        .insert_header(("Authorization", "Bearer secret"))
+        .to_request();
+    let stats: utils::cache::CacheStats = actix_web::test::call_and_read_body_json(&app, req).await;
+    assert_eq!((stats.hits, stats.entries), (1, 1));
+
+    let req = actix_web::test::TestRequest::delete()
+        .uri("/admin/disk_cache")
+        .insert_header(("Authorization", "Bearer secret"))
+        .to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    assert_eq!(resp.status(), actix_web::http::StatusCode::NO_CONTENT);
+    std::fs::remove_dir(dir).unwrap();
 }
@@ -6,6 +6,12 @@ struct ShaderDataUniforms {
   max_iter: u32,
   color_num: u32,
   flags: u32,
+  julia: vec2<f32>,
+  deep_exponent: i32,
+  orbit_len: u32,
+  viewport: vec4<f32>,
+  color_offset: f32,
+  rotation: vec2<f32>,
 }
 
 struct VertexOutput {
@@ -19,6 +25,9 @@ var<uniform> uniforms : ShaderDataUniforms;
 @group(0)
 @binding(1)
 var<storage, read>  colors : array<vec4<f32>>;
+@group(0)
+@binding(2)
+var<storage, read>  orbit : array<vec2<f32>>;
 
 //Helper functions
 //I don't remember where I got this, but it should work
@@ -26,17 +35,36 @@ fn rand(s: f32) -> f32 {
     return fract(sin(s * 12.9898) * 43758.5453);
 }
 
+//Shortcuts that skip the interior of the parent set are not valid for julia sets
+fn julia_mode() -> bool {
+    return (uniforms.flags & (2u << 28u)) != 0u;
+}
+
+fn deep_mode() -> bool {
+    return (uniforms.flags & (2u << 27u)) != 0u;
+}
+
+fn raw_mode() -> bool {
+    return (uniforms.flags & (2u << 26u)) != 0u;
+}
+
+//Rotates around the center of the image
+fn rotate(uv: vec2<f32>) -> vec2<f32> {
+    let r = uniforms.rotation;
+    return vec2<f32>(uv.x * r.x - uv.y * r.y, uv.x * r.y + uv.y * r.x);
+}
+
 fn get_col(coord: f32, col_num: i32) -> vec3<f32> {
     if col_num == 1 {
         return colors[0].xyz;
     }
-    let cstep1 = 1.0 / f32(col_num - 1);
-    for (var i = 1; i < col_num; i += 1) {
-        if coord < cstep1 * f32(i) {
-            return mix(colors[(i - 1) % uniforms.arr_len], colors[i % uniforms.arr_len], coord / cstep1 - f32(i - 1)).xyz;
-        }
+    if coord >= 1.0 {
+        return vec3<f32>(coord);
     }
-    return vec3<f32>(coord);
+    //Wraps around the colors, so that cycling the offset through all of them loops seamlessly
+    let stripe = coord * f32(col_num - 1) + uniforms.color_offset;
+    let i = max(i32(floor(stripe)), 0);
+    return mix(colors[i % uniforms.arr_len], colors[(i + 1) % uniforms.arr_len], stripe - f32(i)).xyz;
 }
 
 fn get_color(uv: vec2<f32>, i: f32, max_i: u32) -> vec3<f32> {
@@ -46,8 +74,13 @@ fn get_color(uv: vec2<f32>, i: f32, max_i: u32) -> vec3<f32> {
     return get_col(f32(i) / f32(max_i), i32(uniforms.color_num));
 }
 
-fn fractal(C: vec2<f32>) -> vec3<f32> {
+fn fractal(uv: vec2<f32>) -> vec3<f32> {
     var coords = vec2<f32>(0.0);
+    var C = uv;
+    if julia_mode() {
+        coords = uv;
+        C = uniforms.julia;
+    }
     var iter = 0u;
 
     let max_iteration = uniforms.max_iter;
@@ -56,6 +89,23 @@ fn fractal(C: vec2<f32>) -> vec3<f32> {
         coords = fractal_func(coords, C);
         iter += 1u;
     }
+    return escape_color(uv, iter, coords);
+}
+
+//Colors a pixel based on how fast it escaped
+fn escape_color(uv: vec2<f32>, iter: u32, coords: vec2<f32>) -> vec3<f32> {
+    let max_iteration = uniforms.max_iter;
+    //Smooth iteration count, |z| and its angle, points that didn't escape get max_iter
+    if raw_mode() {
+        if coords.x == 69.0 && coords.y == 4200.0 {
+            return vec3<f32>(f32(max_iteration), 0.0, 0.0);
+        }
+        var i = f32(max_iteration);
+        if iter < max_iteration {
+            i = f32(iter) - log2(log2(dot(coords, coords))) + 4.0;
+        }
+        return vec3<f32>(i, length(coords), atan2(coords.y, coords.x));
+    }
     if iter >= max_iteration {
         return vec3<f32>(0.0);
     }
@@ -66,17 +116,19 @@ fn fractal(C: vec2<f32>) -> vec3<f32> {
     } else if (uniforms.flags & (2u << 30u)) != 0u {
         i = i - log2(log2(dot(coords, coords))) + 4.0;
     }
-    return get_color(C, i, max_iteration);
+    return get_color(uv, i, max_iteration);
 }
 
 @fragment
 fn main(in: VertexOutput) -> @location(0) vec4<f32> {
     let msaa = f32(uniforms.flags & 255u);
 
-    let uv = (in.uv / vec2<f32>(uniforms.aspect, 1.0));
+    //Tiles only cover a part of the image, so map their uv onto the whole one
+    let image_uv = in.uv * uniforms.viewport.xy + uniforms.viewport.zw;
+    let uv = rotate(image_uv / vec2<f32>(uniforms.aspect, 1.0));
     let transformed_uv = uv / uniforms.zoom + uniforms.position;
-    //Display debug info
-    if (uniforms.flags & (2u << 29u)) != 0u {
+    //Display debug info, the grid doesn't mean anything in deep zoom mode
+    if (uniforms.flags & (2u << 29u)) != 0u && !deep_mode() {
         if length(uv) < 0.025 {
             return vec4<f32>(0.0, 0.0, 1.0, 1.0);
         }
@@ -91,8 +143,13 @@ fn main(in: VertexOutput) -> @location(0) vec4<f32> {
     var col = vec3<f32>(0.0);
     for (var i = 0.0; i < msaa; i += 1.0) {
         let dxy = vec2<f32>(rand(i * .1234), rand(i * .5678)) / 10000.0;
-        let transformed_uv = (uv + dxy) / uniforms.zoom + uniforms.position;
-        col += fractal(transformed_uv);
+        if deep_mode() {
+            //The position is baked into the reference orbit, so only the offset from it is needed
+            col += deep_fractal((uv + dxy) / uniforms.zoom);
+        } else {
+            let transformed_uv = (uv + dxy) / uniforms.zoom + uniforms.position;
+            col += fractal(transformed_uv);
+        }
     }
 
 
@@ -0,0 +1,94 @@
+//Complex number helpers used by custom formulas
+fn complex_mul(a: vec2<f32>, b: vec2<f32>) -> vec2<f32> {
+    return vec2<f32>(a.x * b.x - a.y * b.y, a.x * b.y + a.y * b.x);
+}
+
+fn complex_square(z: vec2<f32>) -> vec2<f32> {
+    return vec2<f32>(z.x * z.x - z.y * z.y, 2.0 * z.x * z.y);
+}
+
+fn complex_div(a: vec2<f32>, b: vec2<f32>) -> vec2<f32> {
+    let denumenator = 1.0 / (b.x * b.x + b.y * b.y);
+    //Multiplying should be a bit faster
+    return vec2<f32>((a.x * b.x + a.y * b.y) * denumenator, (a.y * b.x - a.x * b.y) * denumenator);
+}
+
+fn complex_exp(z: vec2<f32>) -> vec2<f32> {
+    return exp(z.x) * vec2<f32>(cos(z.y), sin(z.y));
+}
+
+fn complex_log(z: vec2<f32>) -> vec2<f32> {
+    return vec2<f32>(log(length(z)), atan2(z.y, z.x));
+}
+
+fn complex_sqrt(z: vec2<f32>) -> vec2<f32> {
+    let r = sqrt(length(z));
+    let angle = atan2(z.y, z.x) * 0.5;
+    return r * vec2<f32>(cos(angle), sin(angle));
+}
+
+fn complex_sin(z: vec2<f32>) -> vec2<f32> {
+    return vec2<f32>(sin(z.x) * cosh(z.y), cos(z.x) * sinh(z.y));
+}
+
+fn complex_cos(z: vec2<f32>) -> vec2<f32> {
+    return vec2<f32>(cos(z.x) * cosh(z.y), -sin(z.x) * sinh(z.y));
+}
+
+fn complex_tan(z: vec2<f32>) -> vec2<f32> {
+    return complex_div(complex_sin(z), complex_cos(z));
+}
+
+fn complex_sinh(z: vec2<f32>) -> vec2<f32> {
+    return vec2<f32>(sinh(z.x) * cos(z.y), cosh(z.x) * sin(z.y));
+}
+
+fn complex_cosh(z: vec2<f32>) -> vec2<f32> {
+    return vec2<f32>(cosh(z.x) * cos(z.y), sinh(z.x) * sin(z.y));
+}
+
+fn complex_tanh(z: vec2<f32>) -> vec2<f32> {
+    return complex_div(complex_sinh(z), complex_cosh(z));
+}
+
+fn complex_pow(a: vec2<f32>, b: vec2<f32>) -> vec2<f32> {
+    if a.x == 0.0 && a.y == 0.0 {
+        return vec2<f32>(0.0);
+    }
+    return complex_exp(complex_mul(b, complex_log(a)));
+}
+
+//Exponentiation by squaring
+fn complex_powi(z: vec2<f32>, n: i32) -> vec2<f32> {
+    var result = vec2<f32>(1.0, 0.0);
+    var base = z;
+    var power = abs(n);
+    while power > 0 {
+        if (power & 1) != 0 {
+            result = complex_mul(result, base);
+        }
+        base = complex_square(base);
+        power = power >> 1u;
+    }
+    if n < 0 {
+        return complex_div(vec2<f32>(1.0, 0.0), result);
+    }
+    return result;
+}
+
+fn real_powi(x: f32, n: i32) -> f32 {
+    var result = 1.0;
+    var base = x;
+    var power = abs(n);
+    while power > 0 {
+        if (power & 1) != 0 {
+            result *= base;
+        }
+        base *= base;
+        power = power >> 1u;
+    }
+    if n < 0 {
+        return 1.0 / result;
+    }
+    return result;
+}
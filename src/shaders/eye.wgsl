@@ -11,7 +11,7 @@ fn complex_div(a: vec2<f32>, b: vec2<f32>) -> vec2<f32> {
 }
 
 fn fractal_func(z: vec2<f32>, c: vec2<f32>) -> vec2<f32> {
-    if c.x < -1.34 || c.x > 4.0 || abs(c.y) > 1.65 {
+    if !julia_mode() && (c.x < -1.34 || c.x > 4.0 || abs(c.y) > 1.65) {
         return vec2<f32>(69.0, 4200.0);
     }
     return complex_square(complex_div(z, c)) + c;
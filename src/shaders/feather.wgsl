@@ -13,7 +13,7 @@ fn complex_cube(z: vec2<f32>) -> vec2<f32> {
 }
 
 fn fractal_func(z: vec2<f32>, c: vec2<f32>) -> vec2<f32> {
-    if length(c) < 0.53 {
+    if !julia_mode() && length(c) < 0.53 {
         return vec2<f32>(69.0, 4200.0);
     }
     return complex_div(complex_cube(z), (vec2<f32>(1.0, 0.0) + (z * z))) + c;
@@ -0,0 +1,4 @@
+//This fractal doesn't have a perturbation formula, so deep zoom requests are rejected before rendering
+fn deep_fractal(offset: vec2<f32>) -> vec3<f32> {
+    return vec3<f32>(0.0);
+}
@@ -0,0 +1,57 @@
+//Deep zoom using perturbation theory, see https://mathr.co.uk/blog/2021-05-14_deep_zoom_theory_and_practice.html
+//Only the difference from the reference orbit, which is computed in high precision on the cpu,
+//is iterated. While that difference is too small for an f32 it's stored scaled by 2^scale.
+fn deep_fractal(offset: vec2<f32>) -> vec3<f32> {
+    let max_iteration = uniforms.max_iter;
+    let orbit_len = uniforms.orbit_len;
+    var scale = uniforms.deep_exponent;
+    var dc = offset;
+    var dz = vec2<f32>(0.0);
+    var z = vec2<f32>(0.0);
+    var m = 0u;
+    var iter = 0u;
+
+    while iter < max_iteration {
+        dz = perturb_func(orbit[m], dz, dc, exp2(f32(scale)));
+        m += 1u;
+        iter += 1u;
+
+        if scale < 0 {
+            let magnitude = max(abs(dz.x), abs(dz.y));
+            if magnitude > 0.0 {
+                let e = i32(floor(log2(magnitude)));
+                var factor = exp2(f32(-e));
+                if scale + e > -40 {
+                    //Big enough to be stored as is from now on
+                    factor = exp2(f32(scale));
+                    scale = 0;
+                } else {
+                    //Keep the scaled difference around 1, so that it neither overflows nor underflows
+                    scale += e;
+                }
+                dz *= factor;
+                dc *= factor;
+            }
+        }
+
+        if scale < 0 {
+            //The difference is still negligible, so the pixel behaves just like the reference
+            z = orbit[m];
+            if dot(z, z) > max_dot || m + 1u >= orbit_len {
+                break;
+            }
+        } else {
+            z = orbit[m] + dz;
+            if dot(z, z) > max_dot {
+                break;
+            }
+            //Rebase to the start of the orbit when the pixel gets closer to 0 than the difference,
+            //or when the reference runs out. This is what avoids glitches
+            if dot(z, z) < dot(dz, dz) || m + 1u >= orbit_len {
+                dz = z;
+                m = 0u;
+            }
+        }
+    }
+    return escape_color(offset, iter, z);
+}
@@ -4,6 +4,15 @@ fn complex_square(z: vec2<f32>) -> vec2<f32> {
     return vec2<f32>(z.x * z.x - z.y * z.y, 2.0 * z.x * z.y);
 }
 
+fn complex_mul(a: vec2<f32>, b: vec2<f32>) -> vec2<f32> {
+    return vec2<f32>(a.x * b.x - a.y * b.y, a.x * b.y + a.y * b.x);
+}
+
+//Same as the mandelbrot one, but conjugated, with dz and dc scaled by 1/s
+fn perturb_func(z: vec2<f32>, dz: vec2<f32>, dc: vec2<f32>, s: f32) -> vec2<f32> {
+    return complex_mul(2.0 * z + s * dz, dz) * vec2<f32>(1.0, -1.0) + dc;
+}
+
 fn fractal_func(z: vec2<f32>, c: vec2<f32>) -> vec2<f32> {
     return complex_square(z * vec2<f32>(1.0, -1.0)) + c;
 }
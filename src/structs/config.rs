@@ -0,0 +1,55 @@
+use std::{env, path::PathBuf, time::Duration};
+
+use crate::grimoire;
+
+///Settings read from the environment, `.env` is loaded before this
+#[derive(Debug, Clone)]
+pub struct Config {
+    ///Maximum size of the render cache in bytes
+    pub cache_size: u64,
+    ///How long rendered images stay in the cache, forever if not set
+    pub cache_ttl: Option<Duration>,
+    ///Directory of the disk cache, it's disabled if this isn't set
+    pub disk_cache_dir: Option<PathBuf>,
+    ///Maximum size of the disk cache in bytes
+    pub disk_cache_size: u64,
+    ///Token needed for the admin endpoints, they're disabled if it's not set
+    pub admin_token: Option<String>,
+    ///How many background jobs render at the same time
+    pub job_workers: usize,
+    ///How long finished jobs and their results are kept
+    pub job_retention: Duration,
+    ///Maximum size of the results of finished jobs in bytes, the oldest ones are dropped first
+    pub job_results_size: u64,
+}
+
+impl Config {
+    pub fn from_env() -> Self {
+        Self {
+            cache_size: env::var("CACHE_SIZE").map_or(grimoire::DEFAULT_CACHE_SIZE, |v| {
+                v.parse().expect("Invalid cache size")
+            }),
+            cache_ttl: env::var("CACHE_TTL")
+                .ok()
+                .map(|v| Duration::from_secs(v.parse().expect("Invalid cache ttl"))),
+            disk_cache_dir: env::var("DISK_CACHE_DIR").ok().map(PathBuf::from),
+            disk_cache_size: env::var("DISK_CACHE_SIZE")
+                .map_or(grimoire::DEFAULT_DISK_CACHE_SIZE, |v| {
+                    v.parse().expect("Invalid disk cache size")
+                }),
+            admin_token: env::var("ADMIN_TOKEN").ok().filter(|t| !t.is_empty()),
+            job_workers: env::var("JOB_WORKERS").map_or(grimoire::DEFAULT_JOB_WORKERS, |v| {
+                v.parse().expect("Invalid number of job workers")
+            }),
+            job_retention: Duration::from_secs(
+                env::var("JOB_RETENTION").map_or(grimoire::DEFAULT_JOB_RETENTION, |v| {
+                    v.parse().expect("Invalid job retention")
+                }),
+            ),
+            job_results_size: env::var("JOB_RESULTS_SIZE")
+                .map_or(grimoire::DEFAULT_JOB_RESULTS_SIZE, |v| {
+                    v.parse().expect("Invalid job results size")
+                }),
+        }
+    }
+}
@@ -0,0 +1,210 @@
+use std::fmt;
+
+use actix_web::{
+    error::{JsonPayloadError, PathError, QueryPayloadError},
+    http::StatusCode,
+    web, HttpRequest, HttpResponse, ResponseError,
+};
+use serde_json::json;
+
+use super::requests::SimplifiedFractals;
+
+///Everything that can go wrong with a request, sent back as json with a `code`, a `message`, and
+///for invalid parameters the `field` and what values are `allowed`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ApiError {
+    ///A number outside of the values it can take
+    OutOfRange {
+        field: &'static str,
+        min: u64,
+        max: u64,
+    },
+    ///A list with too few or too many entries
+    CountOutOfRange {
+        field: &'static str,
+        min: u64,
+        max: u64,
+    },
+    ///A parameter that can't be parsed or doesn't work with the others
+    InvalidParameter {
+        field: &'static str,
+        message: String,
+    },
+    ///A parameter that's needed for what the rest of the request asks for
+    MissingParameter {
+        field: &'static str,
+        message: String,
+    },
+    UnknownFractal(String),
+    ///The query or body can't be read, or the request as a whole is too big
+    BadRequest(String),
+    NotAcceptable(String),
+    NotFound(String),
+    Gone(String),
+    Unavailable(String),
+    ///Logged instead of sent back, so the message is empty unless it's safe to show
+    Internal(String),
+}
+
+impl ApiError {
+    pub const fn code(&self) -> &'static str {
+        match self {
+            Self::OutOfRange { .. } | Self::CountOutOfRange { .. } => "out_of_range",
+            Self::InvalidParameter { .. } => "invalid_parameter",
+            Self::MissingParameter { .. } => "missing_parameter",
+            Self::UnknownFractal(_) => "unknown_fractal",
+            Self::BadRequest(_) => "bad_request",
+            Self::NotAcceptable(_) => "not_acceptable",
+            Self::NotFound(_) => "not_found",
+            Self::Gone(_) => "gone",
+            Self::Unavailable(_) => "unavailable",
+            Self::Internal(_) => "internal_error",
+        }
+    }
+
+    pub const fn status(&self) -> StatusCode {
+        match self {
+            Self::OutOfRange { .. }
+            | Self::CountOutOfRange { .. }
+            | Self::InvalidParameter { .. }
+            | Self::MissingParameter { .. }
+            | Self::BadRequest(_) => StatusCode::BAD_REQUEST,
+            Self::UnknownFractal(_) | Self::NotFound(_) => StatusCode::NOT_FOUND,
+            Self::NotAcceptable(_) => StatusCode::NOT_ACCEPTABLE,
+            Self::Gone(_) => StatusCode::GONE,
+            Self::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            Self::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    pub const fn field(&self) -> Option<&'static str> {
+        match self {
+            Self::OutOfRange { field, .. }
+            | Self::CountOutOfRange { field, .. }
+            | Self::InvalidParameter { field, .. }
+            | Self::MissingParameter { field, .. } => Some(field),
+            Self::UnknownFractal(_) => Some("fractal"),
+            _ => None,
+        }
+    }
+
+    pub fn body(&self) -> serde_json::Value {
+        let mut body = json!({
+            "code": self.code(),
+            "message": self.to_string(),
+        });
+        if let Some(field) = self.field() {
+            body["field"] = json!(field);
+        }
+        match self {
+            Self::OutOfRange { min, max, .. } | Self::CountOutOfRange { min, max, .. } => {
+                body["allowed"] = json!({ "min": min, "max": max });
+            }
+            Self::UnknownFractal(_) => body["allowed"] = json!(SimplifiedFractals::ALL),
+            _ => {}
+        }
+        body
+    }
+
+    ///Checks that `value` is between `min` and `max`, both included
+    pub fn check_range(
+        field: &'static str,
+        value: impl Into<u64>,
+        min: u64,
+        max: u64,
+    ) -> Result<(), Self> {
+        if (min..=max).contains(&value.into()) {
+            Ok(())
+        } else {
+            Err(Self::OutOfRange { field, min, max })
+        }
+    }
+
+    pub fn invalid(field: &'static str, message: impl Into<String>) -> Self {
+        Self::InvalidParameter {
+            field,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::OutOfRange { field, min, max } => {
+                write!(f, "{field} should be between {min} and {max}")
+            }
+            Self::CountOutOfRange { field, min, max } => {
+                write!(f, "{field} should have between {min} and {max} entries")
+            }
+            Self::UnknownFractal(name) => write!(f, "There's no fractal called {name}"),
+            Self::Internal(message) if message.is_empty() => {
+                write!(f, "{}", self.status().canonical_reason().unwrap_or("Error"))
+            }
+            Self::InvalidParameter { message, .. }
+            | Self::MissingParameter { message, .. }
+            | Self::BadRequest(message)
+            | Self::NotAcceptable(message)
+            | Self::NotFound(message)
+            | Self::Gone(message)
+            | Self::Unavailable(message)
+            | Self::Internal(message) => write!(f, "{message}"),
+        }
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        self.status()
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status()).json(self.body())
+    }
+}
+
+///Sends path errors back as json, an unknown fractal gets its own error with the fractals there are
+pub fn path_config() -> web::PathConfig {
+    web::PathConfig::default().error_handler(|e: PathError, req: &HttpRequest| {
+        let error = match req.match_info().get("fractal") {
+            Some(name) if serde_json::from_value::<SimplifiedFractals>(json!(name)).is_err() => {
+                ApiError::UnknownFractal(name.to_string())
+            }
+            _ => ApiError::BadRequest(e.to_string()),
+        };
+        error.into()
+    })
+}
+
+pub fn query_config() -> web::QueryConfig {
+    web::QueryConfig::default()
+        .error_handler(|e: QueryPayloadError, _| ApiError::BadRequest(e.to_string()).into())
+}
+
+pub fn json_config() -> web::JsonConfig {
+    web::JsonConfig::default()
+        .error_handler(|e: JsonPayloadError, _| ApiError::BadRequest(e.to_string()).into())
+}
+
+#[test]
+fn test_error_body() {
+    let error = ApiError::check_range("msaa", 0u8, 1, 16).unwrap_err();
+    assert_eq!(
+        error.body(),
+        json!({
+            "code": "out_of_range",
+            "message": "msaa should be between 1 and 16",
+            "field": "msaa",
+            "allowed": {"min": 1, "max": 16},
+        })
+    );
+    assert!(ApiError::check_range("msaa", 16u8, 1, 16).is_ok());
+
+    let error = ApiError::UnknownFractal("Julia".to_string());
+    assert_eq!(error.status(), StatusCode::NOT_FOUND);
+    assert_eq!(error.body()["allowed"][0], "Mandelbrot");
+    assert_eq!(
+        ApiError::Internal(String::new()).to_string(),
+        "Internal Server Error"
+    );
+}
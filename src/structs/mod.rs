@@ -1,3 +1,7 @@
+///Settings read from the environment
+pub mod config;
+///Errors sent back to the user as json
+pub mod errors;
 ///Internally used structs and enums
 pub mod rendering;
 ///Structs and enums used as request params/bodies
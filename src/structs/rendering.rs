@@ -1,21 +1,180 @@
 // #![allow(dead_code)]
-use std::sync::Mutex;
+#![allow(clippy::cast_precision_loss, clippy::cast_possible_truncation)]
+use std::sync::{
+    atomic::{AtomicBool, AtomicU64, Ordering},
+    mpsc, Arc, Mutex,
+};
+
+use futures::channel::oneshot;
+
+use super::requests::OutputFormat;
+use crate::{
+    grimoire,
+    utils::{
+        cache::LruCache,
+        perturbation::{self, DeepZoom},
+    },
+};
 
 ///Stores all need wgpu structs in the api state
 pub struct GpuStructs {
     pub queue: wgpu::Queue,
-    pub device: wgpu::Device,
-    pub staging_belt: Mutex<wgpu::util::StagingBelt>,
+    pub device: Arc<wgpu::Device>,
+    ///Wakes up the thread that polls the device
+    poller: mpsc::Sender<()>,
+}
+
+impl GpuStructs {
+    ///Spawns a thread that drives the device, it stops once this is dropped
+    pub fn new(device: wgpu::Device, queue: wgpu::Queue) -> Self {
+        let device = Arc::new(device);
+        let (poller, wake_ups) = mpsc::channel::<()>();
+        //Weak, so that the device is dropped along with the rest of the structs instead of on this
+        //thread, which breaks the other gl contexts
+        let driver = Arc::downgrade(&device);
+        std::thread::Builder::new()
+            .name("gpu driver".to_string())
+            .spawn(move || {
+                //Waiting for the submitted work is what calls the map callbacks
+                for () in wake_ups {
+                    let Some(device) = driver.upgrade() else {
+                        break;
+                    };
+                    device.poll(wgpu::Maintain::Wait);
+                }
+            })
+            .expect("Unable to spawn the gpu driver thread");
+        Self {
+            queue,
+            device,
+            poller,
+        }
+    }
+
+    ///Maps the buffer for reading, without blocking the thread while the gpu is working
+    pub async fn map_read(&self, slice: wgpu::BufferSlice<'_>) -> Result<(), String> {
+        let (sender, receiver) = oneshot::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        self.poller
+            .send(())
+            .map_err(|_| "The gpu driver thread has stopped")?;
+        receiver
+            .await
+            .map_err(|_| "Buffer mapping was canceled")?
+            .map_err(|e| e.to_string())
+    }
+
+    ///Waits until the gpu has finished everything submitted so far, without blocking the thread
+    pub async fn wait_submitted(&self) -> Result<(), String> {
+        let (sender, receiver) = oneshot::channel();
+        self.queue.on_submitted_work_done(move || {
+            let _ = sender.send(());
+        });
+        self.poller
+            .send(())
+            .map_err(|_| "The gpu driver thread has stopped")?;
+        receiver
+            .await
+            .map_err(|_| "Waiting for the gpu was canceled".to_string())
+    }
 }
 
-//I'm gonna hard code the buffers for now, tho it may be a good idea to not do it
+///The renderer used by the api
+pub enum Backend {
+    Gpu(GpuStructs),
+    ///Used when there's no usable gpu adapter, or when it's forced with `RENDERER=cpu`
+    Cpu,
+}
+
+///Everything needed to render an image, shared between the backends
+#[derive(Debug, Clone)]
+pub struct RenderRequest {
+    pub fractal: Fractals,
+    pub width: u32,
+    pub height: u32,
+    pub uniforms: ShaderDataUniforms,
+    pub colors: Vec<wgpu::Color>,
+    pub deep_zoom: Option<DeepZoom>,
+}
+
+impl RenderRequest {
+    ///Whether it outputs the raw escape time data instead of colors
+    pub const fn raw(&self) -> bool {
+        self.uniforms.flags & grimoire::rendering_flags::RAW != 0
+    }
+
+    pub const fn texture_format(&self) -> wgpu::TextureFormat {
+        if self.raw() {
+            grimoire::RAW_FORMAT
+        } else {
+            grimoire::FORMAT
+        }
+    }
+
+    ///Rgba bytes, or 4 floats for the raw data
+    pub const fn bytes_per_pixel(&self) -> usize {
+        if self.raw() {
+            16
+        } else {
+            4
+        }
+    }
+}
+
+///How many pixels of a render are done, updated by the renderer while it works so that it can be
+///reported elsewhere
+#[derive(Debug, Default)]
+pub struct RenderProgress {
+    done: AtomicU64,
+    total: u64,
+    ///Checked by the renderer between bands and rows, since a render on another thread can't be
+    ///aborted from the outside
+    canceled: AtomicBool,
+}
+
+impl RenderProgress {
+    pub const fn new(total: u64) -> Self {
+        Self {
+            done: AtomicU64::new(0),
+            total,
+            canceled: AtomicBool::new(false),
+        }
+    }
+
+    pub fn advance(&self, pixels: u64) {
+        self.done.fetch_add(pixels, Ordering::Relaxed);
+    }
+
+    ///Makes the render stop at the next band or row, it then returns an error
+    pub fn cancel(&self) {
+        self.canceled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_canceled(&self) -> bool {
+        self.canceled.load(Ordering::Relaxed)
+    }
+
+    ///From 0 to 1
+    pub fn fraction(&self) -> f32 {
+        if self.total == 0 {
+            return 0.0;
+        }
+        (self.done.load(Ordering::Relaxed) as f64 / self.total as f64).min(1.0) as f32
+    }
+}
+
+///The buffers themselves are created for every render, so that renders can run at the same time
+///without overwriting each others data
 pub struct PipelineBufers {
     pub pipeline: wgpu::RenderPipeline,
-    pub info_buffer: wgpu::Buffer,
-    pub storage_buffer: wgpu::Buffer,
-    pub bind_group: wgpu::BindGroup,
+    ///Uniforms, colors and the reference orbit used for deep zoom
+    pub bind_group_layout: wgpu::BindGroupLayout,
 }
 
+pub type RawUniforms = [u32; 20];
+
 ///Uniforms used by the shader
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
@@ -37,6 +196,19 @@ pub struct ShaderDataUniforms {
     pub num_colors: u32,
     ///First 4 bits for msaa, the rest are flags from the grimoire
     pub flags: u32,
+    ///The constant used when rendering julia sets
+    pub julia: [f32; 2],
+    ///Power of 2 the pixel offsets are scaled by in deep zoom mode
+    pub deep_exponent: i32,
+    ///Length of the reference orbit in deep zoom mode
+    pub orbit_len: u32,
+    ///Maps the uv of the rendered texture onto the whole image, as scale x, scale y, offset x,
+    ///offset y. Used for rendering big images in tiles
+    pub viewport: [f32; 4],
+    ///Shifts the colors by this many stripes, used for palette cycling
+    pub color_offset: f32,
+    ///Cosine and sine of the rotation of the view
+    pub rotation: [f32; 2],
 }
 
 impl Default for ShaderDataUniforms {
@@ -49,12 +221,19 @@ impl Default for ShaderDataUniforms {
             max_iter: 1000,
             num_colors: 200,
             flags: 1,
+            julia: [-0.8, 0.156],
+            deep_exponent: 0,
+            orbit_len: 0,
+            viewport: [1.0, 1.0, 0.0, 0.0],
+            color_offset: 0.0,
+            rotation: [1.0, 0.0],
         }
     }
 }
 
 impl ShaderDataUniforms {
-    pub fn raw(&self) -> [u32; 8] {
+    ///Padded to the size of the struct in the shader, which is a multiple of 16 bytes
+    pub fn raw(&self) -> RawUniforms {
         [
             self.position[0].to_bits(),
             self.position[1].to_bits(),
@@ -64,12 +243,196 @@ impl ShaderDataUniforms {
             self.max_iter,
             self.num_colors,
             self.flags,
+            self.julia[0].to_bits(),
+            self.julia[1].to_bits(),
+            bytemuck::cast(self.deep_exponent),
+            self.orbit_len,
+            self.viewport[0].to_bits(),
+            self.viewport[1].to_bits(),
+            self.viewport[2].to_bits(),
+            self.viewport[3].to_bits(),
+            self.color_offset.to_bits(),
+            //Vec2 is aligned to 8 bytes
+            0,
+            self.rotation[0].to_bits(),
+            self.rotation[1].to_bits(),
         ]
     }
 }
 
+///Deep zoom coordinates, as normalized decimal strings
+#[derive(Debug, Clone, PartialEq, Eq, Hash, serde_derive::Serialize)]
+pub struct DeepParameters {
+    pub x: String,
+    pub y: String,
+    pub zoom: String,
+}
+
+///Request parameters with all the defaults applied, so that requests for the same image are equal.
+///Used as the cache key, and the only thing the uniforms are built from
+#[derive(Debug, Clone, PartialEq, serde_derive::Serialize)]
+pub struct RenderParameters {
+    pub fractal: Fractals,
+    pub width: u32,
+    pub height: u32,
+    pub colors: Vec<wgpu::Color>,
+    pub max_iterations: u32,
+    pub num_colors: u32,
+    ///Between 0 and the number of colors
+    pub color_offset: f32,
+    ///Counter-clockwise rotation of the view in radians, between 0 and 2π
+    pub rotation: f32,
+    ///Not used in deep zoom mode
+    pub zoom: f32,
+    ///Not used in deep zoom mode
+    pub position: [f32; 2],
+    ///Msaa in the lowest byte, the rest are flags from the grimoire
+    pub flags: u32,
+    ///Only used for julia sets
+    pub julia: [f32; 2],
+    pub deep: Option<DeepParameters>,
+    pub format: OutputFormat,
+    ///Only used for jpeg
+    pub quality: u8,
+}
+
+impl Eq for RenderParameters {}
+
+impl std::hash::Hash for RenderParameters {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.fractal.hash(state);
+        self.width.hash(state);
+        self.height.hash(state);
+        for color in &self.colors {
+            [color.r, color.g, color.b, color.a]
+                .map(f64::to_bits)
+                .hash(state);
+        }
+        self.max_iterations.hash(state);
+        self.num_colors.hash(state);
+        self.color_offset.to_bits().hash(state);
+        self.rotation.to_bits().hash(state);
+        self.zoom.to_bits().hash(state);
+        self.position.map(f32::to_bits).hash(state);
+        self.flags.hash(state);
+        self.julia.map(f32::to_bits).hash(state);
+        self.deep.hash(state);
+        self.format.hash(state);
+        self.quality.hash(state);
+    }
+}
+
+impl RenderParameters {
+    ///Computes the reference orbit if it's needed and builds the uniforms, the endpoints use
+    ///[`crate::utils::graphics::render_request`] instead so that they don't block
+    #[cfg(test)]
+    pub fn to_render_request(&self) -> Result<RenderRequest, String> {
+        Ok(self.to_render_request_with(self.deep_zoom()?))
+    }
+
+    ///Computes the reference orbit of a deep zoom, which can take a while. It only depends on the
+    ///view, so it's the same at every size
+    pub fn deep_zoom(&self) -> Result<Option<DeepZoom>, String> {
+        self.deep
+            .as_ref()
+            .map(|deep| {
+                perturbation::prepare(
+                    &self.fractal,
+                    &deep.x,
+                    &deep.y,
+                    &deep.zoom,
+                    self.max_iterations,
+                )
+            })
+            .transpose()
+    }
+
+    ///Builds the uniforms around the orbit from [`RenderParameters::deep_zoom`]
+    pub fn to_render_request_with(&self, deep_zoom: Option<DeepZoom>) -> RenderRequest {
+        let uniforms = ShaderDataUniforms {
+            aspect: self.height as f32 / self.width as f32,
+            arr_len: self.colors.len() as u32,
+            max_iter: self.max_iterations,
+            num_colors: self.num_colors,
+            color_offset: self.color_offset,
+            //Negated like the y axis
+            rotation: [self.rotation.cos(), -self.rotation.sin()],
+            zoom: deep_zoom.as_ref().map_or(self.zoom, |d| d.zoom_mantissa),
+            //The y axis is flipped in the shader
+            position: [self.position[0], -self.position[1]],
+            flags: self.flags,
+            julia: [self.julia[0], -self.julia[1]],
+            deep_exponent: deep_zoom.as_ref().map_or(0, |d| d.scale_exponent),
+            orbit_len: deep_zoom.as_ref().map_or(0, |d| d.orbit.len() as u32),
+            //The whole image
+            viewport: [1.0, 1.0, 0.0, 0.0],
+        };
+
+        RenderRequest {
+            fractal: self.fractal.clone(),
+            width: self.width,
+            height: self.height,
+            uniforms,
+            colors: self.colors.clone(),
+            deep_zoom,
+        }
+    }
+
+    ///A part of this image as x, y, width, height in pixels, rendered at a different size. Used
+    ///for images that are too big to render at once, doesn't work in deep zoom mode
+    pub fn region(&self, [x, y, width, height]: [f64; 4], size: [u32; 2]) -> Self {
+        let (full_width, full_height) = (f64::from(self.width), f64::from(self.height));
+        let zoom = f64::from(self.zoom);
+        //Offset of the center of the region from the center of the image, before rotating
+        let offset = [
+            ((x + width / 2.0) / full_width * 2.0 - 1.0) * full_width / full_height / zoom,
+            //The y axis goes down in images
+            -((y + height / 2.0) / full_height * 2.0 - 1.0) / zoom,
+        ];
+        let (sin, cos) = f64::from(self.rotation).sin_cos();
+        Self {
+            width: size[0],
+            height: size[1],
+            position: [
+                (f64::from(self.position[0]) + offset[0] * cos - offset[1] * sin) as f32,
+                (f64::from(self.position[1]) + offset[0] * sin + offset[1] * cos) as f32,
+            ],
+            zoom: (zoom * full_height / height) as f32,
+            ..self.clone()
+        }
+    }
+
+    ///Part of the complex plane that's visible as x min, x max, y min, y max. Not known for deep
+    ///zoom and rotated views
+    pub fn bounds(&self) -> Option<[f32; 4]> {
+        if self.deep.is_some() || self.rotation != 0.0 {
+            return None;
+        }
+        let half_height = 1.0 / self.zoom;
+        let half_width = half_height * self.width as f32 / self.height as f32;
+        Some([
+            self.position[0] - half_width,
+            self.position[0] + half_width,
+            self.position[1] - half_height,
+            self.position[1] + half_height,
+        ])
+    }
+
+    ///The same view as raw escape time data, so that it can be colored later without iterating
+    ///again, see [`crate::utils::cpu::recolor`]
+    pub fn to_raw_field(&self) -> Self {
+        Self {
+            flags: 1
+                | grimoire::rendering_flags::RAW
+                | self.flags & grimoire::rendering_flags::JULIA
+                | self.flags & grimoire::rendering_flags::DEEP,
+            ..self.clone()
+        }
+    }
+}
+
 ///Represents types of fractals that the api can render
-#[derive(Eq, Hash, PartialEq, Debug, Clone)]
+#[derive(Eq, Hash, PartialEq, Debug, Clone, serde_derive::Serialize)]
 pub enum Fractals {
     Mandelbrot,
     BurningShip,
@@ -92,5 +455,31 @@ impl std::fmt::Display for Fractals {
     }
 }
 
-///A helper type for the api state
-pub type PipelineStore = Mutex<Vec<(Fractals, PipelineBufers)>>;
+impl Fractals {
+    ///Whether the fractal has a perturbation formula, needed for deep zoom
+    pub const fn supports_deep_zoom(&self) -> bool {
+        matches!(self, Self::Mandelbrot | Self::Tricorn)
+    }
+}
+
+type PipelineKey<K> = (K, wgpu::TextureFormat);
+
+///The pipelines in the api state
+///
+///Pipelines are behind an `Arc`, so that the locks are only held while looking them up. They're
+///specific to the texture format they render into
+pub struct PipelineStore {
+    pub builtin: Mutex<Vec<(PipelineKey<Fractals>, Arc<PipelineBufers>)>>,
+    ///Keyed on the generated shader, so formulas that only differ in how they're written share a
+    ///pipeline. Anyone can send a new formula, so only the most recently used ones are kept
+    pub custom: Mutex<LruCache<PipelineKey<String>, Arc<PipelineBufers>>>,
+}
+
+impl Default for PipelineStore {
+    fn default() -> Self {
+        Self {
+            builtin: Mutex::new(Vec::new()),
+            custom: Mutex::new(LruCache::new(grimoire::MAX_CUSTOM_PIPELINES, None)),
+        }
+    }
+}
@@ -1,10 +1,28 @@
 use std::sync::Mutex;
 
-use super::rendering::Fractals;
+use actix_web::http::header::{Accept, Quality};
+
+use super::{
+    errors::ApiError,
+    rendering::{DeepParameters, Fractals, RenderParameters},
+};
+use crate::{
+    formula, grimoire,
+    utils::{
+        animation::{keyframes, Keyframe},
+        cache::LruCache,
+        coalesce::Coalescer,
+        fixed::normalize_decimal,
+        graphics::vec_from_hex,
+        jobs::JobQueue,
+    },
+};
 
 ///Represents types of fractals that the api can render, simplified so that serde can deserialize
 ///all of them
-#[derive(Debug, Clone, Copy, serde_derive::Deserialize, PartialEq, Eq, Hash)]
+#[derive(
+    Debug, Clone, Copy, serde_derive::Deserialize, serde_derive::Serialize, PartialEq, Eq, Hash,
+)]
 pub enum SimplifiedFractals {
     Mandelbrot,
     BurningShip,
@@ -15,6 +33,15 @@ pub enum SimplifiedFractals {
 }
 
 impl SimplifiedFractals {
+    pub const ALL: [Self; 6] = [
+        Self::Mandelbrot,
+        Self::BurningShip,
+        Self::Tricorn,
+        Self::Feather,
+        Self::Eye,
+        Self::Custom,
+    ];
+
     pub fn into_fractals(self, formula: Option<String>) -> Fractals {
         match self {
             Self::Mandelbrot => Fractals::Mandelbrot,
@@ -30,7 +57,132 @@ impl SimplifiedFractals {
     }
 }
 
-#[derive(Debug, Clone, serde_derive::Deserialize, PartialEq)]
+///Formats that the response can be encoded in
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    serde_derive::Deserialize,
+    serde_derive::Serialize,
+    PartialEq,
+    Eq,
+    Hash,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    #[default]
+    Png,
+    #[serde(alias = "jpg")]
+    Jpeg,
+    Webp,
+    Bmp,
+    Tiff,
+    Qoi,
+    ///Uncompressed binary ppm
+    Ppm,
+    ///The rest return the raw escape time data instead of colors, see
+    ///[`crate::utils::export::raw_to_file`]
+    Npy,
+    Exr,
+    Json,
+}
+
+impl OutputFormat {
+    pub const ALL: [Self; 10] = [
+        Self::Png,
+        Self::Jpeg,
+        Self::Webp,
+        Self::Bmp,
+        Self::Tiff,
+        Self::Qoi,
+        Self::Ppm,
+        Self::Npy,
+        Self::Exr,
+        Self::Json,
+    ];
+
+    pub const fn mime(self) -> &'static str {
+        match self {
+            Self::Png => "image/png",
+            Self::Jpeg => "image/jpeg",
+            Self::Webp => "image/webp",
+            Self::Bmp => "image/bmp",
+            Self::Tiff => "image/tiff",
+            Self::Qoi => "image/qoi",
+            Self::Ppm => "image/x-portable-pixmap",
+            Self::Npy => "application/x-npy",
+            Self::Exr => "image/x-exr",
+            Self::Json => "application/json",
+        }
+    }
+
+    ///Extension used for files and iiif urls
+    pub const fn extension(self) -> &'static str {
+        match self {
+            Self::Png => "png",
+            Self::Jpeg => "jpg",
+            Self::Webp => "webp",
+            Self::Bmp => "bmp",
+            Self::Tiff => "tif",
+            Self::Qoi => "qoi",
+            Self::Ppm => "ppm",
+            Self::Npy => "npy",
+            Self::Exr => "exr",
+            Self::Json => "json",
+        }
+    }
+
+    pub const fn is_raw(self) -> bool {
+        matches!(self, Self::Npy | Self::Exr | Self::Json)
+    }
+
+    ///Picks the format the client prefers the most, `None` if it doesn't accept any of them
+    pub fn negotiate(accept: &Accept) -> Option<Self> {
+        if accept.is_empty() {
+            return Some(Self::default());
+        }
+        //Ranked doesn't drop the types that were explicitly refused
+        let accepted = Accept(
+            accept
+                .iter()
+                .filter(|item| item.quality > Quality::ZERO)
+                .cloned()
+                .collect(),
+        );
+        accepted.ranked().iter().find_map(|mime| {
+            match (mime.type_().as_str(), mime.subtype().as_str()) {
+                ("*", _) | ("image", "*") => Some(Self::default()),
+                _ => Self::ALL
+                    .into_iter()
+                    .find(|format| mime.essence_str() == format.mime()),
+            }
+        })
+    }
+}
+
+#[derive(
+    Debug, Clone, Copy, Default, serde_derive::Deserialize, serde_derive::Serialize, PartialEq, Eq,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum AngleUnit {
+    #[default]
+    Degrees,
+    Radians,
+}
+
+#[derive(
+    Debug, Clone, Copy, Default, serde_derive::Deserialize, serde_derive::Serialize, PartialEq, Eq,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum BoundsFit {
+    ///Shows more of the plane along the shorter side, so that all of the bounds are visible
+    #[default]
+    Letterbox,
+    Error,
+}
+
+#[derive(Debug, Clone, serde_derive::Deserialize, serde_derive::Serialize, PartialEq)]
 pub struct RequestBody {
     pub width: Option<u32>,
     pub height: Option<u32>,
@@ -39,52 +191,1058 @@ pub struct RequestBody {
     pub colors: Option<String>,
     pub max_iterations: Option<u32>,
     pub num_colors: Option<u32>,
+    ///Shifts the colors by this many stripes, wraps around after all the colors
+    pub color_offset: Option<f32>,
     pub zoom: Option<f32>,
+    ///Rotates the view counter-clockwise around its center
+    pub angle: Option<f32>,
+    pub angle_unit: Option<AngleUnit>,
     pub position_x: Option<f32>,
     pub position_y: Option<f32>,
+    ///Alternative to `position_x`, `position_y` and `zoom`, the part of the complex plane that
+    ///should be visible
+    pub x_min: Option<f32>,
+    pub x_max: Option<f32>,
+    pub y_min: Option<f32>,
+    pub y_max: Option<f32>,
+    ///Alternative to `zoom`, how much of the real axis is visible around the position
+    pub x_span: Option<f32>,
+    ///What to do when the bounds don't have the same aspect ratio as the image
+    pub fit: Option<BoundsFit>,
     pub msaa: Option<u8>,
     pub smooth: Option<bool>,
     pub debug: Option<bool>,
+    pub julia: Option<bool>,
+    pub julia_x: Option<f32>,
+    pub julia_y: Option<f32>,
+    pub deep: Option<bool>,
+    ///Decimal strings with as much precision as needed, used in deep zoom mode instead of
+    ///`position_x`, `position_y` and `zoom`
+    pub deep_x: Option<String>,
+    pub deep_y: Option<String>,
+    pub deep_zoom: Option<String>,
+    ///Takes priority over the accept header
+    pub format: Option<OutputFormat>,
+    ///Jpeg quality from 1 to 100
+    pub quality: Option<u8>,
 }
 
-impl Eq for RequestBody {}
+impl RequestBody {
+    ///Validates the request and fills in the defaults, errors are meant to be sent back to the
+    ///user
+    pub fn to_parameters(&self, fractal: SimplifiedFractals) -> Result<RenderParameters, ApiError> {
+        self.to_parameters_within(
+            fractal,
+            grimoire::MAX_IMAGE_SIDE,
+            grimoire::MAX_IMAGE_PIXELS,
+        )
+    }
+
+    ///[`RequestBody::to_parameters`] for images rendered as a job, which can be poster sized
+    pub fn to_poster_parameters(
+        &self,
+        fractal: SimplifiedFractals,
+    ) -> Result<RenderParameters, ApiError> {
+        self.to_parameters_within(
+            fractal,
+            grimoire::MAX_IMAGE_SIDE,
+            grimoire::MAX_POSTER_PIXELS,
+        )
+    }
+
+    ///[`RequestBody::to_parameters`] for images that can be up to `max_side` pixels wide and tall
+    ///and have up to `max_pixels` pixels
+    fn to_parameters_within(
+        &self,
+        fractal: SimplifiedFractals,
+        max_side: u32,
+        max_pixels: u64,
+    ) -> Result<RenderParameters, ApiError> {
+        if fractal == SimplifiedFractals::Custom {
+            let Some(formula) = &self.formula else {
+                return Err(ApiError::MissingParameter {
+                    field: "formula",
+                    message: "Formula should be specified to use custom fractal".to_string(),
+                });
+            };
+            //Compiling is cheap, so just do it here to report errors before rendering
+            if let Err(e) = formula::compile(formula) {
+                return Err(ApiError::invalid(
+                    "formula",
+                    format!("Invalid formula: {}", e.highlight(formula)),
+                ));
+            }
+        }
+
+        let fractal = fractal.into_fractals(self.formula.clone());
+        let colors = self
+            .colors
+            .as_ref()
+            .map_or_else(
+                || Ok(grimoire::DEFAULT_COLORS.into()),
+                |v| {
+                    let v = v.split(',').collect::<Vec<&str>>();
+                    vec_from_hex(&v)
+                },
+            )
+            .map_err(|_| ApiError::invalid("colors", "Invalid color format"))?;
+        if colors.len() as u64 > grimoire::MAX_COLORS {
+            return Err(ApiError::CountOutOfRange {
+                field: "colors",
+                min: 1,
+                max: grimoire::MAX_COLORS,
+            });
+        }
+
+        let color_offset = self.color_offset.unwrap_or_default();
+        if !color_offset.is_finite() {
+            return Err(ApiError::invalid("color_offset", "Invalid color offset"));
+        }
+
+        let angle = self.angle.unwrap_or_default();
+        if !angle.is_finite() {
+            return Err(ApiError::invalid("angle", "Invalid angle"));
+        }
+        //Angles a full turn apart give the same image
+        let rotation = match self.angle_unit.unwrap_or_default() {
+            AngleUnit::Degrees => angle.rem_euclid(360.0).to_radians(),
+            AngleUnit::Radians => angle.rem_euclid(std::f32::consts::TAU),
+        };
+
+        let julia = self.julia.unwrap_or_default();
+        let default_position = if julia {
+            grimoire::DEFAULT_JULIA_POSITION
+        } else {
+            grimoire::DEFAULT_POSITION
+        };
+
+        let max_iterations = self.max_iterations.unwrap_or(grimoire::DEFUALT_MAX_ITER);
+        ApiError::check_range(
+            "max_iterations",
+            max_iterations,
+            1,
+            grimoire::MAX_ITERATIONS.into(),
+        )?;
+        let width = self.width.unwrap_or(grimoire::DEFAULT_WIDTH);
+        ApiError::check_range("width", width, 1, max_side.into())?;
+        let height = self.height.unwrap_or(grimoire::DEFAULT_HEIGHT);
+        ApiError::check_range("height", height, 1, max_side.into())?;
+        if u64::from(width) * u64::from(height) > max_pixels {
+            return Err(ApiError::BadRequest(format!(
+                "Images can have at most {max_pixels} pixels"
+            )));
+        }
+        let msaa = self.msaa.unwrap_or(1);
+        ApiError::check_range("msaa", msaa, 1, grimoire::MAX_MSAA.into())?;
+        let num_colors = self.num_colors.unwrap_or(grimoire::DEFAULT_NUM_COLORS);
+        ApiError::check_range("num_colors", num_colors, 1, grimoire::MAX_NUM_COLORS.into())?;
+        let mut zoom = self.zoom.unwrap_or(grimoire::DEFAULT_ZOOM);
+        let mut position = [
+            self.position_x.unwrap_or(default_position[0]),
+            self.position_y.unwrap_or(default_position[1]),
+        ];
+        if let Some((bounds_position, bounds_zoom)) =
+            self.view_from_bounds(width, height, position)?
+        {
+            position = bounds_position;
+            zoom = bounds_zoom;
+        }
+
+        let deep = if self.deep.unwrap_or_default() {
+            if julia {
+                return Err(ApiError::invalid(
+                    "deep",
+                    "Deep zoom can't be used with julia sets",
+                ));
+            }
+            if !fractal.supports_deep_zoom() {
+                return Err(ApiError::invalid(
+                    "deep",
+                    format!("Deep zoom is not supported for {fractal}"),
+                ));
+            }
+            ApiError::check_range(
+                "max_iterations",
+                max_iterations,
+                1,
+                grimoire::MAX_ORBIT_LENGTH - 1,
+            )?;
+            //Fall back to the regular parameters, so that it's easy to switch to deep zoom
+            let normalize = |value: &Option<String>, fallback: f32, name: &'static str| {
+                normalize_decimal(&value.clone().unwrap_or_else(|| fallback.to_string()))
+                    .map_err(|e| ApiError::invalid(name, format!("Invalid {name}: {e}")))
+            };
+            let deep = DeepParameters {
+                x: normalize(&self.deep_x, position[0], "deep_x")?,
+                y: normalize(&self.deep_y, position[1], "deep_y")?,
+                zoom: normalize(&self.deep_zoom, zoom, "deep_zoom")?,
+            };
+            //They're baked into the reference orbit
+            position = grimoire::DEFAULT_POSITION;
+            zoom = grimoire::DEFAULT_ZOOM;
+            Some(deep)
+        } else {
+            None
+        };
+
+        let format = self.format.unwrap_or_default();
+        if format.is_raw() && u64::from(width) * u64::from(height) > grimoire::MAX_RAW_PIXELS {
+            return Err(ApiError::BadRequest(format!(
+                "Raw data can have at most {} pixels",
+                grimoire::MAX_RAW_PIXELS
+            )));
+        }
+        let quality = self.quality.unwrap_or(grimoire::DEFAULT_JPEG_QUALITY);
+        ApiError::check_range("quality", quality, 1, 100)?;
 
-impl std::hash::Hash for RequestBody {
-    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
-        self.width.hash(state);
-        self.height.hash(state);
-        self.formula.hash(state);
-        self.colors.hash(state);
-        self.max_iterations.hash(state);
-        self.num_colors.hash(state);
-        if let Some(zoom) = self.zoom {
-            zoom.to_bits().hash(state);
+        let raw = format.is_raw();
+        let colors_len = colors.len();
+        //Raw data is always smooth and samples the center of each pixel
+        let mut flags = if raw {
+            1 | grimoire::rendering_flags::RAW
+        } else {
+            u32::from(msaa)
+        };
+        if self.smooth.unwrap_or_default() && !raw {
+            flags |= grimoire::rendering_flags::SMOOTH;
+        }
+        if self.debug.unwrap_or_default() && !raw {
+            flags |= grimoire::rendering_flags::DEBUG;
         }
-        if let Some(position_x) = self.position_x {
-            position_x.to_bits().hash(state);
+        if julia {
+            flags |= grimoire::rendering_flags::JULIA;
+        }
+        if deep.is_some() {
+            flags |= grimoire::rendering_flags::DEEP;
+        }
+
+        Ok(RenderParameters {
+            fractal,
+            width,
+            height,
+            //Nothing is colored in raw mode
+            colors: if raw {
+                grimoire::DEFAULT_COLORS.into()
+            } else {
+                colors
+            },
+            max_iterations,
+            num_colors: if raw {
+                grimoire::DEFAULT_NUM_COLORS
+            } else {
+                num_colors
+            },
+            color_offset: if raw {
+                0.0
+            } else {
+                //Offsets that are a whole cycle apart give the same image
+                color_offset.rem_euclid(colors_len as f32)
+            },
+            zoom,
+            rotation,
+            position,
+            flags,
+            //The constant doesn't matter for other fractals
+            julia: if julia {
+                [
+                    self.julia_x.unwrap_or(grimoire::DEFAULT_JULIA[0]),
+                    self.julia_y.unwrap_or(grimoire::DEFAULT_JULIA[1]),
+                ]
+            } else {
+                grimoire::DEFAULT_JULIA
+            },
+            deep,
+            format,
+            //Other formats are lossless
+            quality: if format == OutputFormat::Jpeg {
+                quality
+            } else {
+                grimoire::DEFAULT_JPEG_QUALITY
+            },
+        })
+    }
+
+    ///Parameters of an image pyramid, which is rendered in parts so it can be much bigger than a
+    ///normal image
+    pub fn to_pyramid_parameters(
+        &self,
+        fractal: SimplifiedFractals,
+    ) -> Result<RenderParameters, ApiError> {
+        if self.deep.unwrap_or_default() {
+            return Err(ApiError::invalid(
+                "deep",
+                "Deep zoom can't be used in image pyramids",
+            ));
         }
-        if let Some(position_y) = self.position_y {
-            position_y.to_bits().hash(state);
+        if self.format.is_some_and(OutputFormat::is_raw) {
+            return Err(ApiError::invalid(
+                "format",
+                "Image pyramids can only contain images",
+            ));
         }
-        self.msaa.hash(state);
-        self.smooth.hash(state);
-        self.debug.hash(state);
+        //Iiif images are only rendered a region at a time, so only the side is limited
+        self.to_parameters_within(fractal, grimoire::MAX_PYRAMID_SIDE, u64::MAX)
+    }
+
+    ///Converts `x_min`, `x_max`, `y_min` and `y_max`, or `x_span` into a position and zoom, `None`
+    ///if the view is given by the position and zoom
+    fn view_from_bounds(
+        &self,
+        width: u32,
+        height: u32,
+        position: [f32; 2],
+    ) -> Result<Option<([f32; 2], f32)>, ApiError> {
+        let bounds = [self.x_min, self.x_max, self.y_min, self.y_max];
+        let using_bounds = bounds.iter().any(Option::is_some);
+        if !using_bounds && self.x_span.is_none() {
+            return Ok(None);
+        }
+        if self.zoom.is_some() {
+            return Err(ApiError::invalid(
+                "zoom",
+                "Zoom can't be combined with bounds or x_span",
+            ));
+        }
+        if self.deep.unwrap_or_default() {
+            return Err(ApiError::invalid(
+                "deep",
+                "Deep zoom can't be used with bounds or x_span",
+            ));
+        }
+        if self.angle.is_some_and(|angle| angle != 0.0) {
+            return Err(ApiError::invalid(
+                "angle",
+                "Rotated views can't be given by bounds or x_span",
+            ));
+        }
+        //The visible part of the plane is 2 / zoom tall, and as wide as the image ratio allows
+        let aspect = width as f32 / height as f32;
+
+        if !using_bounds {
+            let span = self.x_span.unwrap_or_default();
+            if !(span.is_finite() && span > 0.0) {
+                return Err(ApiError::invalid("x_span", "X span should be positive"));
+            }
+            return Ok(Some((position, 2.0 * aspect / span)));
+        }
+
+        let [Some(x_min), Some(x_max), Some(y_min), Some(y_max)] = bounds else {
+            let missing = ["x_min", "x_max", "y_min", "y_max"]
+                .into_iter()
+                .zip(bounds)
+                .find_map(|(name, bound)| bound.is_none().then_some(name))
+                .unwrap_or_default();
+            return Err(ApiError::MissingParameter {
+                field: missing,
+                message: "x_min, x_max, y_min and y_max should all be set".to_string(),
+            });
+        };
+        if self.x_span.is_some() || self.position_x.is_some() || self.position_y.is_some() {
+            return Err(ApiError::invalid(
+                "x_min",
+                "Bounds can't be combined with a position or x_span",
+            ));
+        }
+        let (x_span, y_span) = (x_max - x_min, y_max - y_min);
+        if !(x_span.is_finite() && y_span.is_finite() && x_span > 0.0 && y_span > 0.0) {
+            return Err(ApiError::invalid(
+                "x_min",
+                "Bounds should have min smaller than max",
+            ));
+        }
+        //Off by less than half a pixel is just rounding
+        let matching_width = x_span / y_span * height as f32;
+        if self.fit == Some(BoundsFit::Error) && (matching_width - width as f32).abs() > 0.5 {
+            return Err(ApiError::invalid(
+                "fit",
+                format!(
+                    "Bounds are {x_span}x{y_span}, which doesn't match the aspect ratio of a \
+                     {width}x{height} image"
+                ),
+            ));
+        }
+        Ok(Some((
+            [(x_min + x_max) / 2.0, (y_min + y_max) / 2.0],
+            (2.0 * aspect / x_span).min(2.0 / y_span),
+        )))
     }
 }
 
-#[derive(Debug, Clone, Hash, Eq, PartialEq)]
-pub struct RequestIdentifier {
-    fractal: SimplifiedFractals,
-    body: RequestBody,
+///How the zoom changes over the course of an animation
+#[derive(
+    Debug, Clone, Copy, Default, serde_derive::Deserialize, serde_derive::Serialize, PartialEq, Eq,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum Easing {
+    ///Zooms in by the same factor every frame, which looks like a steady speed
+    #[default]
+    Exponential,
+    Linear,
+    ///Exponential, but starts and ends slowly
+    EaseInOut,
 }
 
-impl RequestIdentifier {
-    pub fn new(fractal: SimplifiedFractals, body: &RequestBody) -> Self {
-        Self {
-            fractal,
-            body: body.clone(),
+///What changes between the frames of an animation
+#[derive(
+    Debug, Clone, Copy, Default, serde_derive::Deserialize, serde_derive::Serialize, PartialEq, Eq,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum AnimationMode {
+    ///Moves from the start to the end position and zoom
+    #[default]
+    Zoom,
+    ///Shifts the colors through the whole palette once, the fractal is only rendered once and
+    ///then recolored for every frame
+    Cycle,
+}
+
+///Containers that the frames of an animation can be encoded in
+#[derive(
+    Debug, Clone, Copy, Default, serde_derive::Deserialize, serde_derive::Serialize, PartialEq, Eq,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum AnimationFormat {
+    #[default]
+    Gif,
+    Apng,
+    ///Archives of numbered png frames
+    Zip,
+    Tar,
+}
+
+impl AnimationFormat {
+    pub const fn mime(self) -> &'static str {
+        match self {
+            Self::Gif => "image/gif",
+            Self::Apng => "image/apng",
+            Self::Zip => "application/zip",
+            Self::Tar => "application/x-tar",
         }
     }
 }
 
-pub type Cache = Mutex<Vec<(RequestIdentifier, Vec<u8>)>>;
+///Parameters of an animation, everything else is taken from [`RequestBody`]
+#[derive(Debug, Clone, serde_derive::Deserialize, serde_derive::Serialize, PartialEq)]
+pub struct AnimationBody {
+    pub mode: Option<AnimationMode>,
+    ///Default to `position_x`, `position_y` and `zoom`
+    pub start_x: Option<f32>,
+    pub start_y: Option<f32>,
+    pub start_zoom: Option<f32>,
+    ///Default to the start, so that only the changing values need to be set
+    pub end_x: Option<f32>,
+    pub end_y: Option<f32>,
+    pub end_zoom: Option<f32>,
+    pub frames: Option<u32>,
+    pub fps: Option<u32>,
+    pub easing: Option<Easing>,
+    ///Not called format, since that's already used for the frames
+    pub output: Option<AnimationFormat>,
+}
+
+impl AnimationBody {
+    pub fn fps(&self) -> u32 {
+        self.fps.unwrap_or(grimoire::DEFAULT_FPS)
+    }
+
+    ///Validates the animation and returns the parameters of every frame, errors are meant to be
+    ///sent back to the user
+    pub fn to_frames(
+        &self,
+        query: &RequestBody,
+        fractal: SimplifiedFractals,
+    ) -> Result<Vec<RenderParameters>, ApiError> {
+        if query.deep.unwrap_or_default() {
+            return Err(ApiError::invalid("deep", "Deep zoom can't be animated"));
+        }
+        if query
+            .format
+            .is_some_and(|format| format != OutputFormat::Png)
+        {
+            return Err(ApiError::invalid(
+                "format",
+                "Frames are always png, use output to pick the animation format",
+            ));
+        }
+        let frames = self.frames.unwrap_or(grimoire::DEFAULT_FRAMES);
+        ApiError::check_range("frames", frames, 1, grimoire::MAX_FRAMES.into())?;
+        ApiError::check_range("fps", self.fps(), 1, grimoire::MAX_FPS.into())?;
+
+        let first = query.to_parameters(fractal)?;
+        let pixels = u64::from(first.width) * u64::from(first.height) * u64::from(frames);
+        if pixels > grimoire::MAX_ANIMATION_PIXELS {
+            return Err(ApiError::BadRequest(format!(
+                "Animations can have at most {} pixels across all frames",
+                grimoire::MAX_ANIMATION_PIXELS
+            )));
+        }
+
+        let start = Keyframe {
+            position: [
+                self.start_x.unwrap_or(first.position[0]),
+                self.start_y.unwrap_or(first.position[1]),
+            ],
+            zoom: self.start_zoom.unwrap_or(first.zoom),
+        };
+        let end = Keyframe {
+            position: [
+                self.end_x.unwrap_or(start.position[0]),
+                self.end_y.unwrap_or(start.position[1]),
+            ],
+            zoom: self.end_zoom.unwrap_or(start.zoom),
+        };
+        let positions = [
+            ("start_x", start.position[0]),
+            ("start_y", start.position[1]),
+            ("end_x", end.position[0]),
+            ("end_y", end.position[1]),
+        ];
+        for (field, value) in positions {
+            if !value.is_finite() {
+                return Err(ApiError::invalid(field, "Invalid position"));
+            }
+        }
+        for (field, zoom) in [("start_zoom", start.zoom), ("end_zoom", end.zoom)] {
+            if !(zoom.is_finite() && zoom > 0.0) {
+                return Err(ApiError::invalid(field, "Zoom should be positive"));
+            }
+        }
+
+        match self.mode.unwrap_or_default() {
+            AnimationMode::Zoom => {
+                Ok(
+                    keyframes(start, end, frames, self.easing.unwrap_or_default())
+                        .into_iter()
+                        .map(|keyframe| RenderParameters {
+                            position: keyframe.position,
+                            zoom: keyframe.zoom,
+                            ..first.clone()
+                        })
+                        .collect(),
+                )
+            }
+            AnimationMode::Cycle => {
+                //The raw data doesn't have the grid
+                if query.debug.unwrap_or_default() {
+                    return Err(ApiError::invalid(
+                        "debug",
+                        "Debug can't be used when cycling colors",
+                    ));
+                }
+                //Neither does it have more than one sample per pixel
+                if query.msaa.unwrap_or(1) > 1 {
+                    return Err(ApiError::invalid(
+                        "msaa",
+                        "Msaa can't be used when cycling colors",
+                    ));
+                }
+                let colors = first.colors.len() as f32;
+                Ok((0..frames)
+                    .map(|frame| RenderParameters {
+                        position: start.position,
+                        zoom: start.zoom,
+                        //The last frame is one step before the first one, so that it loops
+                        color_offset: (first.color_offset + colors * frame as f32 / frames as f32)
+                            % colors,
+                        ..first.clone()
+                    })
+                    .collect())
+            }
+        }
+    }
+}
+
+#[derive(
+    Debug, Clone, Copy, Default, serde_derive::Deserialize, serde_derive::Serialize, PartialEq, Eq,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum ArchiveFormat {
+    #[default]
+    Zip,
+    Tar,
+}
+
+impl ArchiveFormat {
+    pub const fn mime(self) -> &'static str {
+        match self {
+            Self::Zip => "application/zip",
+            Self::Tar => "application/x-tar",
+        }
+    }
+}
+
+///Options of a deep zoom pyramid, the image itself is taken from [`RequestBody`]
+#[derive(Debug, Clone, serde_derive::Deserialize, serde_derive::Serialize, PartialEq)]
+pub struct PyramidBody {
+    pub output: Option<ArchiveFormat>,
+}
+
+///A render that runs in the background, with the fields of [`RequestBody`] next to the fractal
+#[derive(Debug, Clone, serde_derive::Deserialize, serde_derive::Serialize, PartialEq)]
+pub struct JobBody {
+    pub fractal: SimplifiedFractals,
+    ///Renders an animation instead of a single image
+    pub animation: Option<AnimationBody>,
+    ///Renders a deep zoom pyramid instead of a single image
+    pub pyramid: Option<PyramidBody>,
+    #[serde(flatten)]
+    pub render: RequestBody,
+}
+
+///A color of a palette in a json body
+#[derive(Debug, Clone, serde_derive::Deserialize, serde_derive::Serialize, PartialEq)]
+#[serde(untagged)]
+pub enum ColorStop {
+    ///Like in the query, but a leading # is allowed
+    Hex(String),
+    Rgb([u8; 3]),
+    Channels {
+        r: u8,
+        g: u8,
+        b: u8,
+    },
+}
+
+impl ColorStop {
+    fn to_hex(&self) -> String {
+        match self {
+            Self::Hex(hex) => hex.strip_prefix('#').unwrap_or(hex).to_string(),
+            Self::Rgb([r, g, b]) | Self::Channels { r, g, b } => format!("{r:02x}{g:02x}{b:02x}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, serde_derive::Deserialize, serde_derive::Serialize, PartialEq)]
+#[serde(untagged)]
+pub enum Palette {
+    ///Hex colors split with , like in the query
+    List(String),
+    Stops(Vec<ColorStop>),
+}
+
+#[derive(Debug, Clone, serde_derive::Deserialize, serde_derive::Serialize, PartialEq)]
+pub struct BoundsSpec {
+    pub x_min: f32,
+    pub x_max: f32,
+    pub y_min: f32,
+    pub y_max: f32,
+}
+
+///Where the view is, the fields work like the ones with the same name in [`RequestBody`]
+#[derive(Debug, Clone, Default, serde_derive::Deserialize, serde_derive::Serialize, PartialEq)]
+pub struct TransformSpec {
+    pub x: Option<f32>,
+    pub y: Option<f32>,
+    pub zoom: Option<f32>,
+    pub x_span: Option<f32>,
+    pub bounds: Option<BoundsSpec>,
+    pub fit: Option<BoundsFit>,
+    pub angle: Option<f32>,
+    pub angle_unit: Option<AngleUnit>,
+}
+
+#[derive(Debug, Clone, Default, serde_derive::Deserialize, serde_derive::Serialize, PartialEq)]
+pub struct OutputSpec {
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub format: Option<OutputFormat>,
+    pub quality: Option<u8>,
+    pub msaa: Option<u8>,
+}
+
+///Json body of a render, the fields of [`RequestBody`] with a richer palette and optional
+///`transform` and `output` objects that group the view and the image settings
+#[derive(Debug, Clone, serde_derive::Deserialize, serde_derive::Serialize, PartialEq)]
+pub struct RenderSpec {
+    pub colors: Option<Palette>,
+    pub transform: Option<TransformSpec>,
+    pub output: Option<OutputSpec>,
+    #[serde(flatten)]
+    pub render: RequestBody,
+}
+
+///Moves a nested field to the flat one, errors if both are set
+fn set_once<T>(
+    flat: &mut Option<T>,
+    nested: Option<T>,
+    name: &'static str,
+) -> Result<(), ApiError> {
+    if nested.is_some() {
+        if flat.is_some() {
+            return Err(ApiError::invalid(name, format!("{name} is set twice")));
+        }
+        *flat = nested;
+    }
+    Ok(())
+}
+
+impl RenderSpec {
+    ///Flattens the spec into the same parameters as a query, so that both go through the same
+    ///validation and end up in the same cache entries
+    pub fn into_request_body(self) -> Result<RequestBody, ApiError> {
+        let mut body = self.render;
+        body.colors = self.colors.map(|palette| match palette {
+            Palette::List(colors) => colors,
+            Palette::Stops(stops) => stops
+                .iter()
+                .map(ColorStop::to_hex)
+                .collect::<Vec<_>>()
+                .join(","),
+        });
+
+        let transform = self.transform.unwrap_or_default();
+        set_once(&mut body.position_x, transform.x, "position_x")?;
+        set_once(&mut body.position_y, transform.y, "position_y")?;
+        set_once(&mut body.zoom, transform.zoom, "zoom")?;
+        set_once(&mut body.x_span, transform.x_span, "x_span")?;
+        set_once(&mut body.fit, transform.fit, "fit")?;
+        set_once(&mut body.angle, transform.angle, "angle")?;
+        set_once(&mut body.angle_unit, transform.angle_unit, "angle_unit")?;
+        if let Some(bounds) = transform.bounds {
+            set_once(&mut body.x_min, Some(bounds.x_min), "x_min")?;
+            set_once(&mut body.x_max, Some(bounds.x_max), "x_max")?;
+            set_once(&mut body.y_min, Some(bounds.y_min), "y_min")?;
+            set_once(&mut body.y_max, Some(bounds.y_max), "y_max")?;
+        }
+
+        let output = self.output.unwrap_or_default();
+        set_once(&mut body.width, output.width, "width")?;
+        set_once(&mut body.height, output.height, "height")?;
+        set_once(&mut body.format, output.format, "format")?;
+        set_once(&mut body.quality, output.quality, "quality")?;
+        set_once(&mut body.msaa, output.msaa, "msaa")?;
+        Ok(body)
+    }
+}
+
+///An image of a batch, with the fields of [`RenderSpec`] next to the fractal
+#[derive(Debug, Clone, serde_derive::Deserialize, serde_derive::Serialize, PartialEq)]
+pub struct BatchItem {
+    pub fractal: SimplifiedFractals,
+    #[serde(flatten)]
+    pub spec: RenderSpec,
+}
+
+#[derive(
+    Debug, Clone, Copy, Default, serde_derive::Deserialize, serde_derive::Serialize, PartialEq, Eq,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum BatchFormat {
+    #[default]
+    Zip,
+    Tar,
+    ///`multipart/mixed`, every image is a part
+    Multipart,
+}
+
+#[derive(Debug, Clone, serde_derive::Deserialize, serde_derive::Serialize, PartialEq)]
+pub struct BatchBody {
+    pub output: Option<BatchFormat>,
+}
+
+///Where the tile pyramid is on the complex plane, the style of the tiles is taken from
+///[`RequestBody`]
+#[derive(Debug, Clone, serde_derive::Deserialize, serde_derive::Serialize, PartialEq)]
+pub struct TileBody {
+    ///Center and side length of the square covered by the single tile at level 0
+    pub root_x: Option<f32>,
+    pub root_y: Option<f32>,
+    pub root_size: Option<f32>,
+}
+
+impl TileBody {
+    ///Validates the tile coordinates and returns the parameters of the tile, errors are meant to
+    ///be sent back to the user
+    pub fn to_parameters(
+        &self,
+        query: &RequestBody,
+        fractal: SimplifiedFractals,
+        [level, x, y]: [u32; 3],
+    ) -> Result<RenderParameters, ApiError> {
+        let view_set = query.width.is_some()
+            || query.height.is_some()
+            || query.deep.unwrap_or_default()
+            || [
+                query.zoom,
+                query.position_x,
+                query.position_y,
+                query.x_min,
+                query.x_max,
+                query.y_min,
+                query.y_max,
+                query.x_span,
+                query.angle,
+            ]
+            .iter()
+            .any(Option::is_some);
+        if view_set {
+            return Err(ApiError::BadRequest(
+                "The view of a tile is only given by its coordinates".to_string(),
+            ));
+        }
+        if query
+            .format
+            .is_some_and(|format| format != OutputFormat::Png)
+        {
+            return Err(ApiError::invalid("format", "Tiles are always png"));
+        }
+        ApiError::check_range("z", level, 0, grimoire::MAX_TILE_LEVEL.into())?;
+        let tiles = 1u32 << level;
+        ApiError::check_range("x", x, 0, u64::from(tiles) - 1)?;
+        ApiError::check_range("y", y, 0, u64::from(tiles) - 1)?;
+        let [default_x, default_y, default_size] = grimoire::DEFAULT_TILE_ROOT;
+        let size = f64::from(self.root_size.unwrap_or(default_size));
+        if !(size.is_finite() && size > 0.0) {
+            return Err(ApiError::invalid(
+                "root_size",
+                "Root size should be positive",
+            ));
+        }
+
+        //In f64 so that neighbouring tiles are exactly one tile apart, y goes down in tiles
+        let side = size / f64::from(tiles);
+        let left = f64::from(self.root_x.unwrap_or(default_x)) - size / 2.0;
+        let top = f64::from(self.root_y.unwrap_or(default_y)) + size / 2.0;
+        let query = RequestBody {
+            width: Some(grimoire::TILE_SIZE),
+            height: Some(grimoire::TILE_SIZE),
+            position_x: Some((left + (f64::from(x) + 0.5) * side) as f32),
+            position_y: Some((top - (f64::from(y) + 0.5) * side) as f32),
+            zoom: Some((2.0 / side) as f32),
+            format: Some(OutputFormat::Png),
+            ..query.clone()
+        };
+        query.to_parameters(fractal)
+    }
+}
+
+///Result of a background job, with the content type that it should be sent back with
+#[derive(Debug, Clone)]
+pub struct JobOutput {
+    pub data: Vec<u8>,
+    pub content_type: &'static str,
+}
+
+pub type Cache = Mutex<LruCache<RenderParameters, Vec<u8>>>;
+pub type InFlightRenders = Coalescer<RenderParameters, Result<Vec<u8>, ApiError>>;
+pub type Jobs = JobQueue<JobOutput, ApiError>;
+
+#[test]
+fn test_canonical_parameters() {
+    let parameters = |query: &str| {
+        actix_web::web::Query::<RequestBody>::from_query(query)
+            .unwrap()
+            .to_parameters(SimplifiedFractals::Mandelbrot)
+            .unwrap()
+    };
+
+    assert_eq!(
+        parameters(""),
+        parameters("width=1920&height=1080&zoom=1&position_x=-0.75&msaa=1&smooth=false")
+    );
+    assert_eq!(parameters("colors=FFFFFF"), parameters("colors=ffffff"));
+    //The julia constant isn't used without julia=true
+    assert_eq!(parameters(""), parameters("julia_x=0.3"));
+    assert_eq!(
+        parameters("deep=true&deep_x=1e-3&deep_y=0&deep_zoom=1000"),
+        parameters("deep=true&deep_x=0.0010&deep_y=-0.0&deep_zoom=1e3&zoom=5")
+    );
+    assert_ne!(parameters("zoom=2"), parameters(""));
+    //There are 5 default colors, so offsets wrap around after 5
+    assert_eq!(parameters("color_offset=5"), parameters(""));
+    assert_eq!(parameters("color_offset=-1"), parameters("color_offset=4"));
+    assert_eq!(parameters("angle=360"), parameters(""));
+    assert_eq!(parameters("angle=-90"), parameters("angle=270"));
+    assert_ne!(
+        parameters("angle=1"),
+        parameters("angle=1&angle_unit=radians")
+    );
+    //Quality only matters for jpeg
+    assert_eq!(parameters("format=png&quality=50"), parameters(""));
+    assert_eq!(
+        parameters("format=jpg"),
+        parameters("format=jpeg&quality=90")
+    );
+    assert_ne!(parameters("format=jpeg"), parameters(""));
+}
+
+#[test]
+fn test_render_spec() {
+    let body = |json: serde_json::Value| {
+        serde_json::from_value::<RenderSpec>(json)
+            .unwrap()
+            .into_request_body()
+    };
+    let query = |query: &str| {
+        actix_web::web::Query::<RequestBody>::from_query(query)
+            .unwrap()
+            .into_inner()
+    };
+
+    assert_eq!(body(serde_json::json!({})), Ok(query("")));
+    assert_eq!(
+        body(serde_json::json!({
+            "colors": ["#ff0000", [0, 255, 0], {"r": 0, "g": 0, "b": 255}],
+            "transform": {"x": 0.5, "bounds": {"x_min": -1, "x_max": 1, "y_min": -1, "y_max": 1}},
+            "output": {"width": 100, "format": "webp"},
+            "smooth": true,
+        })),
+        Ok(query(
+            "colors=ff0000,00ff00,0000ff&position_x=0.5&x_min=-1&x_max=1&y_min=-1&y_max=1\
+             &width=100&format=webp&smooth=true"
+        ))
+    );
+    assert_eq!(
+        body(serde_json::json!({"colors": "ffffff,000000", "zoom": 2})),
+        Ok(query("colors=ffffff,000000&zoom=2"))
+    );
+    assert!(body(serde_json::json!({"zoom": 2, "transform": {"zoom": 3}})).is_err());
+}
+
+#[test]
+fn test_bounds() {
+    let parameters = |query: &str| {
+        actix_web::web::Query::<RequestBody>::from_query(&format!("width=350&height=200&{query}"))
+            .unwrap()
+            .to_parameters(SimplifiedFractals::Mandelbrot)
+    };
+
+    let default = parameters("").unwrap();
+    assert_eq!(
+        parameters("x_min=-2.5&x_max=1&y_min=-1&y_max=1&fit=error").unwrap(),
+        default
+    );
+    assert_eq!(parameters("x_span=3.5").unwrap(), default);
+    assert_eq!(default.bounds(), Some([-2.5, 1.0, -1.0, 1.0]));
+
+    //Too tall, so it's letterboxed horizontally
+    let letterboxed = parameters("x_min=-1&x_max=1&y_min=-2&y_max=2").unwrap();
+    assert_eq!(letterboxed.bounds(), Some([-3.5, 3.5, -2.0, 2.0]));
+    assert!(parameters("x_min=-1&x_max=1&y_min=-2&y_max=2&fit=error").is_err());
+
+    assert!(parameters("x_min=-1&x_max=1&y_min=-2").is_err());
+    assert!(parameters("x_min=1&x_max=-1&y_min=-2&y_max=2").is_err());
+    assert!(parameters("x_span=3.5&zoom=2").is_err());
+    assert!(parameters("x_span=-1").is_err());
+}
+
+#[test]
+fn test_size_limits() {
+    let parameters = |query: &str| {
+        actix_web::web::Query::<RequestBody>::from_query(query)
+            .unwrap()
+            .to_parameters(SimplifiedFractals::Mandelbrot)
+    };
+    assert!(parameters("width=8192&height=8192").is_ok());
+    assert!(parameters("width=65536&height=1").is_ok());
+    assert!(parameters("width=65537&height=1").is_err());
+    //Posters are rendered in tiles, but only as a job
+    assert!(parameters("width=30000&height=20000").is_err());
+    let poster = |query: &str| {
+        actix_web::web::Query::<RequestBody>::from_query(query)
+            .unwrap()
+            .to_poster_parameters(SimplifiedFractals::Mandelbrot)
+    };
+    assert!(poster("width=30000&height=20000").is_ok());
+    assert!(poster("width=65536&height=65536").is_err());
+    assert!(parameters("width=0").is_err());
+    assert!(parameters("width=4096&height=4096&format=npy").is_ok());
+    assert!(parameters("width=4097&height=4096&format=exr").is_err());
+    assert!(parameters("width=30000&height=20000&format=json").is_err());
+
+    //Deep zoom coordinates are checked before the reference orbit is computed
+    let deep = |x: &str| parameters(&format!("deep=true&deep_x={x}&deep_zoom=1e10"));
+    assert!(deep(&format!("0.{}1", "0".repeat(990))).is_ok());
+    let tiny = format!("0.{}1", "0".repeat(1000));
+    assert_eq!(deep(&tiny).unwrap_err().field(), Some("deep_x"));
+    let long = "7".repeat(grimoire::MAX_DECIMAL_DIGITS + 1);
+    assert_eq!(deep(&long).unwrap_err().field(), Some("deep_x"));
+}
+
+#[test]
+fn test_tile_seams() {
+    let tile = |query: &str, coordinates: [u32; 3]| {
+        let query = actix_web::web::Query::<RequestBody>::from_query(query).unwrap();
+        TileBody {
+            root_x: None,
+            root_y: None,
+            root_size: None,
+        }
+        .to_parameters(&query, SimplifiedFractals::Mandelbrot, coordinates)
+    };
+    //Real part of the center of a pixel, the same way the shader computes it
+    let pixel_x = |parameters: &RenderParameters, column: u32| {
+        let uv = ((column as f32 + 0.5) / grimoire::TILE_SIZE as f32).mul_add(2.0, -1.0);
+        uv / parameters.zoom + parameters.position[0]
+    };
+
+    let root = tile("smooth=true", [0, 0, 0]).unwrap();
+    assert_eq!(root.bounds(), Some([-2.5, 1.5, -2.0, 2.0]));
+    for level in [1, 6, grimoire::MAX_TILE_LEVEL] {
+        let left = tile("", [level, 0, 1]).unwrap();
+        let right = tile("", [level, 1, 1]).unwrap();
+        let pixel = 4.0 / (1u32 << level) as f32 / grimoire::TILE_SIZE as f32;
+        let gap = pixel_x(&right, 0) - pixel_x(&left, grimoire::TILE_SIZE - 1);
+        assert!((gap - pixel).abs() < pixel / 16.0, "{level}: {gap} {pixel}");
+    }
+
+    assert!(tile("", [1, 2, 0]).is_err());
+    assert!(tile("", [grimoire::MAX_TILE_LEVEL + 1, 0, 0]).is_err());
+    assert!(tile("zoom=2", [1, 0, 0]).is_err());
+    assert!(tile("format=jpeg", [1, 0, 0]).is_err());
+}
+
+#[test]
+fn test_negotiate_format() {
+    use actix_web::http::header::{Header, ACCEPT};
+
+    let negotiate = |accept: &str| {
+        let req = actix_web::test::TestRequest::default()
+            .insert_header((ACCEPT, accept))
+            .to_http_request();
+        OutputFormat::negotiate(&Accept::parse(&req).unwrap())
+    };
+
+    assert_eq!(
+        negotiate("image/webp,image/*;q=0.8"),
+        Some(OutputFormat::Webp)
+    );
+    assert_eq!(
+        negotiate("image/png;q=0.5, image/jpeg"),
+        Some(OutputFormat::Jpeg)
+    );
+    assert_eq!(negotiate("text/html, */*;q=0.1"), Some(OutputFormat::Png));
+    assert_eq!(
+        negotiate("image/tiff;q=0, image/bmp"),
+        Some(OutputFormat::Bmp)
+    );
+    assert_eq!(negotiate("text/html"), None);
+}
+
+#[test]
+fn test_animation_keyframes() {
+    let frames = |query: &str| {
+        let animation = actix_web::web::Query::<AnimationBody>::from_query(query).unwrap();
+        let render = actix_web::web::Query::<RequestBody>::from_query(query).unwrap();
+        animation.to_frames(&render, SimplifiedFractals::Mandelbrot)
+    };
+
+    assert_eq!(frames("frames=3&end_zoom=8").unwrap().len(), 3);
+    //The field that's wrong is reported, not the first zoom
+    assert_eq!(frames("end_zoom=-1").unwrap_err().field(), Some("end_zoom"));
+    assert_eq!(
+        frames("start_zoom=0").unwrap_err().field(),
+        Some("start_zoom")
+    );
+    assert_eq!(
+        frames("end_zoom=NaN").unwrap_err().field(),
+        Some("end_zoom")
+    );
+    assert_eq!(frames("start_x=inf").unwrap_err().field(), Some("start_x"));
+    assert_eq!(frames("end_y=NaN").unwrap_err().field(), Some("end_y"));
+    assert_eq!(
+        frames("mode=cycle&msaa=4").unwrap_err().field(),
+        Some("msaa")
+    );
+    assert!(frames("mode=cycle&msaa=1").is_ok());
+}
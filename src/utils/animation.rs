@@ -0,0 +1,207 @@
+use std::{
+    io::Write,
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    grimoire,
+    structs::requests::{AnimationFormat, ArchiveFormat, Easing, OutputFormat},
+    utils::{archive::Archive, export::arr_to_image},
+};
+
+///Where the view is in a single frame
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Keyframe {
+    pub position: [f32; 2],
+    pub zoom: f32,
+}
+
+///Position and zoom of every frame, the zoom follows the easing and the position moves with the
+///size of the view, so that the end position drifts towards the center at a steady pace
+pub fn keyframes(start: Keyframe, end: Keyframe, frames: u32, easing: Easing) -> Vec<Keyframe> {
+    //Zooms get big quickly, so f32 would lose precision in the interpolation
+    let (start_zoom, end_zoom) = (f64::from(start.zoom), f64::from(end.zoom));
+    (0..frames)
+        .map(|frame| {
+            let t = if frames > 1 {
+                f64::from(frame) / f64::from(frames - 1)
+            } else {
+                0.0
+            };
+            let eased = match easing {
+                Easing::Exponential | Easing::Linear => t,
+                Easing::EaseInOut => t * t * (3.0 - 2.0 * t),
+            };
+            let zoom = match easing {
+                Easing::Linear => start_zoom + (end_zoom - start_zoom) * eased,
+                Easing::Exponential | Easing::EaseInOut => {
+                    start_zoom * (end_zoom / start_zoom).powf(eased)
+                }
+            };
+            //The view is 1/zoom wide, so this is how much of the change in view size happened
+            let span = start_zoom.recip() - end_zoom.recip();
+            let progress = if span.abs() > f64::EPSILON * start_zoom.recip() {
+                (start_zoom.recip() - zoom.recip()) / span
+            } else {
+                eased
+            };
+            let lerp = |a: f32, b: f32| (f64::from(a) + f64::from(b - a) * progress) as f32;
+            Keyframe {
+                position: [
+                    lerp(start.position[0], end.position[0]),
+                    lerp(start.position[1], end.position[1]),
+                ],
+                zoom: zoom as f32,
+            }
+        })
+        .collect()
+}
+
+///Lets the output be taken back from writers that don't give it back, like the apng one
+#[derive(Clone, Default)]
+struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+enum Container {
+    Gif(gif::Encoder<Vec<u8>>),
+    Apng(png::Writer<SharedBuffer>, SharedBuffer),
+    Archive(Archive),
+}
+
+///Encodes frames one at a time as they're rendered, so that only the encoded animation is kept
+///in memory
+pub struct FrameEncoder {
+    width: u32,
+    height: u32,
+    fps: u32,
+    written: u32,
+    container: Container,
+}
+
+impl FrameEncoder {
+    pub fn new(
+        format: AnimationFormat,
+        width: u32,
+        height: u32,
+        frames: u32,
+        fps: u32,
+    ) -> Result<Self, String> {
+        let container = match format {
+            AnimationFormat::Gif => {
+                let too_big = |_| "Gifs can be at most 65535 pixels wide and tall".to_string();
+                let mut encoder = gif::Encoder::new(
+                    Vec::new(),
+                    u16::try_from(width).map_err(too_big)?,
+                    u16::try_from(height).map_err(too_big)?,
+                    &[],
+                )
+                .map_err(|e| e.to_string())?;
+                encoder
+                    .set_repeat(gif::Repeat::Infinite)
+                    .map_err(|e| e.to_string())?;
+                Container::Gif(encoder)
+            }
+            AnimationFormat::Apng => {
+                let buffer = SharedBuffer::default();
+                let mut encoder = png::Encoder::new(buffer.clone(), width, height);
+                encoder.set_color(png::ColorType::Rgba);
+                encoder.set_depth(png::BitDepth::Eight);
+                encoder.set_animated(frames, 0).map_err(|e| e.to_string())?;
+                encoder
+                    .set_frame_delay(1, fps as u16)
+                    .map_err(|e| e.to_string())?;
+                Container::Apng(encoder.write_header().map_err(|e| e.to_string())?, buffer)
+            }
+            AnimationFormat::Zip => Container::Archive(Archive::new(ArchiveFormat::Zip)),
+            AnimationFormat::Tar => Container::Archive(Archive::new(ArchiveFormat::Tar)),
+        };
+        Ok(Self {
+            width,
+            height,
+            fps,
+            written: 0,
+            container,
+        })
+    }
+
+    ///Adds the tightly packed rgba rows of the next frame
+    pub fn add_frame(&mut self, mut img: Vec<u8>) -> Result<(), String> {
+        match &mut self.container {
+            Container::Gif(encoder) => {
+                let mut frame = gif::Frame::from_rgba_speed(
+                    self.width as u16,
+                    self.height as u16,
+                    &mut img,
+                    10,
+                );
+                //In hundredths of a second
+                frame.delay = ((100 + self.fps / 2) / self.fps) as u16;
+                encoder.write_frame(&frame).map_err(|e| e.to_string())?;
+            }
+            Container::Apng(writer, _) => {
+                writer.write_image_data(&img).map_err(|e| e.to_string())?;
+            }
+            Container::Archive(archive) => {
+                let png = arr_to_image(
+                    img,
+                    self.width,
+                    self.height,
+                    OutputFormat::Png,
+                    grimoire::DEFAULT_JPEG_QUALITY,
+                )
+                .map_err(|e| e.to_string())?;
+                archive.add(&format!("frame_{:04}.png", self.written), &png)?;
+            }
+        }
+        self.written += 1;
+        Ok(())
+    }
+
+    pub fn finish(self) -> Result<Vec<u8>, String> {
+        match self.container {
+            Container::Gif(encoder) => encoder.into_inner().map_err(|e| e.to_string()),
+            Container::Apng(writer, buffer) => {
+                writer.finish().map_err(|e| e.to_string())?;
+                let data = std::mem::take(&mut *buffer.0.lock().unwrap());
+                Ok(data)
+            }
+            Container::Archive(archive) => archive.finish(),
+        }
+    }
+}
+
+#[test]
+fn test_keyframes() {
+    let start = Keyframe {
+        position: [0.0, 0.0],
+        zoom: 1.0,
+    };
+    let end = Keyframe {
+        position: [1.0, -1.0],
+        zoom: 100.0,
+    };
+    let frames = keyframes(start, end, 5, Easing::Exponential);
+    assert_eq!(frames.len(), 5);
+    assert_eq!(frames[0], start);
+    assert!((frames[4].zoom - end.zoom).abs() < 1e-3);
+    assert!((frames[4].position[0] - end.position[0]).abs() < 1e-6);
+    //Same factor every frame
+    assert!((frames[2].zoom - 10.0).abs() < 1e-4);
+    //Most of the movement happens while the view is still big
+    assert!(frames[2].position[0] > 0.5);
+
+    let frames = keyframes(start, end, 3, Easing::Linear);
+    assert!((frames[1].zoom - 50.5).abs() < 1e-4);
+    //Without a change in zoom the position moves linearly
+    let frames = keyframes(start, Keyframe { zoom: 1.0, ..end }, 3, Easing::Linear);
+    assert_eq!(frames[1].position, [0.5, -0.5]);
+}
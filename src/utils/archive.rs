@@ -0,0 +1,53 @@
+use std::io::{Cursor, Write};
+
+use zip::{write::FileOptions, CompressionMethod, ZipWriter};
+
+use crate::structs::requests::ArchiveFormat;
+
+///Zip or tar archive that's built in memory
+pub enum Archive {
+    Zip(ZipWriter<Cursor<Vec<u8>>>),
+    Tar(tar::Builder<Vec<u8>>),
+}
+
+impl Archive {
+    pub fn new(format: ArchiveFormat) -> Self {
+        match format {
+            ArchiveFormat::Zip => Self::Zip(ZipWriter::new(Cursor::new(Vec::new()))),
+            ArchiveFormat::Tar => Self::Tar(tar::Builder::new(Vec::new())),
+        }
+    }
+
+    ///Adds a file, parent folders don't need to be added first
+    pub fn add(&mut self, name: &str, data: &[u8]) -> Result<(), String> {
+        match self {
+            Self::Zip(archive) => {
+                //Everything that goes in is already compressed
+                let options = FileOptions::default().compression_method(CompressionMethod::Stored);
+                archive
+                    .start_file(name, options)
+                    .map_err(|e| e.to_string())?;
+                archive.write_all(data).map_err(|e| e.to_string())
+            }
+            Self::Tar(archive) => {
+                let mut header = tar::Header::new_gnu();
+                header.set_size(data.len() as u64);
+                header.set_mode(0o644);
+                header.set_cksum();
+                archive
+                    .append_data(&mut header, name, data)
+                    .map_err(|e| e.to_string())
+            }
+        }
+    }
+
+    pub fn finish(self) -> Result<Vec<u8>, String> {
+        match self {
+            Self::Zip(mut archive) => archive
+                .finish()
+                .map(Cursor::into_inner)
+                .map_err(|e| e.to_string()),
+            Self::Tar(archive) => archive.into_inner().map_err(|e| e.to_string()),
+        }
+    }
+}
@@ -0,0 +1,172 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    hash::Hash,
+    time::{Duration, Instant},
+};
+
+///Counters exposed on the admin endpoint
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, serde_derive::Serialize, serde_derive::Deserialize,
+)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    ///Entries removed to make space for new ones
+    pub evictions: u64,
+    ///Entries removed bc they were older than the ttl
+    pub expirations: u64,
+    pub entries: usize,
+    pub bytes: u64,
+    pub max_bytes: u64,
+}
+
+struct Entry<V> {
+    value: V,
+    size: u64,
+    created: Instant,
+    ///Position in the lru order
+    last_used: u64,
+}
+
+///Cache with a size limit in bytes, evicts the least recently used entries first
+///
+///Only the sizes given when inserting count towards the limit
+pub struct LruCache<K, V> {
+    entries: HashMap<K, Entry<V>>,
+    ///Keys ordered by when they were last used, oldest first
+    order: BTreeMap<u64, K>,
+    tick: u64,
+    ttl: Option<Duration>,
+    stats: CacheStats,
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> LruCache<K, V> {
+    pub fn new(max_bytes: u64, ttl: Option<Duration>) -> Self {
+        Self {
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+            tick: 0,
+            ttl,
+            stats: CacheStats {
+                max_bytes,
+                ..Default::default()
+            },
+        }
+    }
+
+    ///Returns a copy of the value and marks it as recently used
+    pub fn get(&mut self, key: &K) -> Option<V> {
+        let expired = match self.entries.get(key) {
+            None => {
+                self.stats.misses += 1;
+                return None;
+            }
+            Some(entry) => self.ttl.is_some_and(|ttl| entry.created.elapsed() > ttl),
+        };
+        if expired {
+            self.remove(key);
+            self.stats.expirations += 1;
+            self.stats.misses += 1;
+            return None;
+        }
+
+        self.tick += 1;
+        let entry = self.entries.get_mut(key)?;
+        let key = self.order.remove(&entry.last_used)?;
+        entry.last_used = self.tick;
+        self.order.insert(self.tick, key);
+        self.stats.hits += 1;
+        Some(entry.value.clone())
+    }
+
+    ///Stores the value, evicting old entries if it doesn't fit, returns the evicted keys. Values
+    ///bigger than the whole cache are not stored
+    pub fn insert(&mut self, key: K, value: V, size: u64) -> Vec<K> {
+        let mut evicted = Vec::new();
+        if size > self.stats.max_bytes {
+            return evicted;
+        }
+        self.remove(&key);
+        while self.stats.bytes + size > self.stats.max_bytes {
+            let Some((_, oldest)) = self.order.pop_first() else {
+                break;
+            };
+            if let Some(entry) = self.entries.remove(&oldest) {
+                self.stats.bytes -= entry.size;
+                self.stats.evictions += 1;
+                evicted.push(oldest);
+            }
+        }
+
+        self.tick += 1;
+        self.order.insert(self.tick, key.clone());
+        self.entries.insert(
+            key,
+            Entry {
+                value,
+                size,
+                created: Instant::now(),
+                last_used: self.tick,
+            },
+        );
+        self.stats.bytes += size;
+        evicted
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.order.clear();
+        self.stats.bytes = 0;
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            entries: self.entries.len(),
+            ..self.stats
+        }
+    }
+
+    pub fn remove(&mut self, key: &K) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.order.remove(&entry.last_used);
+            self.stats.bytes -= entry.size;
+        }
+    }
+}
+
+#[test]
+fn test_lru_eviction() {
+    let mut cache = LruCache::new(10, None);
+    cache.insert("a", vec![0; 4], 4);
+    cache.insert("b", vec![0; 4], 4);
+    //Using a makes b the least recently used one
+    assert!(cache.get(&"a").is_some());
+    assert_eq!(cache.insert("c", vec![0; 4], 4), vec!["b"]);
+
+    assert!(cache.get(&"b").is_none());
+    assert!(cache.get(&"a").is_some());
+    assert!(cache.get(&"c").is_some());
+
+    //Too big to ever fit
+    cache.insert("d", vec![0; 11], 11);
+    assert!(cache.get(&"d").is_none());
+
+    let stats = cache.stats();
+    assert_eq!(stats.evictions, 1);
+    assert_eq!(stats.hits, 3);
+    assert_eq!(stats.misses, 2);
+    assert_eq!(stats.entries, 2);
+    assert_eq!(stats.bytes, 8);
+}
+
+#[test]
+fn test_cache_ttl() {
+    let mut cache = LruCache::new(10, Some(Duration::ZERO));
+    cache.insert("a", vec![0; 4], 4);
+    std::thread::sleep(Duration::from_millis(1));
+
+    assert!(cache.get(&"a").is_none());
+    let stats = cache.stats();
+    assert_eq!(stats.expirations, 1);
+    assert_eq!(stats.bytes, 0);
+}
@@ -0,0 +1,151 @@
+use std::{collections::HashMap, hash::Hash, sync::Mutex};
+
+use futures::channel::oneshot;
+
+///Counters exposed on the admin endpoint
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, serde_derive::Serialize, serde_derive::Deserialize,
+)]
+pub struct CoalesceStats {
+    ///Requests that did the work themselves
+    pub renders: u64,
+    ///Requests that got the result of an identical one instead, so renders that were saved
+    pub coalesced: u64,
+    pub in_flight: usize,
+}
+
+struct State<K, V> {
+    ///Requests waiting for each key that's currently being worked on
+    waiting: HashMap<K, Vec<oneshot::Sender<V>>>,
+    stats: CoalesceStats,
+}
+
+///Deduplicates identical work that runs at the same time, the first caller does it and everyone
+///else waits for its result
+pub struct Coalescer<K, V> {
+    state: Mutex<State<K, V>>,
+}
+
+pub enum Joined<'a, K: Hash + Eq + Clone, V: Clone> {
+    ///Should do the work and hand the result to [`Leader::finish`]
+    Leader(Leader<'a, K, V>),
+    ///Resolves to the leaders result, or gets canceled if the leader was dropped before finishing
+    Follower(oneshot::Receiver<V>),
+}
+
+///Held while doing the work, dropping it without finishing cancels everyone waiting
+pub struct Leader<'a, K: Hash + Eq + Clone, V: Clone> {
+    coalescer: &'a Coalescer<K, V>,
+    ///Taken once finished, so that drop doesn't remove a newer leader
+    key: Option<K>,
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> Coalescer<K, V> {
+    pub fn new() -> Self {
+        Self {
+            state: Mutex::new(State {
+                waiting: HashMap::new(),
+                stats: CoalesceStats::default(),
+            }),
+        }
+    }
+
+    pub fn join(&self, key: &K) -> Joined<'_, K, V> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(waiting) = state.waiting.get_mut(key) {
+            let (sender, receiver) = oneshot::channel();
+            waiting.push(sender);
+            return Joined::Follower(receiver);
+        }
+        state.waiting.insert(key.clone(), Vec::new());
+        state.stats.renders += 1;
+        Joined::Leader(Leader {
+            coalescer: self,
+            key: Some(key.clone()),
+        })
+    }
+
+    pub fn stats(&self) -> CoalesceStats {
+        let state = self.state.lock().unwrap();
+        CoalesceStats {
+            in_flight: state.waiting.len(),
+            ..state.stats
+        }
+    }
+
+    fn remove(&self, key: &K) -> Vec<oneshot::Sender<V>> {
+        self.state
+            .lock()
+            .unwrap()
+            .waiting
+            .remove(key)
+            .unwrap_or_default()
+    }
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> Default for Coalescer<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> Leader<'_, K, V> {
+    ///Sends the result to everyone that joined in the meantime
+    pub fn finish(mut self, value: &V) {
+        let Some(key) = self.key.take() else {
+            return;
+        };
+        //They might have disconnected already, those aren't counted as coalesced
+        let delivered = self
+            .coalescer
+            .remove(&key)
+            .into_iter()
+            .filter_map(|sender| sender.send(value.clone()).ok())
+            .count();
+        self.coalescer.state.lock().unwrap().stats.coalesced += delivered as u64;
+    }
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> Drop for Leader<'_, K, V> {
+    fn drop(&mut self) {
+        if let Some(key) = self.key.take() {
+            self.coalescer.remove(&key);
+        }
+    }
+}
+
+#[test]
+fn test_coalescing() {
+    let coalescer = Coalescer::<&str, u32>::new();
+    let Joined::Leader(leader) = coalescer.join(&"a") else {
+        panic!("First request should do the work");
+    };
+    let Joined::Follower(follower) = coalescer.join(&"a") else {
+        panic!("Identical request should wait");
+    };
+    //Different keys don't wait for each other
+    assert!(matches!(coalescer.join(&"b"), Joined::Leader(_)));
+    assert_eq!(coalescer.stats().in_flight, 1);
+
+    //Followers that leave before the result is ready aren't counted
+    let Joined::Follower(gone) = coalescer.join(&"a") else {
+        panic!("Identical request should wait");
+    };
+    drop(gone);
+    leader.finish(&42);
+    assert_eq!(futures::executor::block_on(follower), Ok(42));
+
+    //Dropping the leader cancels the followers, so they can retry
+    let leader = coalescer.join(&"a");
+    let Joined::Follower(follower) = coalescer.join(&"a") else {
+        panic!("Identical request should wait");
+    };
+    drop(leader);
+    assert!(futures::executor::block_on(follower).is_err());
+
+    //Only the follower that got 42 saved a render
+    let stats = coalescer.stats();
+    assert_eq!(stats.renders, 3);
+    assert_eq!(stats.coalesced, 1);
+    assert_eq!(stats.in_flight, 0);
+}
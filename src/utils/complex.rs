@@ -0,0 +1,208 @@
+use std::ops::{Add, Div, Mul, Neg, Sub};
+
+///Complex number used by the cpu renderer, mirrors the `vec2<f32>` used in the shaders,
+///including the component wise operations that the shaders rely on
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Complex {
+    pub re: f32,
+    pub im: f32,
+}
+
+impl Complex {
+    pub const fn new(re: f32, im: f32) -> Self {
+        Self { re, im }
+    }
+
+    pub const fn conj(self) -> Self {
+        Self::new(self.re, -self.im)
+    }
+
+    ///Squared length, same as `dot(z, z)`
+    pub fn norm(self) -> f32 {
+        self.re * self.re + self.im * self.im
+    }
+
+    pub fn length(self) -> f32 {
+        self.norm().sqrt()
+    }
+
+    pub fn arg(self) -> f32 {
+        self.im.atan2(self.re)
+    }
+
+    ///Component wise absolute value
+    pub fn abs(self) -> Self {
+        Self::new(self.re.abs(), self.im.abs())
+    }
+
+    pub fn square(self) -> Self {
+        Self::new(
+            self.re * self.re - self.im * self.im,
+            2.0 * self.re * self.im,
+        )
+    }
+
+    pub fn cube(self) -> Self {
+        let x2 = self.re * self.re;
+        let y2 = self.im * self.im;
+        Self::new(
+            self.re * x2 - 3.0 * self.re * y2,
+            3.0 * x2 * self.im - self.im * y2,
+        )
+    }
+
+    ///Component wise multiplication, `a * b` on vectors in the shaders
+    pub fn scale(self, other: Self) -> Self {
+        Self::new(self.re * other.re, self.im * other.im)
+    }
+
+    pub fn exp(self) -> Self {
+        let r = self.re.exp();
+        Self::new(r * self.im.cos(), r * self.im.sin())
+    }
+
+    pub fn ln(self) -> Self {
+        Self::new(self.length().ln(), self.arg())
+    }
+
+    pub fn sqrt(self) -> Self {
+        let r = self.length().sqrt();
+        let angle = self.arg() * 0.5;
+        Self::new(r * angle.cos(), r * angle.sin())
+    }
+
+    pub fn sin(self) -> Self {
+        Self::new(
+            self.re.sin() * self.im.cosh(),
+            self.re.cos() * self.im.sinh(),
+        )
+    }
+
+    pub fn cos(self) -> Self {
+        Self::new(
+            self.re.cos() * self.im.cosh(),
+            -self.re.sin() * self.im.sinh(),
+        )
+    }
+
+    pub fn tan(self) -> Self {
+        self.sin() / self.cos()
+    }
+
+    pub fn sinh(self) -> Self {
+        Self::new(
+            self.re.sinh() * self.im.cos(),
+            self.re.cosh() * self.im.sin(),
+        )
+    }
+
+    pub fn cosh(self) -> Self {
+        Self::new(
+            self.re.cosh() * self.im.cos(),
+            self.re.sinh() * self.im.sin(),
+        )
+    }
+
+    pub fn tanh(self) -> Self {
+        self.sinh() / self.cosh()
+    }
+
+    pub fn pow(self, exponent: Self) -> Self {
+        if self.re == 0.0 && self.im == 0.0 {
+            return Self::default();
+        }
+        (exponent * self.ln()).exp()
+    }
+
+    ///Exponentiation by squaring
+    pub fn powi(self, n: i32) -> Self {
+        let mut result = Self::new(1.0, 0.0);
+        let mut base = self;
+        let mut power = n.unsigned_abs();
+        while power > 0 {
+            if power & 1 != 0 {
+                result = result * base;
+            }
+            base = base.square();
+            power >>= 1;
+        }
+        if n < 0 {
+            return Self::new(1.0, 0.0) / result;
+        }
+        result
+    }
+}
+
+impl Add for Complex {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self {
+        Self::new(self.re + rhs.re, self.im + rhs.im)
+    }
+}
+
+impl Sub for Complex {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self {
+        Self::new(self.re - rhs.re, self.im - rhs.im)
+    }
+}
+
+impl Mul for Complex {
+    type Output = Self;
+
+    fn mul(self, rhs: Self) -> Self {
+        Self::new(
+            self.re * rhs.re - self.im * rhs.im,
+            self.re * rhs.im + self.im * rhs.re,
+        )
+    }
+}
+
+impl Mul<f32> for Complex {
+    type Output = Self;
+
+    fn mul(self, rhs: f32) -> Self {
+        Self::new(self.re * rhs, self.im * rhs)
+    }
+}
+
+impl Div for Complex {
+    type Output = Self;
+
+    fn div(self, rhs: Self) -> Self {
+        let denumenator = 1.0 / rhs.norm();
+        Self::new(
+            (self.re * rhs.re + self.im * rhs.im) * denumenator,
+            (self.im * rhs.re - self.re * rhs.im) * denumenator,
+        )
+    }
+}
+
+impl Div<f32> for Complex {
+    type Output = Self;
+
+    fn div(self, rhs: f32) -> Self {
+        Self::new(self.re / rhs, self.im / rhs)
+    }
+}
+
+impl Neg for Complex {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        Self::new(-self.re, -self.im)
+    }
+}
+
+#[test]
+fn test_complex_arithmetic() {
+    let a = Complex::new(1.0, 2.0);
+    let b = Complex::new(3.0, -1.0);
+
+    assert_eq!(a * b, Complex::new(5.0, 5.0));
+    assert_eq!((a * b) / b, a);
+    assert_eq!(a.powi(3), a * a * a);
+    assert_eq!(a.cube(), a * a * a);
+}
@@ -0,0 +1,552 @@
+#![allow(
+    clippy::cast_precision_loss,
+    clippy::cast_possible_truncation,
+    clippy::cast_possible_wrap,
+    clippy::cast_sign_loss
+)]
+use std::num::NonZeroUsize;
+
+use super::complex::Complex;
+use crate::{
+    formula::{self, ast::TypedExpr, eval},
+    grimoire,
+    structs::rendering::{Fractals, RenderProgress, RenderRequest},
+};
+
+///Same magic value the shaders use to mark points that are known to be inside the set
+const INTERIOR: Complex = Complex::new(69.0, 4200.0);
+
+type Color = [f32; 3];
+
+///A port of the fragment shaders, kept as close to them as possible so that both backends produce
+///the same images
+struct Renderer<'a> {
+    request: &'a RenderRequest,
+    ///Type checked custom formula
+    formula: Option<TypedExpr>,
+    colors: Vec<Color>,
+    max_dot: f32,
+}
+
+impl<'a> Renderer<'a> {
+    fn new(request: &'a RenderRequest) -> Self {
+        let formula = match &request.fractal {
+            Fractals::Custom(formula) => {
+                Some(formula::parse(formula).expect("Formula should be validated before rendering"))
+            }
+            _ => None,
+        };
+        let max_dot = match request.fractal {
+            Fractals::Mandelbrot | Fractals::BurningShip | Fractals::Tricorn => 4.0,
+            Fractals::Feather | Fractals::Eye => 200_000.0,
+            Fractals::Custom(_) => grimoire::CUSTOM_MAX_DOT,
+        };
+        Self {
+            request,
+            formula,
+            colors: request
+                .colors
+                .iter()
+                .map(|c| [c.r as f32, c.g as f32, c.b as f32])
+                .collect(),
+            max_dot,
+        }
+    }
+
+    const fn flag(&self, flag: u32) -> bool {
+        self.request.uniforms.flags & flag != 0
+    }
+
+    fn fractal_func(&self, z: Complex, c: Complex) -> Complex {
+        let julia = self.flag(grimoire::rendering_flags::JULIA);
+        match &self.request.fractal {
+            Fractals::Mandelbrot => {
+                if !julia {
+                    let c2 = c.norm();
+                    if 256.0 * c2 * c2 - 96.0 * c2 + 32.0 * c.re - 3.0 < 0.0 {
+                        return INTERIOR;
+                    }
+                    if 16.0 * (c2 + 2.0 * c.re + 1.0) - 1.0 < 0.0 {
+                        return INTERIOR;
+                    }
+                }
+                z.square() + c
+            }
+            Fractals::BurningShip => z.abs().square() + c,
+            Fractals::Tricorn => z.conj().square() + c,
+            Fractals::Feather => {
+                if !julia && c.length() < 0.53 {
+                    return INTERIOR;
+                }
+                z.cube() / (Complex::new(1.0, 0.0) + z.scale(z)) + c
+            }
+            Fractals::Eye => {
+                if !julia && (c.re < -1.34 || c.re > 4.0 || c.im.abs() > 1.65) {
+                    return INTERIOR;
+                }
+                (z / c).square() + c
+            }
+            Fractals::Custom(_) => eval::evaluate(self.formula.as_ref().unwrap(), z, c),
+        }
+    }
+
+    ///Perturbation formulas, with dz and dc scaled by 1/s
+    fn perturb_func(&self, z: Complex, dz: Complex, dc: Complex, s: f32) -> Complex {
+        let product = (z * 2.0 + dz * s) * dz;
+        match self.request.fractal {
+            Fractals::Tricorn => product.conj() + dc,
+            _ => product + dc,
+        }
+    }
+
+    fn get_col(&self, coord: f32, col_num: i32) -> Color {
+        if col_num == 1 {
+            return self.colors[0];
+        }
+        if coord >= 1.0 {
+            return [coord; 3];
+        }
+        let arr_len = self.colors.len();
+        let stripe = coord * (col_num - 1) as f32 + self.request.uniforms.color_offset;
+        let i = stripe.floor().max(0.0) as usize;
+        let a = self.colors[i % arr_len];
+        let b = self.colors[(i + 1) % arr_len];
+        let t = stripe - i as f32;
+        [0, 1, 2].map(|j| a[j] * (1.0 - t) + b[j] * t)
+    }
+
+    fn get_color(&self, i: f32, max_i: u32) -> Color {
+        if i >= max_i as f32 {
+            return [0.0; 3];
+        }
+        self.get_col(i / max_i as f32, self.request.uniforms.num_colors as i32)
+    }
+
+    fn fractal(&self, uv: Complex) -> Color {
+        let (mut coords, c) = if self.flag(grimoire::rendering_flags::JULIA) {
+            let julia = self.request.uniforms.julia;
+            (uv, Complex::new(julia[0], julia[1]))
+        } else {
+            (Complex::default(), uv)
+        };
+        let mut iter = 0;
+        let max_iteration = self.request.uniforms.max_iter;
+
+        while coords.norm() <= self.max_dot && iter < max_iteration {
+            coords = self.fractal_func(coords, c);
+            iter += 1;
+        }
+        self.escape_color(iter, coords)
+    }
+
+    fn escape_color(&self, iter: u32, coords: Complex) -> Color {
+        let max_iteration = self.request.uniforms.max_iter;
+        if self.request.raw() {
+            if coords == INTERIOR {
+                return [max_iteration as f32, 0.0, 0.0];
+            }
+            let mut i = max_iteration as f32;
+            if iter < max_iteration {
+                i = iter as f32 - coords.norm().log2().log2() + 4.0;
+            }
+            return [i, coords.length(), coords.arg()];
+        }
+        if iter >= max_iteration {
+            return [0.0; 3];
+        }
+
+        let mut i = iter as f32;
+        if coords == INTERIOR {
+            return [0.0; 3];
+        } else if self.flag(grimoire::rendering_flags::SMOOTH) {
+            i = i - coords.norm().log2().log2() + 4.0;
+        }
+        self.get_color(i, max_iteration)
+    }
+
+    fn deep_fractal(&self, offset: Complex) -> Color {
+        let Some(deep_zoom) = &self.request.deep_zoom else {
+            return [0.0; 3];
+        };
+        if !self.request.fractal.supports_deep_zoom() {
+            return [0.0; 3];
+        }
+        //Out of bounds reads are clamped on the gpu
+        let orbit = |m: usize| {
+            let point = deep_zoom.orbit[m.min(deep_zoom.orbit.len() - 1)];
+            Complex::new(point[0], point[1])
+        };
+        let uniforms = &self.request.uniforms;
+        let orbit_len = uniforms.orbit_len as usize;
+        let mut scale = uniforms.deep_exponent;
+        let mut dc = offset;
+        let mut dz = Complex::default();
+        let mut z = Complex::default();
+        let mut m = 0;
+        let mut iter = 0;
+
+        while iter < uniforms.max_iter {
+            dz = self.perturb_func(orbit(m), dz, dc, (scale as f32).exp2());
+            m += 1;
+            iter += 1;
+
+            if scale < 0 {
+                let magnitude = dz.re.abs().max(dz.im.abs());
+                if magnitude > 0.0 {
+                    let e = magnitude.log2().floor() as i32;
+                    let mut factor = (-e as f32).exp2();
+                    if scale + e > -40 {
+                        factor = (scale as f32).exp2();
+                        scale = 0;
+                    } else {
+                        scale += e;
+                    }
+                    dz = dz * factor;
+                    dc = dc * factor;
+                }
+            }
+
+            if scale < 0 {
+                z = orbit(m);
+                if z.norm() > self.max_dot || m + 1 >= orbit_len {
+                    break;
+                }
+            } else {
+                z = orbit(m) + dz;
+                if z.norm() > self.max_dot {
+                    break;
+                }
+                if z.norm() < dz.norm() || m + 1 >= orbit_len {
+                    dz = z;
+                    m = 0;
+                }
+            }
+        }
+        self.escape_color(iter, z)
+    }
+
+    ///Averaged color of the pixel, or the raw data in raw mode
+    fn pixel(&self, x: u32, y: u32) -> Color {
+        let uniforms = &self.request.uniforms;
+        let msaa = (uniforms.flags & 255) as f32;
+        let deep = self.flag(grimoire::rendering_flags::DEEP);
+        let position = Complex::new(uniforms.position[0], uniforms.position[1]);
+
+        //Same as the interpolated uv from the vertex shader, sampled at the center of the pixel
+        let [scale_x, scale_y, offset_x, offset_y] = uniforms.viewport;
+        let uv = Complex::new(
+            ((x as f32 + 0.5) / self.request.width as f32)
+                .mul_add(2.0, -1.0)
+                .mul_add(scale_x, offset_x)
+                / uniforms.aspect,
+            ((y as f32 + 0.5) / self.request.height as f32)
+                .mul_add(2.0, -1.0)
+                .mul_add(scale_y, offset_y),
+        ) * Complex::new(uniforms.rotation[0], uniforms.rotation[1]);
+        let transformed_uv = uv / uniforms.zoom + position;
+        if self.flag(grimoire::rendering_flags::DEBUG) && !deep {
+            if uv.length() < 0.025 {
+                return [0.0, 0.0, 1.0];
+            }
+            if transformed_uv.re.abs() % 0.1 < 0.01 {
+                return [1.0, 0.0, 0.0];
+            }
+            if transformed_uv.im.abs() % 0.1 < 0.01 {
+                return [0.0, 1.0, 0.0];
+            }
+        }
+
+        let mut col = [0.0; 3];
+        let mut i = 0.0;
+        while i < msaa {
+            let dxy = Complex::new(rand(i * 0.1234), rand(i * 0.5678)) / 10000.0;
+            let sample = if deep {
+                self.deep_fractal((uv + dxy) / uniforms.zoom)
+            } else {
+                self.fractal((uv + dxy) / uniforms.zoom + position)
+            };
+            for (col, sample) in col.iter_mut().zip(sample) {
+                *col += sample;
+            }
+            i += 1.0;
+        }
+
+        col.map(|c| c / msaa)
+    }
+}
+
+fn rand(s: f32) -> f32 {
+    let x = (s * 12.9898).sin() * 43758.547;
+    x - x.floor()
+}
+
+///Same conversion the gpu does when writing into an `Rgba8Unorm` texture, NaN ends up as 0
+fn to_unorm(value: f32) -> u8 {
+    (value.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+///[`render_with_progress`] when nobody is watching
+#[cfg(test)]
+pub fn render(request: &RenderRequest) -> Vec<u8> {
+    render_with_progress(request, &RenderProgress::default()).unwrap()
+}
+
+///Renders the image on all cpu cores, returns tightly packed rows in the same format as the
+///texture the gpu would render into. `progress` is advanced after every row, and errors if it gets
+///canceled
+pub fn render_with_progress(
+    request: &RenderRequest,
+    progress: &RenderProgress,
+) -> Result<Vec<u8>, String> {
+    let renderer = Renderer::new(request);
+    let pixel_size = request.bytes_per_pixel();
+    let row_size = request.width as usize * pixel_size;
+    let mut pixels = vec![0; row_size * request.height as usize];
+    if pixels.is_empty() {
+        return Ok(pixels);
+    }
+
+    //Rows are dealt out in turns, so that the slow parts of the image are spread between threads
+    let threads = std::thread::available_parallelism().map_or(1, NonZeroUsize::get);
+    let mut batches = (0..threads).map(|_| Vec::new()).collect::<Vec<_>>();
+    for (y, row) in pixels.chunks_exact_mut(row_size).enumerate() {
+        batches[y % threads].push((y as u32, row));
+    }
+
+    std::thread::scope(|scope| {
+        for batch in batches {
+            let renderer = &renderer;
+            scope.spawn(move || {
+                for (y, row) in batch {
+                    if progress.is_canceled() {
+                        return;
+                    }
+                    for (x, pixel) in row.chunks_exact_mut(pixel_size).enumerate() {
+                        let [r, g, b] = renderer.pixel(x as u32, y);
+                        if request.raw() {
+                            for (bytes, value) in pixel.chunks_exact_mut(4).zip([r, g, b, 1.0]) {
+                                bytes.copy_from_slice(&value.to_ne_bytes());
+                            }
+                        } else {
+                            pixel.copy_from_slice(&[to_unorm(r), to_unorm(g), to_unorm(b), 255]);
+                        }
+                    }
+                    progress.advance(u64::from(request.width));
+                }
+            });
+        }
+    });
+    if progress.is_canceled() {
+        return Err("The render was canceled".to_string());
+    }
+    Ok(pixels)
+}
+
+///Colors the raw escape time data of a render the same way the shaders would, without iterating
+///again. The data should come from [`crate::structs::rendering::RenderParameters::to_raw_field`]
+///of the same view
+pub fn recolor(request: &RenderRequest, field: &[u8]) -> Vec<u8> {
+    let renderer = Renderer::new(request);
+    let max_iteration = request.uniforms.max_iter;
+    field
+        .chunks_exact(16)
+        .flat_map(|pixel| {
+            let [mut i, magnitude] = [0, 4].map(|start| {
+                f32::from_ne_bytes([
+                    pixel[start],
+                    pixel[start + 1],
+                    pixel[start + 2],
+                    pixel[start + 3],
+                ])
+            });
+            //The raw iteration count is always smooth, so undo it
+            if !renderer.flag(grimoire::rendering_flags::SMOOTH) && i < max_iteration as f32 {
+                i = (i + (magnitude * magnitude).log2().log2() - 4.0).round();
+            }
+            let [r, g, b] = renderer.get_color(i, max_iteration);
+            [to_unorm(r), to_unorm(g), to_unorm(b), 255]
+        })
+        .collect()
+}
+
+#[test]
+fn test_cpu_matches_gpu() {
+    use crate::structs::{
+        rendering::{Backend, PipelineStore},
+        requests::{RequestBody, SimplifiedFractals},
+    };
+    use actix_web::web::Query;
+
+    let backend = futures::executor::block_on(super::graphics::generate_backend()).unwrap();
+    if matches!(backend, Backend::Cpu) {
+        //Nothing to compare against, said out loud so that it doesn't look like a pass
+        eprintln!("Skipping test_cpu_matches_gpu, there's no usable gpu adapter");
+        return;
+    }
+    let pipelines = PipelineStore::default();
+
+    let cases = [
+        (SimplifiedFractals::Mandelbrot, "smooth=true&msaa=4"),
+        (SimplifiedFractals::BurningShip, "max_iterations=200"),
+        (
+            SimplifiedFractals::BurningShip,
+            "angle=30&debug=true&position_y=-0.5",
+        ),
+        (SimplifiedFractals::Tricorn, "smooth=true"),
+        (
+            SimplifiedFractals::Feather,
+            "julia=true&julia_x=0.3&julia_y=0.1",
+        ),
+        (SimplifiedFractals::Eye, "position_x=0.5&zoom=0.5"),
+        (SimplifiedFractals::Custom, "formula=z%5E3%20%2B%20c*sin(z)"),
+        (
+            SimplifiedFractals::Mandelbrot,
+            "deep=true&deep_x=0&deep_y=1&deep_zoom=1e30&max_iterations=3000",
+        ),
+        (
+            SimplifiedFractals::Mandelbrot,
+            "deep=true&deep_x=0&deep_y=1&deep_zoom=1e30&angle=1&angle_unit=radians",
+        ),
+    ];
+    for (fractal, query) in cases {
+        let query = Query::<RequestBody>::from_query(&format!("width=160&height=90&{query}"))
+            .unwrap()
+            .into_inner();
+        let request = query
+            .to_parameters(fractal)
+            .unwrap()
+            .to_render_request()
+            .unwrap();
+
+        let gpu =
+            futures::executor::block_on(super::graphics::render(&backend, &pipelines, &request))
+                .unwrap();
+        let cpu = render(&request);
+        assert_eq!(gpu.len(), cpu.len());
+
+        //Points on the boundary are chaotic, so they can end up with a different iteration count
+        //due to tiny float differences, like fused multiply adds on the gpu. Only require that
+        //almost all pixels match
+        let different = gpu
+            .chunks_exact(4)
+            .zip(cpu.chunks_exact(4))
+            .filter(|(a, b)| a.iter().zip(b.iter()).any(|(a, b)| a.abs_diff(*b) > 8))
+            .count();
+        assert!(
+            different * 20 < gpu.len() / 4,
+            "{fractal:?} {query:?}: {different} pixels differ"
+        );
+    }
+}
+
+#[test]
+fn test_raw_cpu_matches_gpu() {
+    use crate::structs::{
+        rendering::{Backend, PipelineStore},
+        requests::{RequestBody, SimplifiedFractals},
+    };
+    use actix_web::web::Query;
+
+    let backend = futures::executor::block_on(super::graphics::generate_backend()).unwrap();
+    if matches!(backend, Backend::Cpu) {
+        eprintln!("Skipping test_raw_cpu_matches_gpu, there's no usable gpu adapter");
+        return;
+    }
+    let pipelines = PipelineStore::default();
+    let request = Query::<RequestBody>::from_query("width=160&height=90&format=npy")
+        .unwrap()
+        .to_parameters(SimplifiedFractals::Mandelbrot)
+        .unwrap()
+        .to_render_request()
+        .unwrap();
+
+    let gpu = futures::executor::block_on(super::graphics::render(&backend, &pipelines, &request))
+        .unwrap();
+    let cpu = render(&request);
+    assert_eq!(gpu.len(), 160 * 90 * 16);
+    assert_eq!(gpu.len(), cpu.len());
+
+    let iterations = |data: &[u8]| {
+        data.chunks_exact(16)
+            .map(|pixel| f32::from_ne_bytes([pixel[0], pixel[1], pixel[2], pixel[3]]))
+            .collect::<Vec<_>>()
+    };
+    //Same tolerance as for the colors, the boundary is chaotic
+    let different = iterations(&gpu)
+        .into_iter()
+        .zip(iterations(&cpu))
+        .filter(|(a, b)| (a - b).abs() > 0.1)
+        .count();
+    assert!(different * 20 < 160 * 90, "{different} pixels differ");
+}
+
+#[test]
+fn test_recolor_matches_render() {
+    use crate::structs::requests::{RequestBody, SimplifiedFractals};
+    use actix_web::web::Query;
+
+    for query in [
+        "smooth=true&color_offset=2.5",
+        "color_offset=1",
+        "julia=true",
+    ] {
+        let parameters = Query::<RequestBody>::from_query(&format!("width=64&height=48&{query}"))
+            .unwrap()
+            .to_parameters(SimplifiedFractals::Mandelbrot)
+            .unwrap();
+        let request = parameters.to_render_request().unwrap();
+        let field = render(&parameters.to_raw_field().to_render_request().unwrap());
+
+        let different = recolor(&request, &field)
+            .chunks_exact(4)
+            .zip(render(&request).chunks_exact(4))
+            .filter(|(a, b)| a.iter().zip(b.iter()).any(|(a, b)| a.abs_diff(*b) > 1))
+            .count();
+        assert_eq!(different, 0, "{query}");
+    }
+}
+
+#[test]
+fn test_rotation() {
+    use crate::structs::requests::{RequestBody, SimplifiedFractals};
+    use actix_web::web::Query;
+
+    let image = |query: &str| {
+        let request = Query::<RequestBody>::from_query(&format!(
+            "width=64&height=64&position_x=-0.5&position_y=-0.5&{query}"
+        ))
+        .unwrap()
+        .to_parameters(SimplifiedFractals::BurningShip)
+        .unwrap()
+        .to_render_request()
+        .unwrap();
+        render(&request)
+    };
+    let pixel = |data: &[u8], x: usize, y: usize| data[(y * 64 + x) * 4];
+    let straight = image("");
+    let rotated = image("angle=90");
+    assert_ne!(straight, rotated);
+
+    //Turning the view counter-clockwise moves whatever was at the top to the right
+    let different = (0..64)
+        .flat_map(|y| (0..64).map(move |x| (x, y)))
+        .filter(|&(x, y)| pixel(&rotated, x, y).abs_diff(pixel(&straight, y, 63 - x)) > 8)
+        .count();
+    assert!(different < 64 * 64 / 20, "{different} pixels differ");
+}
+
+#[test]
+fn test_canceled_render() {
+    use crate::structs::requests::{RequestBody, SimplifiedFractals};
+
+    let request = actix_web::web::Query::<RequestBody>::from_query("width=64&height=64")
+        .unwrap()
+        .to_parameters(SimplifiedFractals::Mandelbrot)
+        .unwrap()
+        .to_render_request()
+        .unwrap();
+    let progress = RenderProgress::new(64 * 64);
+    progress.cancel();
+    assert!(render_with_progress(&request, &progress).is_err());
+    assert_eq!(progress.fraction(), 0.0);
+}
@@ -0,0 +1,227 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use actix_web::web;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use super::cache::{CacheStats, LruCache};
+use crate::grimoire;
+
+///Stored next to every cached image
+#[derive(Debug, Clone, serde_derive::Serialize, serde_derive::Deserialize)]
+pub struct Metadata {
+    ///Parameters the image was rendered with
+    pub key: serde_json::Value,
+    ///Unix timestamp in seconds
+    pub created: u64,
+    pub render_time_ms: u64,
+    pub size: u64,
+}
+
+///Second cache tier, keeps encoded images on disk so that they survive restarts
+///
+///Every image is stored as `<hash>.bin` along with `<hash>.json` for the metadata, where the hash
+///is a sha256 of the key serialized as json
+///
+///Reading and writing the files happens on the blocking thread pool, the lock is only held while
+///updating the index
+pub struct DiskCache {
+    dir: PathBuf,
+    ///The files are the source of truth, this only keeps track of their sizes and lru order
+    index: Mutex<LruCache<String, ()>>,
+}
+
+impl DiskCache {
+    ///Opens the cache directory, creating it if needed, and indexes the images that are already
+    ///in it
+    pub fn open(dir: impl Into<PathBuf>, max_bytes: u64) -> io::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+
+        let mut existing = Vec::new();
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            let Some(hash) = path.file_stem().and_then(|s| s.to_str()) else {
+                continue;
+            };
+            let hash = hash.to_string();
+            match path.extension().and_then(|e| e.to_str()) {
+                Some("json") => {
+                    //Broken entries are skipped, they get overwritten eventually
+                    let metadata = fs::read(&path)
+                        .ok()
+                        .and_then(|data| serde_json::from_slice::<Metadata>(&data).ok());
+                    if let Some(metadata) = metadata {
+                        existing.push((metadata.created, hash, metadata.size));
+                    }
+                }
+                //Left behind if writing the metadata failed
+                Some("bin") if !dir.join(format!("{hash}.json")).exists() => {
+                    fs::remove_file(&path)?;
+                }
+                _ => {}
+            }
+        }
+
+        //Oldest first, so that they are the first ones to be evicted
+        existing.sort();
+        let mut index = LruCache::new(max_bytes, None);
+        for (_, hash, size) in existing {
+            delete(&dir, &index.insert(hash, (), size));
+        }
+        Ok(Self {
+            dir,
+            index: Mutex::new(index),
+        })
+    }
+
+    ///Stable hash of the key, used as the file name
+    pub fn hash<K: Serialize>(key: &K) -> String {
+        let json = serde_json::to_vec(key).expect("Cache keys should be serializable");
+        Sha256::digest(json)
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect()
+    }
+
+    pub async fn get<K: Serialize>(&self, key: &K) -> Option<Vec<u8>> {
+        let hash = Self::hash(key);
+        self.index.lock().unwrap().get(&hash)?;
+        let path = self.dir.join(format!("{hash}.bin"));
+        match web::block(move || fs::read(path)).await {
+            Ok(Ok(data)) => Some(data),
+            Ok(Err(e)) => {
+                log::warn!(
+                    target: grimoire::LOGGING_TARGET,
+                    "Could not read cached image {hash}: {e}"
+                );
+                self.index.lock().unwrap().remove(&hash);
+                None
+            }
+            Err(e) => {
+                log::warn!(
+                    target: grimoire::LOGGING_TARGET,
+                    "Could not read cached image {hash}: {e}"
+                );
+                None
+            }
+        }
+    }
+
+    ///Writes the image and its metadata, evicting old images if there isn't enough space
+    pub async fn insert<K: Serialize>(
+        &self,
+        key: &K,
+        data: Vec<u8>,
+        render_time: Duration,
+    ) -> io::Result<()> {
+        let size = data.len() as u64;
+        if size > self.stats().max_bytes {
+            return Ok(());
+        }
+        let hash = Self::hash(key);
+        let metadata = Metadata {
+            key: serde_json::to_value(key)?,
+            created: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            render_time_ms: render_time.as_millis() as u64,
+            size,
+        };
+
+        let metadata = serde_json::to_vec_pretty(&metadata)?;
+
+        //The metadata is written last, so that an image without it is known to be incomplete
+        let (bin, json) = (
+            self.dir.join(format!("{hash}.bin")),
+            self.dir.join(format!("{hash}.json")),
+        );
+        web::block(move || {
+            fs::write(bin, data)?;
+            fs::write(json, metadata)
+        })
+        .await
+        .map_err(io::Error::other)??;
+
+        let evicted = self.index.lock().unwrap().insert(hash, (), size);
+        if !evicted.is_empty() {
+            let dir = self.dir.clone();
+            web::block(move || delete(&dir, &evicted))
+                .await
+                .map_err(io::Error::other)?;
+        }
+        Ok(())
+    }
+
+    pub async fn clear(&self) {
+        //Emptied first, so that nothing is read from the files while they're being removed
+        self.index.lock().unwrap().clear();
+        let dir = self.dir.clone();
+        let result = web::block(move || {
+            for entry in fs::read_dir(&dir).into_iter().flatten().flatten() {
+                let path = entry.path();
+                if let Some("bin" | "json") = path.extension().and_then(|e| e.to_str()) {
+                    //It's gone from the index either way
+                    let _ = fs::remove_file(path);
+                }
+            }
+        })
+        .await;
+        if let Err(e) = result {
+            log::warn!(
+                target: grimoire::LOGGING_TARGET,
+                "Could not clear the disk cache {e}"
+            );
+        }
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.index.lock().unwrap().stats()
+    }
+}
+
+///Removes the files of evicted images
+fn delete(dir: &Path, hashes: &[String]) {
+    for hash in hashes {
+        for extension in ["bin", "json"] {
+            if let Err(e) = fs::remove_file(dir.join(format!("{hash}.{extension}"))) {
+                log::warn!(
+                    target: grimoire::LOGGING_TARGET,
+                    "Could not remove cached image {hash}: {e}"
+                );
+            }
+        }
+    }
+}
+
+#[actix_web::test]
+async fn test_disk_cache() {
+    let dir = std::env::temp_dir().join(format!("fractals_api_disk_cache_{}", std::process::id()));
+    let render_time = Duration::from_millis(10);
+
+    let cache = DiskCache::open(&dir, 10).unwrap();
+    cache.insert(&"a", vec![1; 4], render_time).await.unwrap();
+    cache.insert(&"b", vec![2; 4], render_time).await.unwrap();
+    assert_eq!(cache.get(&"a").await, Some(vec![1; 4]));
+    //b is the least recently used one
+    cache.insert(&"c", vec![3; 4], render_time).await.unwrap();
+    assert_eq!(cache.get(&"b").await, None);
+    assert!(!dir.join(format!("{}.bin", DiskCache::hash(&"b"))).exists());
+    drop(cache);
+
+    //Survives reopening
+    let cache = DiskCache::open(&dir, 10).unwrap();
+    assert_eq!(cache.get(&"a").await, Some(vec![1; 4]));
+    assert_eq!(cache.get(&"c").await, Some(vec![3; 4]));
+    assert_eq!(cache.stats().bytes, 8);
+
+    cache.clear().await;
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+    fs::remove_dir(dir).unwrap();
+}
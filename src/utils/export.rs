@@ -1,64 +1,159 @@
 use std::io::Cursor;
 
 use actix_web::web::Bytes;
-use image::{ImageBuffer, ImageError, Rgba};
+use image::{
+    codecs::pnm::{PnmSubtype, SampleEncoding},
+    error::{EncodingError, ImageFormatHint},
+    DynamicImage, ImageBuffer, ImageError, ImageOutputFormat, Rgb, Rgba,
+};
 
-use crate::grimoire;
+use crate::{grimoire, structs::requests::OutputFormat};
 
-///Transforms an array of raw image bytes into a specified format
+///Encodes the output of a render, quality is only used for jpeg
+pub fn encode(
+    data: Vec<u8>,
+    width: u32,
+    height: u32,
+    format: OutputFormat,
+    quality: u8,
+) -> Result<Vec<u8>, String> {
+    if format.is_raw() {
+        raw_to_file(&data, width, height, format)
+    } else {
+        arr_to_image(data, width, height, format, quality).map_err(|e| e.to_string())
+    }
+}
+
+///Transforms tightly packed rgba rows into a specified format, quality is only used for jpeg
 pub fn arr_to_image(
-    img: &[u8],
-    bytes_per_row: u32,
+    img: Vec<u8>,
     width: u32,
     height: u32,
-    format: image::ImageOutputFormat,
+    format: OutputFormat,
+    quality: u8,
 ) -> Result<Vec<u8>, ImageError> {
-    let img = img
-        .chunks_exact(4)
-        .map(|i| {
-            let mut array = [0; 4];
-            array.copy_from_slice(i);
-            Rgba(array)
-        })
-        .collect::<Vec<Rgba<u8>>>();
-
-    log::debug!(target: grimoire::LOGGING_TARGET, "Collected the array");
+    //Taking the vec avoids copying the image, which matters for really big ones
+    let image_buffer = ImageBuffer::<Rgba<u8>, Vec<u8>>::from_raw(width, height, img)
+        .expect("Image data should match its dimensions");
     log::debug!(
         target: grimoire::LOGGING_TARGET,
-        "BPR = {bytes_per_row}, width = {width}"
+        "Encoding a {width}x{height} image"
     );
-    let mut image_buffer = ImageBuffer::<Rgba<u8>, Vec<u8>>::new(width, height);
-    let mut x = 0;
-    let mut y = 0;
 
-    for i in img {
-        //Remove padding
-        if width != bytes_per_row && x >= width {
-            x += 1;
-            if x == bytes_per_row {
-                x = 0;
-                y += 1;
-            }
-            continue;
+    let mut byte_stream = Vec::new();
+    let mut cursor = Cursor::new(&mut byte_stream);
+    match format {
+        OutputFormat::Png => image_buffer.write_to(&mut cursor, ImageOutputFormat::Png)?,
+        //The alpha is always 1 anyway, and neither of these support it
+        OutputFormat::Jpeg => DynamicImage::ImageRgba8(image_buffer)
+            .into_rgb8()
+            .write_to(&mut cursor, ImageOutputFormat::Jpeg(quality))?,
+        OutputFormat::Ppm => DynamicImage::ImageRgba8(image_buffer)
+            .into_rgb8()
+            .write_to(
+                &mut cursor,
+                ImageOutputFormat::Pnm(PnmSubtype::Pixmap(SampleEncoding::Binary)),
+            )?,
+        //The image crate can only encode webp through libwebp, this encoder is lossless only so the
+        //quality doesn't apply
+        OutputFormat::Webp => image_webp::WebPEncoder::new(&mut cursor)
+            .encode(&image_buffer, width, height, image_webp::ColorType::Rgba8)
+            .map_err(|e| {
+                ImageError::Encoding(EncodingError::new(
+                    ImageFormatHint::Name("WebP".to_string()),
+                    e,
+                ))
+            })?,
+        OutputFormat::Bmp => image_buffer.write_to(&mut cursor, ImageOutputFormat::Bmp)?,
+        OutputFormat::Tiff => image_buffer.write_to(&mut cursor, ImageOutputFormat::Tiff)?,
+        OutputFormat::Qoi => image_buffer.write_to(&mut cursor, ImageOutputFormat::Qoi)?,
+        OutputFormat::Npy | OutputFormat::Exr | OutputFormat::Json => {
+            unreachable!("Raw data should be exported with raw_to_file")
         }
+    }
 
-        *image_buffer.get_pixel_mut(x, y) = i;
+    Ok(byte_stream)
+}
 
-        x += 1;
-        //This is kind of a hacky fix, but it works
-        if x == width && width == bytes_per_row {
-            x = 0;
-            y += 1;
-        }
-    }
+///Raw escape time data as json, every field is in row major order
+#[derive(Debug, serde_derive::Serialize, serde_derive::Deserialize)]
+pub struct RawData {
+    pub width: u32,
+    pub height: u32,
+    ///Smooth iteration count, points that didn't escape have `max_iterations`
+    pub iterations: Vec<f32>,
+    ///`|z|` when the point escaped
+    pub magnitude: Vec<f32>,
+    ///Angle of z when the point escaped, from -pi to pi
+    pub angle: Vec<f32>,
+}
+
+///Transforms tightly packed rows of raw data into npy, exr or json. The pixels are 4 native
+///endian floats, the iteration count, `|z|`, the angle and an unused one
+pub fn raw_to_file(
+    data: &[u8],
+    width: u32,
+    height: u32,
+    format: OutputFormat,
+) -> Result<Vec<u8>, String> {
     log::debug!(
         target: grimoire::LOGGING_TARGET,
-        "Iterated through the array"
+        "Exporting {width}x{height} raw data as {format:?}"
     );
-    let mut byte_stream = Vec::new();
-    image_buffer.write_to(&mut Cursor::new(&mut byte_stream), format)?;
+    let values = data
+        .chunks_exact(16)
+        .flat_map(|pixel| {
+            pixel[..12]
+                .chunks_exact(4)
+                .map(|value| f32::from_ne_bytes([value[0], value[1], value[2], value[3]]))
+        })
+        .collect::<Vec<_>>();
 
-    Ok(byte_stream)
+    match format {
+        OutputFormat::Npy => Ok(to_npy(&values, width, height)),
+        OutputFormat::Exr => {
+            let image = ImageBuffer::<Rgb<f32>, Vec<f32>>::from_raw(width, height, values)
+                .ok_or("Raw data should match its dimensions")?;
+            let mut byte_stream = Vec::new();
+            DynamicImage::ImageRgb32F(image)
+                .write_to(
+                    &mut Cursor::new(&mut byte_stream),
+                    ImageOutputFormat::OpenExr,
+                )
+                .map_err(|e| e.to_string())?;
+            Ok(byte_stream)
+        }
+        OutputFormat::Json => {
+            let field = |i: usize| values.iter().skip(i).step_by(3).copied().collect();
+            let raw = RawData {
+                width,
+                height,
+                iterations: field(0),
+                magnitude: field(1),
+                angle: field(2),
+            };
+            serde_json::to_vec(&raw).map_err(|e| e.to_string())
+        }
+        _ => Err(format!("{format:?} isn't a raw data format")),
+    }
+}
+
+///Stores the values as a `height x width x 3` array of little endian f32s, see
+///<https://numpy.org/doc/stable/reference/generated/numpy.lib.format.html>
+fn to_npy(values: &[f32], width: u32, height: u32) -> Vec<u8> {
+    let mut header =
+        format!("{{'descr': '<f4', 'fortran_order': False, 'shape': ({height}, {width}, 3), }}");
+    //The data has to start at a multiple of 64 bytes, counting the magic, version, length and
+    //the newline at the end of the header
+    let unpadded = 10 + header.len() + 1;
+    header.push_str(&" ".repeat(unpadded.next_multiple_of(64) - unpadded));
+    header.push('\n');
+
+    let mut npy = b"\x93NUMPY\x01\x00".to_vec();
+    npy.extend((header.len() as u16).to_le_bytes());
+    npy.extend(header.as_bytes());
+    npy.extend(values.iter().flat_map(|value| value.to_le_bytes()));
+    npy
 }
 
 pub fn async_iter(
@@ -66,3 +161,21 @@ pub fn async_iter(
 ) -> futures::stream::Iter<std::option::IntoIter<Result<Bytes, std::io::Error>>> {
     futures::stream::iter(Some(Ok::<Bytes, std::io::Error>(Bytes::from(arr))))
 }
+
+#[test]
+fn test_npy_header() {
+    let values = [1.0, 2.0, 3.0, 4.0, 5.0, 6.0];
+    let npy = to_npy(&values, 2, 1);
+    let header_len = u16::from_le_bytes([npy[8], npy[9]]) as usize;
+
+    assert!(npy.starts_with(b"\x93NUMPY\x01\x00"));
+    assert_eq!((10 + header_len) % 64, 0);
+    let header = std::str::from_utf8(&npy[10..10 + header_len]).unwrap();
+    assert!(header.contains("'shape': (1, 2, 3)"));
+    assert!(header.ends_with('\n'));
+    assert_eq!(npy.len(), 10 + header_len + values.len() * 4);
+    assert_eq!(
+        npy[10 + header_len..10 + header_len + 4],
+        1f32.to_le_bytes()
+    );
+}
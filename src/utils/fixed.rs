@@ -0,0 +1,252 @@
+use num_bigint::BigInt;
+use std::ops::{Add, Mul, Neg, Sub};
+
+use crate::grimoire;
+
+///Arbitrary precision fixed point number, equal to `mantissa / 2^precision`
+///
+///Only meant for computing reference orbits, so all the numbers in a calculation are expected
+///to have the same precision
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fixed {
+    mantissa: BigInt,
+    precision: u32,
+}
+
+///A decimal number split into its digits and power of 10, `digits * 10^exponent`
+struct Decimal {
+    digits: BigInt,
+    exponent: i64,
+}
+
+///Parses strings like -1.25, .5 or 3e-40
+fn parse_decimal(value: &str) -> Result<Decimal, String> {
+    let invalid = || format!("Invalid decimal number {value}");
+    let value = value.trim();
+
+    let (number, exponent) = match value.find(['e', 'E']) {
+        Some(index) => (
+            &value[..index],
+            value[index + 1..].parse::<i64>().map_err(|_| invalid())?,
+        ),
+        None => (value, 0),
+    };
+    let (negative, number) = match number.strip_prefix('-') {
+        Some(number) => (true, number),
+        None => (false, number.strip_prefix('+').unwrap_or(number)),
+    };
+    let (integer, fraction) = number.split_once('.').unwrap_or((number, ""));
+    if integer.is_empty() && fraction.is_empty()
+        || !integer
+            .bytes()
+            .chain(fraction.bytes())
+            .all(|b| b.is_ascii_digit())
+    {
+        return Err(invalid());
+    }
+
+    //Trailing zeros only move the exponent, stripping them here keeps big inputs cheap to parse
+    let digits = format!("{integer}{fraction}");
+    let significant = digits.trim_end_matches('0');
+    let exponent = exponent
+        .saturating_sub(fraction.len() as i64)
+        .saturating_add((digits.len() - significant.len()) as i64);
+    let significant = significant.trim_start_matches('0');
+    if significant.is_empty() {
+        return Ok(Decimal {
+            digits: BigInt::default(),
+            exponent: 0,
+        });
+    }
+    if significant.len() > grimoire::MAX_DECIMAL_DIGITS {
+        return Err(format!(
+            "Decimal numbers can have at most {} significant digits",
+            grimoire::MAX_DECIMAL_DIGITS
+        ));
+    }
+    //Bounds the power of 10 that's actually used, so that 0.000…1 can't get around the limit
+    if exponent.unsigned_abs() > grimoire::MAX_DECIMAL_EXPONENT as u64 {
+        return Err(format!(
+            "Exponent is outside of the supported range of ±{}",
+            grimoire::MAX_DECIMAL_EXPONENT
+        ));
+    }
+
+    let digits = significant.parse::<BigInt>().map_err(|_| invalid())?;
+    Ok(Decimal {
+        digits: if negative { -digits } else { digits },
+        exponent,
+    })
+}
+
+///Rewrites a decimal number in a canonical form, so that `1e3`, `1000` and `1000.00` are all the
+///same string
+pub fn normalize_decimal(value: &str) -> Result<String, String> {
+    let Decimal { digits, exponent } = parse_decimal(value)?;
+    if digits.sign() == num_bigint::Sign::NoSign {
+        return Ok("0".to_string());
+    }
+    Ok(format!("{digits}e{exponent}"))
+}
+
+///Returns the base 2 logarithm of a positive decimal number, without the limited range of an f64
+pub fn log2_decimal(value: &str) -> Result<f64, String> {
+    let decimal = parse_decimal(value)?;
+    if decimal.digits.sign() != num_bigint::Sign::Plus {
+        return Err(format!("{value} should be positive"));
+    }
+    //Only the top 60 bits matter for an f64
+    let shift = decimal.digits.bits().saturating_sub(60);
+    let top = i64::try_from(&(decimal.digits >> shift)).map_err(|e| e.to_string())?;
+
+    Ok((top as f64).log2() + shift as f64 + decimal.exponent as f64 * std::f64::consts::LOG2_10)
+}
+
+impl Fixed {
+    pub fn zero(precision: u32) -> Self {
+        Self {
+            mantissa: BigInt::default(),
+            precision,
+        }
+    }
+
+    pub const fn precision(&self) -> u32 {
+        self.precision
+    }
+
+    ///Parses a decimal string, rounding it to `precision` binary digits after the point
+    pub fn parse(value: &str, precision: u32) -> Result<Self, String> {
+        let decimal = parse_decimal(value)?;
+        let power = BigInt::from(10).pow(decimal.exponent.unsigned_abs() as u32);
+
+        let mantissa = if decimal.exponent >= 0 {
+            (decimal.digits * power) << precision
+        } else {
+            (decimal.digits << precision) / power
+        };
+        Ok(Self {
+            mantissa,
+            precision,
+        })
+    }
+
+    ///Converts into the closest f64
+    pub fn to_f64(&self) -> f64 {
+        //Keep 60 significant bits, which is more than an f64 can store anyway
+        let shift = self.mantissa.bits().saturating_sub(60);
+        //Can't fail, it has at most 60 bits after the shift
+        let top = i64::try_from(&(&self.mantissa >> shift)).unwrap_or_default();
+        top as f64 * 2f64.powi(shift as i32 - self.precision as i32)
+    }
+}
+
+impl Add for &Fixed {
+    type Output = Fixed;
+
+    fn add(self, rhs: Self) -> Fixed {
+        Fixed {
+            mantissa: &self.mantissa + &rhs.mantissa,
+            precision: self.precision,
+        }
+    }
+}
+
+impl Sub for &Fixed {
+    type Output = Fixed;
+
+    fn sub(self, rhs: Self) -> Fixed {
+        Fixed {
+            mantissa: &self.mantissa - &rhs.mantissa,
+            precision: self.precision,
+        }
+    }
+}
+
+impl Mul for &Fixed {
+    type Output = Fixed;
+
+    fn mul(self, rhs: Self) -> Fixed {
+        Fixed {
+            mantissa: (&self.mantissa * &rhs.mantissa) >> self.precision,
+            precision: self.precision,
+        }
+    }
+}
+
+impl Neg for Fixed {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        Self {
+            mantissa: -self.mantissa,
+            precision: self.precision,
+        }
+    }
+}
+
+#[test]
+fn test_fixed_parse() {
+    let value = Fixed::parse("-1.25", 64).unwrap();
+    assert!((value.to_f64() + 1.25).abs() < f64::EPSILON);
+
+    let value = Fixed::parse("3e-5", 128).unwrap();
+    assert!((value.to_f64() - 3e-5).abs() < 1e-20);
+
+    assert!(Fixed::parse("1.2.3", 64).is_err());
+    assert!(Fixed::parse("abc", 64).is_err());
+    assert!(Fixed::parse("", 64).is_err());
+}
+
+#[test]
+fn test_fixed_precision() {
+    //Adding a tiny offset to a number, which wouldn't survive in an f64
+    let precision = 400;
+    let a = Fixed::parse("0.5", precision).unwrap();
+    let b = Fixed::parse("1e-100", precision).unwrap();
+    let sum = &a + &b;
+
+    let difference = &(&sum - &a) * &Fixed::parse("1e100", precision).unwrap();
+    assert!((difference.to_f64() - 1.0).abs() < 1e-10);
+}
+
+#[test]
+fn test_log2_decimal() {
+    assert!((log2_decimal("1024").unwrap() - 10.0).abs() < 1e-10);
+    assert!((log2_decimal("1e300").unwrap() - 300.0 * std::f64::consts::LOG2_10).abs() < 1e-6);
+    assert!(log2_decimal("-5").is_err());
+    assert!(log2_decimal("0").is_err());
+}
+
+#[test]
+fn test_normalize_decimal() {
+    assert_eq!(normalize_decimal("1e3").unwrap(), "1e3");
+    assert_eq!(normalize_decimal("1000.00").unwrap(), "1e3");
+    assert_eq!(normalize_decimal("-0.0250").unwrap(), "-25e-3");
+    assert_eq!(normalize_decimal("-0.0").unwrap(), "0");
+
+    //Zeros are stripped without going through every digit as a big number
+    let zeros = format!("0.{}1", "0".repeat(2_000_000));
+    assert!(normalize_decimal(&zeros).is_err());
+    let zeros = format!("1.{}", "0".repeat(2_000_000));
+    assert_eq!(normalize_decimal(&zeros).unwrap(), "1e0");
+    let digits = "1".repeat(grimoire::MAX_DECIMAL_DIGITS);
+    assert!(normalize_decimal(&format!("000{digits}.000")).is_ok());
+    assert!(normalize_decimal(&format!("{digits}1")).is_err());
+}
+
+#[test]
+fn test_decimal_exponent_limit() {
+    let max = grimoire::MAX_DECIMAL_EXPONENT;
+    assert!(normalize_decimal(&format!("1e{max}")).is_ok());
+    assert!(normalize_decimal(&format!("1e-{max}")).is_ok());
+    assert!(normalize_decimal(&format!("1e{}", max + 1)).is_err());
+    //The digits after the point and the trailing zeros count towards the exponent
+    assert!(normalize_decimal(&format!("0.{}1", "0".repeat(max as usize))).is_err());
+    assert!(normalize_decimal(&format!("1{}", "0".repeat(max as usize + 1))).is_err());
+    assert!(normalize_decimal(&format!("1e{}", i64::MIN)).is_err());
+    assert!(Fixed::parse("25e-1001", 64).is_err());
+    assert_eq!(
+        normalize_decimal(&format!("10e-{}", max + 1)).unwrap(),
+        format!("1e-{max}")
+    );
+}
@@ -1,10 +1,18 @@
 #![allow(clippy::cast_possible_truncation, clippy::cast_lossless)]
 use crate::{
-    grimoire,
-    structs::rendering::{Fractals, GpuStructs, PipelineBufers},
+    formula, grimoire,
+    structs::rendering::{
+        Backend, Fractals, GpuStructs, PipelineBufers, PipelineStore, RawUniforms,
+        RenderParameters, RenderProgress, RenderRequest,
+    },
+    utils::{
+        cpu,
+        perturbation::DeepZoom,
+        vec::{contains_key, get},
+    },
 };
-use std::sync::Mutex;
-use wgpu::{include_wgsl, RequestDeviceError};
+use std::{borrow::Cow, sync::Arc};
+use wgpu::{include_wgsl, util::DeviceExt, RequestDeviceError};
 
 ///Flatten `wgpu::Color` into a `[f32; 4]`
 pub const fn color_raw(color: &wgpu::Color) -> [f32; 4] {
@@ -28,7 +36,8 @@ pub fn vec_from_hex(hex: &[&str]) -> Result<Vec<wgpu::Color>, String> {
 ///Converts a hex string ffffffff into `wgpu::Color`
 ///No hash check bc it's reserved in urls and I don't want to have to input %23
 pub fn from_hex(hex: &str) -> Result<wgpu::Color, String> {
-    if hex.len() != 6 {
+    //Slicing a string with multi byte characters could panic
+    if hex.len() != 6 || !hex.is_ascii() {
         return Err("Invalid hex color format".to_string());
     }
 
@@ -46,8 +55,13 @@ pub fn from_hex(hex: &str) -> Result<wgpu::Color, String> {
     Ok(color)
 }
 
-///Gets all necessary wgpu structures for the work of the API
-pub async fn generate_backend() -> Result<GpuStructs, RequestDeviceError> {
+///Gets all necessary wgpu structures for the work of the API, falls back to rendering on the
+///cpu if there's no usable adapter or if `RENDERER` is set to `cpu`
+pub async fn generate_backend() -> Result<Backend, RequestDeviceError> {
+    if std::env::var("RENDERER").is_ok_and(|r| r.eq_ignore_ascii_case("cpu")) {
+        log::info!(target: grimoire::LOGGING_TARGET, "Using the cpu renderer");
+        return Ok(Backend::Cpu);
+    }
     let instance = wgpu::Instance::default();
 
     let adapter = instance
@@ -56,24 +70,369 @@ pub async fn generate_backend() -> Result<GpuStructs, RequestDeviceError> {
             force_fallback_adapter: false,
             compatible_surface: None,
         })
-        .await
-        .expect("Unable to get an adapter");
+        .await;
+    let Some(adapter) = adapter else {
+        log::warn!(
+            target: grimoire::LOGGING_TARGET,
+            "Unable to get an adapter, falling back to the cpu renderer"
+        );
+        return Ok(Backend::Cpu);
+    };
 
     let (device, queue) = adapter
         .request_device(&wgpu::DeviceDescriptor::default(), None)
         .await?;
-    let staging_belt = Mutex::new(wgpu::util::StagingBelt::new(grimoire::STAGING_BELT_SIZE));
 
-    Ok(GpuStructs {
-        queue,
-        device,
-        staging_belt,
-    })
+    Ok(Backend::Gpu(GpuStructs::new(device, queue)))
+}
+
+///[`render_with_progress`] when nobody is watching
+#[cfg(test)]
+pub async fn render(
+    backend: &Backend,
+    pipelines: &PipelineStore,
+    request: &RenderRequest,
+) -> Result<Vec<u8>, String> {
+    render_with_progress(backend, pipelines, request, &Arc::default()).await
+}
+
+///Renders the image with whichever backend is available, returns tightly packed rgba rows.
+///`progress` is advanced by the pixels that are done while it works
+pub async fn render_with_progress(
+    backend: &Backend,
+    pipelines: &PipelineStore,
+    request: &RenderRequest,
+    progress: &Arc<RenderProgress>,
+) -> Result<Vec<u8>, String> {
+    match backend {
+        Backend::Gpu(gpu) => render_gpu(gpu, pipelines, request, progress).await,
+        //On another thread, so that the worker can keep answering while it renders
+        Backend::Cpu => {
+            let (request, progress) = (request.clone(), progress.clone());
+            actix_web::web::block(move || cpu::render_with_progress(&request, &progress))
+                .await
+                .map_err(|e| e.to_string())
+                .and_then(|result| result)
+        }
+    }
+}
+
+///[`RenderParameters::deep_zoom`] on another thread, the big number math of the reference orbit
+///would otherwise keep the worker from answering
+pub async fn deep_zoom(parameters: &RenderParameters) -> Result<Option<DeepZoom>, String> {
+    if parameters.deep.is_none() {
+        return Ok(None);
+    }
+    let parameters = parameters.clone();
+    actix_web::web::block(move || parameters.deep_zoom())
+        .await
+        .map_err(|e| e.to_string())
+        .and_then(|result| result)
+}
+
+///Computes the reference orbit if it's needed and builds the uniforms, without blocking the worker
+pub async fn render_request(parameters: &RenderParameters) -> Result<RenderRequest, String> {
+    Ok(parameters.to_render_request_with(deep_zoom(parameters).await?))
+}
+
+///Draws the rows from `start` to `end` of the tile, only the first band clears the texture
+fn draw_band(
+    encoder: &mut wgpu::CommandEncoder,
+    texture_view: &wgpu::TextureView,
+    pipeline: &PipelineBufers,
+    bind_group: &wgpu::BindGroup,
+    width: u32,
+    [start, end]: [u32; 2],
+) {
+    let load = if start == 0 {
+        wgpu::LoadOp::Clear(grimoire::CLEAR_COLOR)
+    } else {
+        wgpu::LoadOp::Load
+    };
+    let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+        label: Some("Render image pass"),
+        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+            view: texture_view,
+            resolve_target: None,
+            ops: wgpu::Operations { load, store: true },
+        })],
+        depth_stencil_attachment: None,
+    });
+
+    render_pass.set_bind_group(0, bind_group, &[]);
+    render_pass.set_pipeline(&pipeline.pipeline);
+    //Every pixel is computed the same way no matter how the tile is split into bands
+    render_pass.set_scissor_rect(0, start, width, end - start);
+    render_pass.draw(0..6, 0..1);
+}
+
+async fn render_gpu(
+    gpu: &GpuStructs,
+    pipelines: &PipelineStore,
+    request: &RenderRequest,
+    progress: &RenderProgress,
+) -> Result<Vec<u8>, String> {
+    let max_tile_size = gpu
+        .device
+        .limits()
+        .max_texture_dimension_2d
+        .min(grimoire::MAX_TILE_SIZE);
+    render_tiled(
+        gpu,
+        pipelines,
+        request,
+        [max_tile_size, grimoire::PROGRESS_BAND_HEIGHT],
+        progress,
+    )
+    .await
+}
+
+///Renders the image in tiles of at most `max_tile_size` pixels per side and stitches them
+///together, so that the image can be bigger than the largest texture the gpu supports. Every tile
+///is drawn in bands of `band_height` rows, waiting for each one so that progress can be reported
+async fn render_tiled(
+    gpu: &GpuStructs,
+    pipelines: &PipelineStore,
+    request: &RenderRequest,
+    [max_tile_size, band_height]: [u32; 2],
+    progress: &RenderProgress,
+) -> Result<Vec<u8>, String> {
+    let (width, height) = (request.width, request.height);
+    let pixel_size = request.bytes_per_pixel();
+    let row_size = width as usize * pixel_size;
+    let mut img = vec![0; row_size * height as usize];
+    if img.is_empty() {
+        return Ok(img);
+    }
+
+    //Split evenly, so that the tiles at the edges don't end up mostly wasted
+    let tile_width = width.div_ceil(width.div_ceil(max_tile_size));
+    let tile_height = height.div_ceil(height.div_ceil(max_tile_size));
+    let tiles_x = width.div_ceil(tile_width);
+    let tiles_y = height.div_ceil(tile_height);
+
+    let colors = to_raw_colors(&request.colors);
+
+    //I'm not checking these bc if they were poisoned, it's basically fucked
+    //According to chat GPT you can't salvage a poisoned mutex
+    let format = request.texture_format();
+    let shader = fractal_shader(&request.fractal)?;
+    let pipeline = if let Fractals::Custom(_) = request.fractal {
+        let key = (shader.into_owned(), format);
+        let mut pipelines = pipelines.custom.lock().unwrap();
+        if let Some(pipeline) = pipelines.get(&key) {
+            pipeline
+        } else {
+            let pipeline = Arc::new(generate_pipeline(
+                &request.fractal,
+                &key.0,
+                &gpu.device,
+                format,
+            ));
+            pipelines.insert(key, pipeline.clone(), 1);
+            pipeline
+        }
+    } else {
+        let key = (request.fractal.clone(), format);
+        let mut pipelines = pipelines.builtin.lock().unwrap();
+        if !contains_key(&pipelines, &key) {
+            let pipeline = generate_pipeline(&request.fractal, &shader, &gpu.device, format);
+            pipelines.push((key.clone(), Arc::new(pipeline)));
+        }
+        get(&pipelines, &key)
+            .ok_or("Could not get pipeline")?
+            .clone()
+    };
+
+    //Every render gets its own buffers, the pipelines are the only thing shared between them
+    if colors.is_empty() {
+        return Err("Could not get color buffer size".to_string());
+    }
+    let info_buffer = gpu.device.create_buffer(&wgpu::BufferDescriptor {
+        label: None,
+        size: std::mem::size_of::<RawUniforms>() as u64,
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::UNIFORM,
+        mapped_at_creation: false,
+    });
+    let storage_buffer = gpu
+        .device
+        .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: None,
+            contents: bytemuck::cast_slice(&colors),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+    //Bindings can't be empty, so there's a dummy point when deep zoom isn't used
+    let orbit = request
+        .deep_zoom
+        .as_ref()
+        .map_or(&[[0.0; 2]][..], |deep_zoom| &deep_zoom.orbit);
+    let orbit_buffer = gpu
+        .device
+        .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: None,
+            contents: bytemuck::cast_slice(orbit),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+    let bind_group = gpu.device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: None,
+        layout: &pipeline.bind_group_layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: info_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: storage_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: orbit_buffer.as_entire_binding(),
+            },
+        ],
+    });
+
+    //The texture and the buffer are reused for every tile
+    let texture = gpu.device.create_texture(&wgpu::TextureDescriptor {
+        label: None,
+        size: wgpu::Extent3d {
+            width: tile_width,
+            height: tile_height,
+            depth_or_array_layers: 1,
+        },
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+        format,
+        view_formats: &[format],
+        dimension: wgpu::TextureDimension::D2,
+        sample_count: 1,
+        mip_level_count: 1,
+    });
+    let texture_view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+    let size = texture.size();
+    let format_block_size = texture.format().block_size(None).unwrap();
+    let mut bytes_per_row = size.width * format_block_size;
+    if !bytes_per_row.is_multiple_of(256) {
+        bytes_per_row = bytes_per_row + (256 - (bytes_per_row % 256));
+    }
+    let buffer = gpu.device.create_buffer(&wgpu::BufferDescriptor {
+        label: None,
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        size: { u64::from(bytes_per_row * size.height) },
+        mapped_at_creation: false,
+    });
+
+    for tile in 0..tiles_x * tiles_y {
+        let offset_x = (tile % tiles_x) * tile_width;
+        let offset_y = (tile / tiles_x) * tile_height;
+
+        let mut uniforms = request.uniforms;
+        uniforms.viewport = [
+            tile_width as f32 / width as f32,
+            tile_height as f32 / height as f32,
+            (tile_width + 2 * offset_x) as f32 / width as f32 - 1.0,
+            (tile_height + 2 * offset_y) as f32 / height as f32 - 1.0,
+        ];
+        //Goes in before the next submit, the previous tile is already done by then
+        gpu.queue
+            .write_buffer(&info_buffer, 0, bytemuck::cast_slice(&uniforms.raw()));
+
+        //Parts of the edge tiles that are outside of the image aren't drawn
+        let visible_width = tile_width.min(width - offset_x);
+        let visible_height = tile_height.min(height - offset_y);
+        let mut start = 0;
+        let last_band = loop {
+            if progress.is_canceled() {
+                return Err("The render was canceled".to_string());
+            }
+            let end = (start + band_height).min(visible_height);
+            let mut encoder = gpu
+                .device
+                .create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+            draw_band(
+                &mut encoder,
+                &texture_view,
+                &pipeline,
+                &bind_group,
+                visible_width,
+                [start, end],
+            );
+            if end == visible_height {
+                //Copy contents of render texture to the buffer
+                encoder.copy_texture_to_buffer(
+                    texture.as_image_copy(),
+                    wgpu::ImageCopyBuffer {
+                        buffer: &buffer,
+                        layout: wgpu::ImageDataLayout {
+                            offset: 0,
+                            bytes_per_row: Some(bytes_per_row),
+                            rows_per_image: None,
+                        },
+                    },
+                    texture.size(),
+                );
+                gpu.queue.submit(Some(encoder.finish()));
+                break end - start;
+            }
+            gpu.queue.submit(Some(encoder.finish()));
+            gpu.wait_submitted().await?;
+            progress.advance(u64::from(visible_width) * u64::from(end - start));
+            start = end;
+        };
+
+        //Get the data from the gpu, this also waits for the last band
+        let slice = buffer.slice(..);
+        gpu.map_read(slice).await?;
+        progress.advance(u64::from(visible_width) * u64::from(last_band));
+
+        //Copy the visible part of the tile into place, this also removes the row padding
+        {
+            let mapped = slice.get_mapped_range();
+            let visible_width = visible_width as usize * pixel_size;
+            for (row, data) in mapped
+                .chunks_exact(bytes_per_row as usize)
+                .take(visible_height as usize)
+                .enumerate()
+            {
+                let start = (offset_y as usize + row) * row_size + offset_x as usize * pixel_size;
+                img[start..start + visible_width].copy_from_slice(&data[..visible_width]);
+            }
+        }
+        buffer.unmap();
+    }
+    texture.destroy();
+    buffer.destroy();
+    info_buffer.destroy();
+    storage_buffer.destroy();
+    orbit_buffer.destroy();
+
+    Ok(img)
+}
+
+///The WGSL defining `fractal_func` for a fractal, custom formulas get compiled
+pub fn fractal_shader(fractal: &Fractals) -> Result<Cow<'static, str>, String> {
+    Ok(Cow::Borrowed(match fractal {
+        Fractals::Mandelbrot => include_str!("../shaders/madelbrot.wgsl"),
+        Fractals::BurningShip => include_str!("../shaders/burning_ship.wgsl"),
+        Fractals::Tricorn => include_str!("../shaders/tricorn.wgsl"),
+        Fractals::Feather => include_str!("../shaders/feather.wgsl"),
+        Fractals::Eye => include_str!("../shaders/eye.wgsl"),
+        Fractals::Custom(formula) => {
+            return formula::compile(formula)
+                .map(Cow::Owned)
+                .map_err(|e| format!("Invalid formula: {e}"))
+        }
+    }))
 }
 
 #[allow(clippy::too_many_lines)]
-///Generates a pipeline for rendering a specific type of fractal
-pub fn generate_pipeline(fractal: &Fractals, device: &wgpu::Device) -> PipelineBufers {
+///Generates a pipeline for rendering a specific type of fractal, `fractal_fn` comes from
+///[`fractal_shader`]
+pub fn generate_pipeline(
+    fractal: &Fractals,
+    fractal_fn: &str,
+    device: &wgpu::Device,
+    format: wgpu::TextureFormat,
+) -> PipelineBufers {
     log::info!(
         target: grimoire::LOGGING_TARGET,
         "Generating new pipeline for {fractal}"
@@ -83,33 +442,17 @@ pub fn generate_pipeline(fractal: &Fractals, device: &wgpu::Device) -> PipelineB
     let vertex = device.create_shader_module(include_wgsl!("../shaders/vert.wgsl"));
 
     let mut base = include_str!("../shaders/base_fragment.wgsl").to_owned();
-    let fractal_fn = match fractal {
-        Fractals::Custom(_) | Fractals::Mandelbrot => include_str!("../shaders/madelbrot.wgsl"),
-        Fractals::BurningShip => include_str!("../shaders/burning_ship.wgsl"),
-        Fractals::Tricorn => include_str!("../shaders/tricorn.wgsl"),
-        Fractals::Feather => include_str!("../shaders/feather.wgsl"),
-        Fractals::Eye => include_str!("../shaders/eye.wgsl"),
-    };
     base.push_str(fractal_fn);
+    base.push_str(if fractal.supports_deep_zoom() {
+        include_str!("../shaders/perturbation.wgsl")
+    } else {
+        include_str!("../shaders/no_perturbation.wgsl")
+    });
     let fragment = device.create_shader_module(wgpu::ShaderModuleDescriptor {
         label: None,
         source: wgpu::ShaderSource::Wgsl(base.into()),
     });
 
-    let info_buffer = device.create_buffer(&wgpu::BufferDescriptor {
-        label: None,
-        size: 10 * 4,
-        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::UNIFORM,
-        mapped_at_creation: false,
-    });
-
-    let storage_buffer = device.create_buffer(&wgpu::BufferDescriptor {
-        label: None,
-        size: 4 * 4 * grimoire::MAX_COLORS,
-        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::STORAGE,
-        mapped_at_creation: false,
-    });
-
     let bg_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
         label: Some(&format!("{fractal:#?} bind group layout")),
         entries: &[
@@ -133,20 +476,15 @@ pub fn generate_pipeline(fractal: &Fractals, device: &wgpu::Device) -> PipelineB
                 },
                 count: None,
             },
-        ],
-    });
-
-    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-        label: None,
-        layout: &bg_layout,
-        entries: &[
-            wgpu::BindGroupEntry {
-                binding: 0,
-                resource: info_buffer.as_entire_binding(),
-            },
-            wgpu::BindGroupEntry {
-                binding: 1,
-                resource: storage_buffer.as_entire_binding(),
+            wgpu::BindGroupLayoutEntry {
+                binding: 2,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
             },
         ],
     });
@@ -169,11 +507,9 @@ pub fn generate_pipeline(fractal: &Fractals, device: &wgpu::Device) -> PipelineB
                 module: &fragment,
                 entry_point: "main",
                 targets: &[Some(wgpu::ColorTargetState {
-                    format: grimoire::FORMAT,
-                    blend: Some(wgpu::BlendState {
-                        color: wgpu::BlendComponent::REPLACE,
-                        alpha: wgpu::BlendComponent::REPLACE,
-                    }),
+                    format,
+                    //Float textures can't be blended, and replacing is the same as not blending
+                    blend: None,
                     write_mask: wgpu::ColorWrites::ALL,
                 })],
             }),
@@ -182,13 +518,12 @@ pub fn generate_pipeline(fractal: &Fractals, device: &wgpu::Device) -> PipelineB
             depth_stencil: None,
             multiview: None,
         }),
-        info_buffer,
-        storage_buffer,
-        bind_group,
+        bind_group_layout: bg_layout,
     }
 }
 
 #[test]
+#[allow(clippy::bool_assert_comparison)]
 fn test_hex_to_color() {
     let hex = "ffffff";
     let color = from_hex(hex);
@@ -196,8 +531,170 @@ fn test_hex_to_color() {
 }
 
 #[test]
+#[allow(clippy::bool_assert_comparison)]
 fn test_hex_vec_to_color() {
     let hex = vec!["ffffff", "ffffff"];
     let color = vec_from_hex(&hex);
     assert_ne!(color.is_err(), true);
 }
+
+#[test]
+fn test_tiled_render() {
+    use crate::structs::requests::{RequestBody, SimplifiedFractals};
+
+    let Ok(Backend::Gpu(gpu)) = futures::executor::block_on(generate_backend()) else {
+        return;
+    };
+    let pipelines = PipelineStore::default();
+    let query = actix_web::web::Query::<RequestBody>::from_query(
+        "width=300&height=170&msaa=2&max_iterations=100",
+    )
+    .unwrap();
+    let request = query
+        .to_parameters(SimplifiedFractals::Mandelbrot)
+        .unwrap()
+        .to_render_request()
+        .unwrap();
+
+    let render = |sizes, progress| {
+        futures::executor::block_on(render_tiled(&gpu, &pipelines, &request, sizes, progress))
+            .unwrap()
+    };
+    let progress = RenderProgress::new(300 * 170);
+    let whole = render([1024, 1024], &progress);
+    assert_eq!(progress.fraction(), 1.0);
+    //Bands only limit what's drawn, so they don't change anything
+    let progress = RenderProgress::new(300 * 170);
+    assert!(render([1024, 7], &progress) == whole);
+    assert_eq!(progress.fraction(), 1.0);
+    //Uneven tiles, to make sure that the edges are handled
+    let tiled = render([64, 1024], &RenderProgress::default());
+    assert_eq!(whole.len(), tiled.len());
+    //Stops before drawing anything
+    let progress = RenderProgress::new(300 * 170);
+    progress.cancel();
+    let canceled = futures::executor::block_on(render_tiled(
+        &gpu,
+        &pipelines,
+        &request,
+        [1024, 1024],
+        &progress,
+    ));
+    assert!(canceled.is_err());
+    assert_eq!(progress.fraction(), 0.0);
+
+    //The tile uv goes through a few extra float operations, so allow for differences on the edges
+    let different = whole
+        .chunks_exact(4)
+        .zip(tiled.chunks_exact(4))
+        .filter(|(a, b)| a != b)
+        .count();
+    assert!(
+        different * 100 < whole.len() / 4,
+        "{different} pixels differ"
+    );
+}
+
+#[test]
+fn test_concurrent_renders() {
+    use crate::structs::requests::{RequestBody, SimplifiedFractals};
+
+    let Ok(Backend::Gpu(gpu)) = futures::executor::block_on(generate_backend()) else {
+        return;
+    };
+    let pipelines = PipelineStore::default();
+    //Same fractal so that they share the pipeline, but with different uniforms, colors and orbits
+    let requests = [
+        "zoom=2&colors=ff0000,00ff00",
+        "julia=true&julia_x=0.3&julia_y=0.1&colors=0000ff,ffffff",
+        "deep=true&deep_x=-1.75&deep_zoom=1e10&colors=ffffff,000000",
+        "max_iterations=50&num_colors=10&smooth=true",
+    ]
+    .map(|query| {
+        actix_web::web::Query::<RequestBody>::from_query(&format!("width=96&height=80&{query}"))
+            .unwrap()
+            .to_parameters(SimplifiedFractals::Mandelbrot)
+            .unwrap()
+            .to_render_request()
+            .unwrap()
+    });
+    //Small tiles, so that the uniforms are rewritten in the middle of every render
+    let expected = requests.each_ref().map(|request| {
+        futures::executor::block_on(render_tiled(
+            &gpu,
+            &pipelines,
+            request,
+            [32, 32],
+            &RenderProgress::default(),
+        ))
+        .unwrap()
+    });
+
+    let (gpu, pipelines) = (&gpu, &pipelines);
+    std::thread::scope(|scope| {
+        for _ in 0..4 {
+            for (i, (request, expected)) in requests.iter().zip(&expected).enumerate() {
+                scope.spawn(move || {
+                    for _ in 0..5 {
+                        let img = futures::executor::block_on(render_tiled(
+                            gpu,
+                            pipelines,
+                            request,
+                            [32, 32],
+                            &RenderProgress::default(),
+                        ))
+                        .unwrap();
+                        assert!(
+                            img == *expected,
+                            "Request {i} got mixed up with another one"
+                        );
+                    }
+                });
+            }
+        }
+    });
+}
+
+#[test]
+fn test_custom_pipelines() {
+    use crate::structs::requests::{RequestBody, SimplifiedFractals};
+
+    let Ok(Backend::Gpu(gpu)) = futures::executor::block_on(generate_backend()) else {
+        return;
+    };
+    let pipelines = PipelineStore::default();
+    let render = |formula: &str| {
+        let request = actix_web::web::Query::<RequestBody>::from_query(&format!(
+            "width=8&height=8&max_iterations=10&formula={}",
+            formula
+                .replace(' ', "%20")
+                .replace('+', "%2B")
+                .replace('^', "%5E")
+        ))
+        .unwrap()
+        .to_parameters(SimplifiedFractals::Custom)
+        .unwrap()
+        .to_render_request()
+        .unwrap();
+        futures::executor::block_on(render_tiled(
+            &gpu,
+            &pipelines,
+            &request,
+            [1024, 1024],
+            &RenderProgress::default(),
+        ))
+        .unwrap()
+    };
+
+    //Same shader, so they share a pipeline
+    assert!(render("z^2+c") == render("z ^ 2 +  c"));
+    assert_eq!(pipelines.custom.lock().unwrap().stats().entries, 1);
+
+    for i in 0..=grimoire::MAX_CUSTOM_PIPELINES {
+        render(&format!("z^2+c*{i}"));
+    }
+    let stats = pipelines.custom.lock().unwrap().stats();
+    assert_eq!(stats.entries as u64, grimoire::MAX_CUSTOM_PIPELINES);
+    assert!(stats.evictions > 0);
+    assert!(pipelines.builtin.lock().unwrap().is_empty());
+}
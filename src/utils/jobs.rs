@@ -0,0 +1,331 @@
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use tokio::{
+    sync::{Semaphore, SemaphorePermit},
+    task::AbortHandle,
+};
+
+use crate::{grimoire, structs::rendering::RenderProgress};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde_derive::Serialize, serde_derive::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    ///Waiting for a free worker
+    Queued,
+    Running,
+    Done,
+    Failed,
+    Canceled,
+}
+
+impl JobStatus {
+    pub const fn is_finished(self) -> bool {
+        matches!(self, Self::Done | Self::Failed | Self::Canceled)
+    }
+}
+
+///What the status endpoint reports about a job
+#[derive(Debug, Clone, PartialEq, serde_derive::Serialize, serde_derive::Deserialize)]
+pub struct JobInfo {
+    pub id: u64,
+    pub status: JobStatus,
+    ///Fraction of the work that's done, from 0 to 1
+    pub progress: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+struct Job<T, E> {
+    status: JobStatus,
+    ///Advanced by the renderer
+    progress: Arc<RenderProgress>,
+    result: Option<Result<T, E>>,
+    ///The retention starts once the job is finished
+    finished: Option<Instant>,
+    ///Set once the job's task is spawned
+    abort: Option<AbortHandle>,
+    ///Bytes of the result, counted towards the size limit
+    size: u64,
+}
+
+struct State<T, E> {
+    next_id: u64,
+    jobs: HashMap<u64, Job<T, E>>,
+    ///Sum of the sizes of the results that are kept
+    result_bytes: u64,
+}
+
+impl<T, E> State<T, E> {
+    fn remove(&mut self, id: u64) {
+        if let Some(job) = self.jobs.remove(&id) {
+            self.result_bytes -= job.size;
+        }
+    }
+}
+
+///Keeps track of renders that run in the background, only `workers` of them run at once and
+///the rest wait in line
+pub struct JobQueue<T, E> {
+    state: Mutex<State<T, E>>,
+    workers: Semaphore,
+    ///How long finished jobs are kept
+    retention: Duration,
+    ///Size of the results that are kept, the oldest finished jobs are dropped past it
+    max_result_bytes: u64,
+}
+
+impl<T: Clone, E: Clone + Display> JobQueue<T, E> {
+    pub fn new(workers: usize, retention: Duration, max_result_bytes: u64) -> Self {
+        Self {
+            state: Mutex::new(State {
+                next_id: 1,
+                jobs: HashMap::new(),
+                result_bytes: 0,
+            }),
+            workers: Semaphore::new(workers.max(1)),
+            retention,
+            max_result_bytes,
+        }
+    }
+
+    ///Adds a queued job that goes through `pixels` pixels, and returns its id and the progress
+    ///that the renderer should advance. Errors if too many jobs are already waiting
+    pub fn submit(&self, pixels: u64) -> Result<(u64, Arc<RenderProgress>), String> {
+        let mut state = self.lock();
+        let pending = state
+            .jobs
+            .values()
+            .filter(|job| !job.status.is_finished())
+            .count();
+        if pending >= grimoire::MAX_PENDING_JOBS {
+            return Err(format!(
+                "There can be at most {} jobs waiting, try again later",
+                grimoire::MAX_PENDING_JOBS
+            ));
+        }
+        let id = state.next_id;
+        state.next_id += 1;
+        let progress = Arc::new(RenderProgress::new(pixels));
+        state.jobs.insert(
+            id,
+            Job {
+                status: JobStatus::Queued,
+                progress: progress.clone(),
+                result: None,
+                finished: None,
+                abort: None,
+                size: 0,
+            },
+        );
+        Ok((id, progress))
+    }
+
+    ///Lets [`JobQueue::cancel`] stop the task that runs the job, the task is stopped right away
+    ///if the job was canceled before it got here
+    pub fn set_task(&self, id: u64, abort: AbortHandle) {
+        match self.lock().jobs.get_mut(&id) {
+            Some(job) if job.status != JobStatus::Canceled => job.abort = Some(abort),
+            _ => abort.abort(),
+        }
+    }
+
+    ///Waits for a free worker and marks the job as running, the worker is freed once the permit
+    ///is dropped
+    pub async fn start(&self, id: u64) -> SemaphorePermit<'_> {
+        let permit = self
+            .workers
+            .acquire()
+            .await
+            .expect("The semaphore is never closed");
+        if let Some(job) = self.lock().jobs.get_mut(&id) {
+            job.status = JobStatus::Running;
+        }
+        permit
+    }
+
+    ///Stores the result, `size` counts towards the limit of kept results. Older finished jobs are
+    ///dropped to make space, but the newest one is always kept
+    pub fn finish(&self, id: u64, result: Result<T, E>, size: u64) {
+        let mut state = self.lock();
+        let Some(job) = state.jobs.get_mut(&id) else {
+            return;
+        };
+        //Canceled jobs stay canceled, even if they managed to finish
+        if job.status.is_finished() {
+            return;
+        }
+        job.status = if result.is_ok() {
+            JobStatus::Done
+        } else {
+            JobStatus::Failed
+        };
+        job.result = Some(result);
+        job.finished = Some(Instant::now());
+        job.abort = None;
+        job.size = size;
+        state.result_bytes += size;
+
+        while state.result_bytes > self.max_result_bytes {
+            let oldest = state
+                .jobs
+                .iter()
+                .filter(|(other, job)| **other != id && job.size > 0)
+                .min_by_key(|(_, job)| job.finished)
+                .map(|(other, _)| *other);
+            let Some(oldest) = oldest else {
+                break;
+            };
+            state.remove(oldest);
+        }
+    }
+
+    pub fn info(&self, id: u64) -> Option<JobInfo> {
+        let state = self.lock();
+        let job = state.jobs.get(&id)?;
+        Some(JobInfo {
+            id,
+            status: job.status,
+            //Encoding isn't counted, so it can be all rendered without being done yet
+            progress: if job.status == JobStatus::Done {
+                1.0
+            } else {
+                job.progress.fraction()
+            },
+            error: match &job.result {
+                Some(Err(e)) => Some(e.to_string()),
+                _ => None,
+            },
+        })
+    }
+
+    ///The result of a finished job, `None` if it's still running or was canceled
+    pub fn result(&self, id: u64) -> Option<Result<T, E>> {
+        self.lock().jobs.get(&id)?.result.clone()
+    }
+
+    ///Stops a job that's still queued or running, finished jobs are removed instead. Returns
+    ///false if there's no such job
+    pub fn cancel(&self, id: u64) -> bool {
+        let mut state = self.lock();
+        let Some(job) = state.jobs.get_mut(&id) else {
+            return false;
+        };
+        if job.status.is_finished() {
+            state.remove(id);
+            return true;
+        }
+        //Aborting the task doesn't stop a render that's running on another thread
+        job.progress.cancel();
+        if let Some(abort) = job.abort.take() {
+            abort.abort();
+        }
+        job.status = JobStatus::Canceled;
+        job.finished = Some(Instant::now());
+        true
+    }
+
+    ///Locks the state, dropping the jobs that finished longer than the retention ago
+    fn lock(&self) -> std::sync::MutexGuard<'_, State<T, E>> {
+        let mut state = self.state.lock().unwrap();
+        let State {
+            jobs, result_bytes, ..
+        } = &mut *state;
+        jobs.retain(|_, job| {
+            let keep = job
+                .finished
+                .is_none_or(|finished| finished.elapsed() < self.retention);
+            if !keep {
+                *result_bytes -= job.size;
+            }
+            keep
+        });
+        state
+    }
+}
+
+#[test]
+fn test_job_queue() {
+    let queue = JobQueue::<u32, String>::new(1, Duration::from_secs(60), u64::MAX);
+    let (first, progress) = queue.submit(10).unwrap();
+    let (second, second_progress) = queue.submit(10).unwrap();
+    assert_ne!(first, second);
+    assert_eq!(queue.info(first).unwrap().status, JobStatus::Queued);
+
+    let permit = futures::executor::block_on(queue.start(first));
+    assert_eq!(queue.info(first).unwrap().status, JobStatus::Running);
+    //Only one worker, so the second job has to wait
+    assert!(queue.workers.try_acquire().is_err());
+    progress.advance(5);
+    assert_eq!(queue.info(first).unwrap().progress, 0.5);
+    queue.finish(first, Ok(42), 0);
+    drop(permit);
+    let info = queue.info(first).unwrap();
+    assert_eq!((info.status, info.progress), (JobStatus::Done, 1.0));
+    assert_eq!(queue.result(first), Some(Ok(42)));
+
+    assert!(queue.cancel(second));
+    assert!(second_progress.is_canceled());
+    queue.finish(second, Err("Too late".to_string()), 0);
+    assert_eq!(queue.info(second).unwrap().status, JobStatus::Canceled);
+    assert_eq!(queue.result(second), None);
+
+    //Canceling a finished job removes it
+    assert!(queue.cancel(first));
+    assert!(queue.info(first).is_none());
+    assert!(!queue.cancel(first));
+
+    let (failed, _) = queue.submit(10).unwrap();
+    queue.finish(failed, Err("Broken".to_string()), 0);
+    let info = queue.info(failed).unwrap();
+    assert_eq!(info.status, JobStatus::Failed);
+    assert_eq!(info.error.as_deref(), Some("Broken"));
+
+    //Finished jobs are dropped after the retention
+    let queue = JobQueue::<u32, String>::new(1, Duration::ZERO, u64::MAX);
+    let (id, _) = queue.submit(10).unwrap();
+    assert!(queue.info(id).is_some());
+    queue.finish(id, Ok(1), 0);
+    assert!(queue.info(id).is_none());
+}
+
+#[test]
+fn test_result_size_limit() {
+    let queue = JobQueue::<u32, String>::new(1, Duration::from_secs(60), 10);
+    let ids = [4, 4, 4].map(|size| {
+        let (id, _) = queue.submit(10).unwrap();
+        queue.finish(id, Ok(1), size);
+        id
+    });
+    //The oldest one is dropped to make space for the third
+    assert!(queue.info(ids[0]).is_none());
+    assert!(queue.info(ids[1]).is_some());
+    assert!(queue.info(ids[2]).is_some());
+
+    //Bigger than the whole limit, but the newest one is kept anyway
+    let (big, _) = queue.submit(10).unwrap();
+    queue.finish(big, Ok(2), 20);
+    assert_eq!(queue.result(big), Some(Ok(2)));
+    assert!(queue.info(ids[2]).is_none());
+
+    //Deleting it frees the space again
+    assert!(queue.cancel(big));
+    let (id, _) = queue.submit(10).unwrap();
+    queue.finish(id, Ok(3), 10);
+    assert_eq!(queue.result(id), Some(Ok(3)));
+}
+
+#[actix_web::test]
+async fn test_cancel_before_task() {
+    let queue = JobQueue::<u32, String>::new(1, Duration::from_secs(60), u64::MAX);
+    let (id, _) = queue.submit(10).unwrap();
+    //Canceled between submitting and spawning the task
+    assert!(queue.cancel(id));
+    let task = actix_web::rt::spawn(std::future::pending::<()>());
+    queue.set_task(id, task.abort_handle());
+    assert!(task.await.unwrap_err().is_cancelled());
+}
@@ -1,7 +1,31 @@
+///Interpolating and encoding zoom animations
+pub mod animation;
+///Zip and tar archives
+pub mod archive;
+///Render cache with lru eviction
+pub mod cache;
+///Deduplicating identical renders that run at the same time
+pub mod coalesce;
+///Complex numbers for the cpu renderer
+pub mod complex;
+///Rendering fractals without a gpu
+pub mod cpu;
+///Render cache that persists between restarts
+pub mod disk_cache;
 ///Contains various export functions
 pub mod export;
+///Arbitrary precision numbers for deep zoom
+pub mod fixed;
 ///Contains everything related to rendering
 pub mod graphics;
+///Background render jobs
+pub mod jobs;
+///Reference orbits for deep zoom
+pub mod perturbation;
+///Deep zoom and iiif image pyramids
+pub mod pyramid;
 
 //Some helper funcs for vecs
 pub mod vec;
+///Websocket handshake and frames
+pub mod websocket;
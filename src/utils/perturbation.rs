@@ -0,0 +1,115 @@
+#![allow(clippy::cast_possible_truncation)]
+use crate::{grimoire, structs::rendering::Fractals};
+
+use super::fixed::{log2_decimal, Fixed};
+
+///Everything the shader needs to render a deep zoom
+#[derive(Debug, Clone)]
+pub struct DeepZoom {
+    ///Reference orbit of the center of the image, starting at 0
+    pub orbit: Vec<[f32; 2]>,
+    ///Zoom is split into `zoom_mantissa * 2^-scale_exponent`, so that it fits into an f32
+    pub zoom_mantissa: f32,
+    ///Power of 2 that the pixel offsets are scaled by in the shader, always <= 0
+    pub scale_exponent: i32,
+}
+
+///Same as max_dot in the shaders that support deep zoom
+const BAILOUT: f64 = 4.0;
+
+///Computes the reference orbit for a deep zoom, the position and zoom are decimal strings so
+///that they can have as much precision as needed
+pub fn prepare(
+    fractal: &Fractals,
+    position_x: &str,
+    position_y: &str,
+    zoom: &str,
+    max_iterations: u32,
+) -> Result<DeepZoom, String> {
+    let zoom_log2 = log2_decimal(zoom).map_err(|e| format!("Invalid zoom: {e}"))?;
+    //There's no need for scaling if the zoom is small enough
+    let exponent = (zoom_log2.floor() as i64).max(0);
+    let zoom_mantissa = (zoom_log2 - exponent as f64).exp2() as f32;
+
+    //Enough bits to tell neighbouring pixels apart, with some to spare for the iteration
+    let precision = u32::try_from(exponent).map_err(|_| "Zoom is too large".to_string())?
+        + grimoire::DEEP_ZOOM_EXTRA_PRECISION;
+
+    let c_x =
+        Fixed::parse(position_x, precision).map_err(|e| format!("Invalid position_x: {e}"))?;
+    //Flipped like in the normal rendering path
+    let c_y =
+        -Fixed::parse(position_y, precision).map_err(|e| format!("Invalid position_y: {e}"))?;
+
+    Ok(DeepZoom {
+        orbit: reference_orbit(fractal, &c_x, &c_y, max_iterations),
+        zoom_mantissa,
+        scale_exponent: -(exponent as i32),
+    })
+}
+
+///Iterates the center of the image in high precision, until it escapes or the iteration limit
+///is reached
+fn reference_orbit(
+    fractal: &Fractals,
+    c_x: &Fixed,
+    c_y: &Fixed,
+    max_iterations: u32,
+) -> Vec<[f32; 2]> {
+    let mut x = Fixed::zero(c_x.precision());
+    let mut y = Fixed::zero(c_x.precision());
+    let mut orbit = vec![[0.0, 0.0]];
+
+    for _ in 0..max_iterations {
+        let x2 = &x * &x;
+        let y2 = &y * &y;
+        let xy = &x * &y;
+        let double_xy = &xy + &xy;
+        let next_y = match fractal {
+            Fractals::Tricorn => c_y - &double_xy,
+            _ => &double_xy + c_y,
+        };
+        x = &(&x2 - &y2) + c_x;
+        y = next_y;
+
+        let point = [x.to_f64(), y.to_f64()];
+        orbit.push([point[0] as f32, point[1] as f32]);
+        if point[0] * point[0] + point[1] * point[1] > BAILOUT {
+            break;
+        }
+    }
+    orbit
+}
+
+#[test]
+fn test_reference_orbit() {
+    let precision = 128;
+    //Inside of the main cardioid, so it should never escape
+    let orbit = reference_orbit(
+        &Fractals::Mandelbrot,
+        &Fixed::parse("-0.1", precision).unwrap(),
+        &Fixed::zero(precision),
+        100,
+    );
+    assert_eq!(orbit.len(), 101);
+
+    //Far outside, escapes right away
+    let orbit = reference_orbit(
+        &Fractals::Mandelbrot,
+        &Fixed::parse("2", precision).unwrap(),
+        &Fixed::parse("2", precision).unwrap(),
+        100,
+    );
+    assert_eq!(orbit, vec![[0.0, 0.0], [2.0, 2.0]]);
+}
+
+#[test]
+fn test_prepare_zoom() {
+    let deep = prepare(&Fractals::Mandelbrot, "-0.75", "0.1", "3e100", 10).unwrap();
+
+    let zoom_log2 = f64::from(deep.zoom_mantissa).log2() - f64::from(deep.scale_exponent);
+    assert!((zoom_log2 - (3e100f64).log2()).abs() < 1e-5);
+    assert!(deep.scale_exponent < 0);
+
+    assert!(prepare(&Fractals::Mandelbrot, "-0.75", "0.1", "-1", 10).is_err());
+}
@@ -0,0 +1,247 @@
+use crate::{grimoire, structs::requests::OutputFormat};
+
+///A tile of a deep zoom pyramid
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PyramidTile {
+    pub level: u32,
+    pub column: u32,
+    pub row: u32,
+    ///Part of the full image as x, y, width, height, can go past the edge of the image by less
+    ///than a pixel of the level
+    pub region: [f64; 4],
+    pub size: [u32; 2],
+}
+
+///Size of every level of a deep zoom pyramid, level 0 is a single pixel and each level is twice
+///as big as the previous one, up to the full image
+pub fn dzi_levels(width: u32, height: u32) -> Vec<[u32; 2]> {
+    let max_level = width.max(height).next_power_of_two().trailing_zeros();
+    (0..=max_level)
+        .map(|level| {
+            let scale = 1 << (max_level - level);
+            [width.div_ceil(scale), height.div_ceil(scale)]
+        })
+        .collect()
+}
+
+///Every tile of a deep zoom pyramid, from the smallest level to the full image
+pub fn dzi_tiles(width: u32, height: u32) -> Vec<PyramidTile> {
+    let levels = dzi_levels(width, height);
+    let max_level = levels.len() as u32 - 1;
+    let tile_size = grimoire::TILE_SIZE;
+    let mut tiles = Vec::new();
+    for (level, [level_width, level_height]) in (0..).zip(levels) {
+        let scale = f64::from(1 << (max_level - level));
+        for row in 0..level_height.div_ceil(tile_size) {
+            for column in 0..level_width.div_ceil(tile_size) {
+                let size = [
+                    tile_size.min(level_width - column * tile_size),
+                    tile_size.min(level_height - row * tile_size),
+                ];
+                tiles.push(PyramidTile {
+                    level,
+                    column,
+                    row,
+                    region: [
+                        f64::from(column * tile_size) * scale,
+                        f64::from(row * tile_size) * scale,
+                        f64::from(size[0]) * scale,
+                        f64::from(size[1]) * scale,
+                    ],
+                    size,
+                });
+            }
+        }
+    }
+    tiles
+}
+
+///The xml file that describes a deep zoom pyramid, the tiles go in `{name}_files/{level}/`
+pub fn dzi_descriptor(width: u32, height: u32, format: OutputFormat) -> String {
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<Image xmlns="http://schemas.microsoft.com/deepzoom/2008" Format="{}" Overlap="0" TileSize="{}">
+  <Size Width="{width}" Height="{height}"/>
+</Image>
+"#,
+        format.extension(),
+        grimoire::TILE_SIZE
+    )
+}
+
+///Iiif image api 3 `info.json` of an image, `id` is the url without `/info.json`
+pub fn iiif_info(id: &str, width: u32, height: u32) -> serde_json::Value {
+    let levels = width
+        .max(height)
+        .div_ceil(grimoire::TILE_SIZE)
+        .next_power_of_two()
+        .trailing_zeros();
+    let formats = OutputFormat::ALL
+        .into_iter()
+        .filter(|format| !format.is_raw() && *format != OutputFormat::Jpeg)
+        .map(OutputFormat::extension)
+        .collect::<Vec<_>>();
+    serde_json::json!({
+        "@context": "http://iiif.io/api/image/3/context.json",
+        "id": id,
+        "type": "ImageService3",
+        "protocol": "http://iiif.io/api/image",
+        "profile": "level1",
+        "width": width,
+        "height": height,
+        "maxArea": grimoire::MAX_IIIF_AREA,
+        "tiles": [{
+            "width": grimoire::TILE_SIZE,
+            "scaleFactors": (0..=levels).map(|level| 1 << level).collect::<Vec<u32>>(),
+        }],
+        "preferredFormats": ["png"],
+        "extraFormats": formats,
+        "extraQualities": ["color"],
+        "extraFeatures": ["regionByPct", "regionSquare", "sizeByPct", "sizeByConfinedWh", "sizeByWh", "sizeUpscaling"],
+    })
+}
+
+fn parse_numbers<const N: usize>(value: &str) -> Option<[f64; N]> {
+    let numbers = value
+        .split(',')
+        .map(|n| n.parse::<f64>().ok().filter(|n| n.is_finite() && *n >= 0.0))
+        .collect::<Option<Vec<_>>>()?;
+    numbers.try_into().ok()
+}
+
+///Parses the region of an iiif url, returns x, y, width, height in pixels of the full image
+pub fn parse_region(region: &str, [width, height]: [u32; 2]) -> Result<[u32; 4], String> {
+    let invalid = || format!("Invalid region {region}");
+    let (full_width, full_height) = (f64::from(width), f64::from(height));
+    let [x, y, w, h] = match region {
+        "full" => [0.0, 0.0, full_width, full_height],
+        "square" => {
+            let side = full_width.min(full_height);
+            [
+                ((full_width - side) / 2.0).floor(),
+                ((full_height - side) / 2.0).floor(),
+                side,
+                side,
+            ]
+        }
+        _ => match region.strip_prefix("pct:") {
+            Some(percentages) => {
+                let [x, y, w, h] = parse_numbers(percentages).ok_or_else(invalid)?;
+                [
+                    x * full_width / 100.0,
+                    y * full_height / 100.0,
+                    w * full_width / 100.0,
+                    h * full_height / 100.0,
+                ]
+                .map(f64::round)
+            }
+            None => parse_numbers(region).ok_or_else(invalid)?,
+        },
+    };
+    //Cropped to the image
+    let (x, y) = (x.min(full_width), y.min(full_height));
+    let (w, h) = (w.min(full_width - x), h.min(full_height - y));
+    if w < 1.0 || h < 1.0 {
+        return Err(format!("Region {region} is outside of the image"));
+    }
+    Ok([x, y, w, h].map(|n| n as u32))
+}
+
+///Parses the size of an iiif url for a region of `[width, height]`
+pub fn parse_size(size: &str, [width, height]: [u32; 2]) -> Result<[u32; 2], String> {
+    let invalid = || format!("Invalid size {size}");
+    let (upscale, size_value) = match size.strip_prefix('^') {
+        Some(size) => (true, size),
+        None => (false, size),
+    };
+    let (region_width, region_height) = (f64::from(width), f64::from(height));
+    let [w, h] = match size_value {
+        "max" | "full" => {
+            //Scaled down to the largest area that's allowed
+            let area = region_width * region_height;
+            let scale = (grimoire::MAX_IIIF_AREA as f64 / area).sqrt().min(1.0);
+            [region_width * scale, region_height * scale].map(f64::floor)
+        }
+        _ => {
+            if let Some(percent) = size_value.strip_prefix("pct:") {
+                let [percent] = parse_numbers(percent).ok_or_else(invalid)?;
+                [region_width, region_height].map(|n| (n * percent / 100.0).round())
+            } else if let Some(confined) = size_value.strip_prefix('!') {
+                let [w, h] = parse_numbers(confined).ok_or_else(invalid)?;
+                let scale = (w / region_width).min(h / region_height);
+                [region_width * scale, region_height * scale].map(f64::round)
+            } else {
+                let (w, h) = size_value.split_once(',').ok_or_else(invalid)?;
+                let parse = |n: &str| n.parse::<f64>().ok().filter(|n| *n >= 1.0);
+                match (parse(w), parse(h)) {
+                    (Some(w), None) if h.is_empty() => {
+                        [w, (w * region_height / region_width).round()]
+                    }
+                    (None, Some(h)) if w.is_empty() => {
+                        [(h * region_width / region_height).round(), h]
+                    }
+                    (Some(w), Some(h)) => [w, h],
+                    _ => return Err(invalid()),
+                }
+            }
+        }
+    };
+    if w < 1.0 || h < 1.0 {
+        return Err(format!("Size {size} is too small"));
+    }
+    if !upscale && (w > region_width || h > region_height) {
+        return Err(format!(
+            "Size {size} is bigger than the region, use ^ to upscale"
+        ));
+    }
+    if w * h > grimoire::MAX_IIIF_AREA as f64 {
+        return Err(format!(
+            "Images can have at most {} pixels",
+            grimoire::MAX_IIIF_AREA
+        ));
+    }
+    Ok([w as u32, h as u32])
+}
+
+#[test]
+fn test_dzi_tiles() {
+    assert_eq!(dzi_levels(1, 1), vec![[1, 1]]);
+    let levels = dzi_levels(600, 300);
+    assert_eq!(levels.len(), 11);
+    assert_eq!(levels[10], [600, 300]);
+    assert_eq!(levels[9], [300, 150]);
+    assert_eq!(levels[0], [1, 1]);
+
+    let tiles = dzi_tiles(600, 300);
+    //3x2 tiles at full size, 2x1 at half and one for every level below
+    assert_eq!(tiles.len(), 6 + 2 + 9);
+    let last = tiles.last().unwrap();
+    assert_eq!((last.level, last.column, last.row), (10, 2, 1));
+    assert_eq!(last.region, [512.0, 256.0, 88.0, 44.0]);
+    assert_eq!(last.size, [88, 44]);
+}
+
+#[test]
+fn test_iiif_parsing() {
+    let full = [1000, 500];
+    assert_eq!(parse_region("full", full), Ok([0, 0, 1000, 500]));
+    assert_eq!(parse_region("square", full), Ok([250, 0, 500, 500]));
+    assert_eq!(
+        parse_region("pct:50,50,50,50", full),
+        Ok([500, 250, 500, 250])
+    );
+    //Cropped to the image
+    assert_eq!(parse_region("900,0,200,100", full), Ok([900, 0, 100, 100]));
+    assert!(parse_region("1000,0,200,100", full).is_err());
+    assert!(parse_region("0,0,-1,100", full).is_err());
+
+    let region = [512, 256];
+    assert_eq!(parse_size("max", region), Ok([512, 256]));
+    assert_eq!(parse_size("256,", region), Ok([256, 128]));
+    assert_eq!(parse_size(",64", region), Ok([128, 64]));
+    assert_eq!(parse_size("pct:25", region), Ok([128, 64]));
+    assert_eq!(parse_size("!100,100", region), Ok([100, 50]));
+    assert_eq!(parse_size("^1024,", region), Ok([1024, 512]));
+    assert!(parse_size("1024,", region).is_err());
+    assert!(parse_size(",", region).is_err());
+}
@@ -0,0 +1,101 @@
+use actix_codec::{Decoder, Encoder};
+use actix_http::ws::{hash_key, verify_handshake, Codec, Frame, Message};
+use actix_web::{
+    http::header::{self, HeaderValue},
+    web::{self, BytesMut},
+    HttpRequest, HttpResponse,
+};
+use futures::{stream, Stream, StreamExt};
+
+///Answers the websocket handshake, `messages` are sent to the client until the stream ends and
+///the connection is closed. Errors with the response to send back if it's not a valid handshake
+pub fn upgrade(
+    req: &HttpRequest,
+    messages: impl Stream<Item = Message> + 'static,
+) -> Result<HttpResponse, HttpResponse> {
+    verify_handshake(req.head()).map_err(HttpResponse::from_error)?;
+    let key = req
+        .headers()
+        .get(header::SEC_WEBSOCKET_KEY)
+        .expect("The handshake has a key");
+    let accept =
+        HeaderValue::from_bytes(&hash_key(key.as_bytes())).expect("The key hash is base64");
+
+    let mut codec = Codec::new();
+    let body = messages.map(move |message| {
+        let mut buffer = BytesMut::new();
+        codec
+            .encode(message, &mut buffer)
+            .map(|()| buffer.freeze())
+            .map_err(actix_web::error::ErrorInternalServerError)
+    });
+    Ok(HttpResponse::SwitchingProtocols()
+        .upgrade("websocket")
+        .insert_header((header::SEC_WEBSOCKET_ACCEPT, accept))
+        .streaming(body))
+}
+
+///Decodes the frames the client sends, ends when the client stops sending or sends something
+///that isn't a valid frame
+pub fn frames(payload: web::Payload) -> impl Stream<Item = Result<Frame, String>> {
+    stream::unfold(
+        Some((payload, BytesMut::new(), Codec::new())),
+        |state| async move {
+            let (mut payload, mut buffer, mut codec) = state?;
+            loop {
+                match codec.decode(&mut buffer) {
+                    Ok(Some(frame)) => return Some((Ok(frame), Some((payload, buffer, codec)))),
+                    Ok(None) => {}
+                    Err(e) => return Some((Err(e.to_string()), None)),
+                }
+                match payload.next().await? {
+                    Ok(bytes) => buffer.extend_from_slice(&bytes),
+                    Err(e) => return Some((Err(e.to_string()), None)),
+                }
+            }
+        },
+    )
+}
+
+///The frames a client would send, they're masked unlike the ones sent by the server
+#[cfg(test)]
+pub fn client_frames(messages: Vec<Message>) -> web::Bytes {
+    let mut codec = Codec::new().client_mode();
+    let mut buffer = BytesMut::new();
+    for message in messages {
+        codec.encode(message, &mut buffer).unwrap();
+    }
+    buffer.freeze()
+}
+
+///Decodes what the server sent, the opposite of [`client_frames`]
+#[cfg(test)]
+pub fn server_frames(data: &[u8]) -> Vec<Frame> {
+    let mut codec = Codec::new().client_mode().max_size(usize::MAX);
+    let mut buffer = BytesMut::from(data);
+    std::iter::from_fn(|| codec.decode(&mut buffer).unwrap()).collect()
+}
+
+#[test]
+fn test_frames() {
+    let data = client_frames(vec![
+        Message::Text("hello".into()),
+        Message::Ping(web::Bytes::from_static(b"ping")),
+    ]);
+    let (req, mut payload) = actix_web::test::TestRequest::default()
+        .set_payload(data)
+        .to_http_parts();
+    let frames = futures::executor::block_on(async {
+        let payload = <web::Payload as actix_web::FromRequest>::from_request(&req, &mut payload)
+            .await
+            .unwrap();
+        frames(payload).collect::<Vec<_>>().await
+    });
+    assert_eq!(
+        frames,
+        [
+            Ok(Frame::Text(web::Bytes::from_static(b"hello"))),
+            Ok(Frame::Ping(web::Bytes::from_static(b"ping")))
+        ]
+    );
+}